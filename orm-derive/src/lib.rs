@@ -3,87 +3,1827 @@
 use proc_macro::TokenStream;
 
 use quote::quote;
-use syn::{parse_macro_input, parse_quote, Attribute, Data, DeriveInput, Generics};
+use syn::{
+    parse_macro_input, parse_quote, punctuated::Punctuated, Attribute, Data, DeriveInput, Generics,
+    Token, WherePredicate,
+};
 
-#[proc_macro_derive(Object, attributes(table_name, column_name))]
+#[proc_macro_derive(Object, attributes(table_name, column_name, orm, index))]
 pub fn derive_object(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
+    expand_object(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
 
+fn expand_object(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let container_attrs = parse_container_attrs(&input.attrs)?;
+    if let Some(object) = container_attrs.view_of.clone() {
+        return expand_view(input, object);
+    }
+    let index_attrs = parse_index_attrs(&input.attrs)?;
+    if container_attrs.query && !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.generics,
+            "#[orm(query)] is not supported for generic objects",
+        ));
+    }
+    if container_attrs.patch && !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.generics,
+            "#[orm(patch)] is not supported for generic objects",
+        ));
+    }
+    let vis = input.vis;
     let type_name = input.ident;
-    let table_name = input
-        .attrs
-        .iter()
-        .find_map(get_table_name)
-        .unwrap_or(type_name.to_string());
+    let explicit_table_name = input.attrs.iter().find_map(get_table_name);
+    let table_name = explicit_table_name
+        .clone()
+        .unwrap_or_else(|| match container_attrs.naming {
+            Naming::Default => type_name.to_string(),
+            Naming::Diesel => format!("{}s", snake_case(&type_name.to_string())),
+        });
 
-    let generics = add_train_bounds(input.generics);
+    let type_params = input
+        .generics
+        .type_params()
+        .map(|param| param.ident.clone())
+        .collect::<Vec<_>>();
+    let generics = add_train_bounds(input.generics, container_attrs.bound.as_ref());
     let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
 
-    let fields = if let Data::Struct(data) = input.data {
-        data.fields
-    } else {
-        panic!("Object derive only works on structs");
+    let fields = match input.data {
+        Data::Struct(data) => data.fields,
+        Data::Enum(_) if !generics.params.is_empty() => {
+            return Err(syn::Error::new_spanned(
+                &generics,
+                "generic enums are not supported",
+            ))
+        }
+        Data::Enum(data) => {
+            return expand_enum(type_name, table_name, container_attrs, index_attrs, data)
+        }
+        Data::Union(_) => panic!("Object derive only works on structs and enums"),
     };
+    if let Some(discriminator) = &container_attrs.discriminator {
+        return Err(syn::Error::new_spanned(
+            discriminator,
+            "#[orm(discriminator)] is only supported on enums",
+        ));
+    }
 
     let mut column_names = Vec::with_capacity(fields.len());
     let mut types = Vec::with_capacity(fields.len());
     let mut attrs = Vec::with_capacity(fields.len());
+    let mut flags = Vec::with_capacity(fields.len());
+    let mut id_attr = None;
     for field in fields {
         let field_name = field.ident.clone().expect("Unnamed field not supported");
-        let column_name = field
+        let orm_attrs = parse_field_attrs(&field.attrs)?;
+        if orm_attrs.is_id {
+            if id_attr.is_some() {
+                return Err(syn::Error::new_spanned(
+                    &field_name,
+                    "only one field can be marked with #[orm(id)]",
+                ));
+            }
+            id_attr = Some(field_name);
+            continue;
+        }
+        if orm_attrs.interned && !is_string(&field.ty) {
+            return Err(syn::Error::new_spanned(
+                &field.ty,
+                "#[orm(interned)] is only supported on String fields",
+            ));
+        }
+        if orm_attrs.interned {
+            let unsupported = [
+                (orm_attrs.default.is_some(), "default"),
+                (orm_attrs.encrypt, "encrypt"),
+                (orm_attrs.codec.is_some(), "codec"),
+            ];
+            if let Some((_, name)) = unsupported.iter().find(|(set, _)| *set) {
+                return Err(syn::Error::new_spanned(
+                    &field_name,
+                    format!("#[orm({})] can't be combined with #[orm(interned)]", name),
+                ));
+            }
+        }
+        if orm_attrs.codec.is_some() {
+            let unsupported = [
+                (orm_attrs.checksum, "checksum"),
+                (orm_attrs.external_blob, "external_blob"),
+                (orm_attrs.encrypt, "encrypt"),
+            ];
+            if let Some((_, name)) = unsupported.iter().find(|(set, _)| *set) {
+                return Err(syn::Error::new_spanned(
+                    &field_name,
+                    format!("#[orm({})] can't be combined with #[orm(codec)]", name),
+                ));
+            }
+        }
+        if orm_attrs.checksum && !is_bytes(&field.ty) {
+            return Err(syn::Error::new_spanned(
+                &field.ty,
+                "#[orm(checksum)] is only supported on Vec<u8> fields",
+            ));
+        }
+        if orm_attrs.external_blob && !is_bytes(&field.ty) {
+            return Err(syn::Error::new_spanned(
+                &field.ty,
+                "#[orm(external_blob)] is only supported on Vec<u8> fields",
+            ));
+        }
+        if orm_attrs.encrypt && !is_string(&field.ty) && !is_bytes(&field.ty) {
+            return Err(syn::Error::new_spanned(
+                &field.ty,
+                "#[orm(encrypt)] is only supported on String and Vec<u8> fields",
+            ));
+        }
+        if orm_attrs.bool_encoding.is_some() {
+            if !matches!(&field.ty, syn::Type::Path(path) if path.path.is_ident("bool")) {
+                return Err(syn::Error::new_spanned(
+                    &field.ty,
+                    "#[orm(bool_encoding)] is only supported on bool fields",
+                ));
+            }
+            let unsupported = [
+                (orm_attrs.default.is_some(), "default"),
+                (orm_attrs.codec.is_some(), "codec"),
+            ];
+            if let Some((_, name)) = unsupported.iter().find(|(set, _)| *set) {
+                return Err(syn::Error::new_spanned(
+                    &field_name,
+                    format!(
+                        "#[orm({})] can't be combined with #[orm(bool_encoding)]",
+                        name
+                    ),
+                ));
+            }
+        }
+        if orm_attrs.currency.is_some() && is_money(&field.ty).is_none() {
+            return Err(syn::Error::new_spanned(
+                &field.ty,
+                "#[orm(currency)] is only supported on Money fields",
+            ));
+        }
+        if is_money(&field.ty).is_some() {
+            let two_columns = orm_attrs.currency.is_none();
+            let unsupported = [
+                (orm_attrs.unique && two_columns, "unique"),
+                (orm_attrs.primary_key, "primary_key"),
+                (orm_attrs.default.is_some(), "default"),
+                (
+                    orm_attrs.rename_from.is_some() && two_columns,
+                    "rename_from",
+                ),
+                (orm_attrs.encrypt, "encrypt"),
+                (orm_attrs.codec.is_some(), "codec"),
+                (orm_attrs.interned, "interned"),
+            ];
+            if let Some((_, name)) = unsupported.iter().find(|(set, _)| *set) {
+                return Err(syn::Error::new_spanned(
+                    &field_name,
+                    format!("#[orm({})] is not supported on this Money field", name),
+                ));
+            }
+        }
+        if is_poly_ref(&field.ty).is_some() {
+            let unsupported = [
+                (orm_attrs.unique, "unique"),
+                (orm_attrs.primary_key, "primary_key"),
+                (orm_attrs.default.is_some(), "default"),
+                (orm_attrs.rename_from.is_some(), "rename_from"),
+                (orm_attrs.checksum, "checksum"),
+                (orm_attrs.encrypt, "encrypt"),
+                (orm_attrs.codec.is_some(), "codec"),
+                (!orm_attrs.validators.is_empty(), "validate"),
+            ];
+            if let Some((_, name)) = unsupported.iter().find(|(set, _)| *set) {
+                return Err(syn::Error::new_spanned(
+                    &field_name,
+                    format!("#[orm({})] is not supported on PolyRef fields", name),
+                ));
+            }
+        }
+        let (column_name, span) = field
             .attrs
             .iter()
             .find_map(get_column_name)
-            .unwrap_or_else(|| field_name.to_string());
+            .map(|lit| (lit.value(), lit.span()))
+            .unwrap_or_else(|| (field_name.to_string(), field_name.span()));
+        if column_name.eq_ignore_ascii_case("id") {
+            return Err(syn::Error::new(
+                span,
+                format!(
+                    "column `{}` collides with the implicit `id` primary key",
+                    column_name
+                ),
+            ));
+        }
+        if column_names
+            .iter()
+            .any(|name: &String| name.eq_ignore_ascii_case(&column_name))
+        {
+            return Err(syn::Error::new(
+                span,
+                format!("duplicate column name `{}`", column_name),
+            ));
+        }
         column_names.push(column_name);
         types.push(field.ty);
         attrs.push(field_name);
+        flags.push(orm_attrs);
+    }
+    // Index of the field storing each column, hidden ones included.
+    let field_of = |column: &str| {
+        (0..attrs.len()).find(|&i| {
+            stored_columns(&column_names[i], &types[i], &flags[i])
+                .iter()
+                .any(|name| name == column)
+        })
+    };
+    for column in index_attrs.iter().flat_map(|index| &index.columns) {
+        if field_of(&column.value()).is_none() {
+            return Err(syn::Error::new_spanned(
+                column,
+                format!("unknown column `{}`", column.value()),
+            ));
+        }
+    }
+
+    // Fields moved out to a secondary table keyed by the object id.
+    let mut split_tables: Vec<Option<&syn::LitStr>> = vec![None; attrs.len()];
+    for split in &container_attrs.splits {
+        if split.table.value().eq_ignore_ascii_case(&table_name) {
+            return Err(syn::Error::new_spanned(
+                &split.table,
+                "split table must differ from the object's table",
+            ));
+        }
+        for field in &split.fields {
+            if id_attr.as_ref() == Some(field) {
+                return Err(syn::Error::new_spanned(
+                    field,
+                    "the #[orm(id)] field can't be split",
+                ));
+            }
+            let Some(i) = attrs.iter().position(|attr| attr == field) else {
+                return Err(syn::Error::new_spanned(
+                    field,
+                    format!("unknown field `{}`", field),
+                ));
+            };
+            if split_tables[i].is_some() {
+                return Err(syn::Error::new_spanned(
+                    field,
+                    format!("field `{}` is split more than once", field),
+                ));
+            }
+            if flags[i].primary_key {
+                return Err(syn::Error::new_spanned(
+                    field,
+                    "#[orm(primary_key)] fields can't be split",
+                ));
+            }
+            if container_attrs
+                .expires
+                .as_ref()
+                .is_some_and(|expires| *field == expires.value())
+            {
+                return Err(syn::Error::new_spanned(
+                    field,
+                    "the #[orm(expires)] field can't be split",
+                ));
+            }
+            split_tables[i] = Some(&split.table);
+        }
+    }
+    for column in index_attrs.iter().flat_map(|index| &index.columns) {
+        if field_of(&column.value()).is_some_and(|i| split_tables[i].is_some()) {
+            return Err(syn::Error::new_spanned(
+                column,
+                format!("can't index split column `{}`", column.value()),
+            ));
+        }
+    }
+    if let Some(i) = column_names
+        .iter()
+        .position(|name| container_attrs.revisions && name.eq_ignore_ascii_case("_rev"))
+    {
+        return Err(syn::Error::new_spanned(
+            &attrs[i],
+            "column `_rev` is reserved by #[orm(revisions)]",
+        ));
+    }
+    if container_attrs.without_rowid && !flags.iter().any(|flags| flags.primary_key) {
+        return Err(syn::Error::new_spanned(
+            &type_name,
+            "#[orm(without_rowid)] requires a field marked with #[orm(primary_key)]",
+        ));
+    }
+    for (i, attr) in attrs.iter().enumerate() {
+        for hidden_name in stored_columns(&column_names[i], &types[i], &flags[i]) {
+            if hidden_name != column_names[i]
+                && column_names
+                    .iter()
+                    .any(|name| name.eq_ignore_ascii_case(&hidden_name))
+            {
+                return Err(syn::Error::new_spanned(
+                    attr,
+                    format!("duplicate column name `{}`", hidden_name),
+                ));
+            }
+        }
     }
 
-    let row_constructors = attrs.iter().enumerate().map(|(i, field_name)| {
+    // Checksummed columns are followed by a hidden column holding their hash
+    // and PolyRef fields take two columns, so field positions and row
+    // positions no longer line up one to one.
+    let mut index = 0;
+    let row_constructors = attrs
+        .iter()
+        .zip(&types)
+        .zip(&flags)
+        .zip(&column_names)
+        .map(|(((field_name, ty), flags), name)| {
+            let i = index;
+            index += stored_columns(name, ty, flags).len();
+            let value = if is_poly_ref(ty).is_some() {
+                quote!(orm::object::PolyRefField::try_from_values(&row[#i], &row[#i + 1]))
+            } else if let Some(currency) = is_money(ty).and(flags.currency.as_ref()) {
+                quote! {
+                    orm::money::MoneyField::try_from_values(
+                        &row[#i],
+                        &orm::data::Value::String(::std::borrow::Cow::Borrowed(#currency)),
+                    )
+                }
+            } else if is_money(ty).is_some() {
+                quote!(orm::money::MoneyField::try_from_values(&row[#i], &row[#i + 1]))
+            } else if let Some(codec) = &flags.codec {
+                quote!(<#codec as orm::codec::ColumnCodec<#ty>>::try_decode(&row[#i]))
+            } else {
+                quote!(row[#i].try_convert())
+            };
+            quote! {
+                #field_name: #value
+                    .map_err(|e| orm::object::conversion_error(Self::schema(), #i, e))?
+            }
+        })
+        .collect::<Vec<_>>();
+    let row_values = attrs
+        .iter()
+        .zip(&types)
+        .zip(&flags)
+        .map(|((attr, ty), flags)| {
+            if is_poly_ref(ty).is_some() {
+                quote! {
+                    orm::object::PolyRefField::table_value(&self.#attr),
+                    orm::object::PolyRefField::id_value(&self.#attr)
+                }
+            } else if is_money(ty).is_some() && flags.currency.is_some() {
+                quote!(orm::money::MoneyField::minor_units_value(&self.#attr))
+            } else if is_money(ty).is_some() {
+                quote! {
+                    orm::money::MoneyField::minor_units_value(&self.#attr),
+                    orm::money::MoneyField::currency_value(&self.#attr)
+                }
+            } else if let Some(codec) = &flags.codec {
+                quote!(<#codec as orm::codec::ColumnCodec<#ty>>::encode(&self.#attr))
+            } else if flags.checksum {
+                quote! {
+                    self.#attr.to_value_ref(),
+                    orm::checksum::checksum_value(&self.#attr.to_value_ref())
+                }
+            } else {
+                quote!(self.#attr.to_value_ref())
+            }
+        });
+    let id_constructor = id_attr.iter().map(|field_name| {
         quote! {
-            #field_name: row[#i].convert()
+            #field_name: ::core::default::Default::default()
+        }
+    });
+    let set_id = id_attr.iter().map(|field_name| {
+        quote! {
+            fn set_id(&mut self, id: orm::ObjectId) {
+                self.#field_name = ::core::convert::Into::into(id);
+            }
+        }
+    });
+
+    let checks = attrs
+        .iter()
+        .zip(&flags)
+        .flat_map(|(attr, flags)| {
+            let attr_name = attr.to_string();
+            let currency = flags.currency.as_ref().map(|currency| {
+                quote! {
+                    if let ::core::result::Result::Err(message) =
+                        orm::money::check_currency(&self.#attr, #currency)
+                    {
+                        failures.push(orm::validate::ValidationFailure {
+                            attr_name: #attr_name,
+                            message,
+                        });
+                    }
+                }
+            });
+            currency
+                .into_iter()
+                .chain(flags.validators.iter().map(move |validator| {
+                    let check = match validator {
+                        Validator::Length(min, max) => {
+                            let (min, max) = (option_expr(min), option_expr(max));
+                            quote!(orm::validate::check_length(&self.#attr, #min, #max))
+                        }
+                        Validator::Range(min, max) => {
+                            let (min, max) = (option_expr(min), option_expr(max));
+                            quote!(orm::validate::check_range(&self.#attr, #min, #max))
+                        }
+                        Validator::Custom(path) => quote!(#path(&self.#attr)),
+                    };
+                    let check = match validator {
+                        Validator::Length(..) | Validator::Range(..) if flags.redact => {
+                            quote!(orm::validate::redact_value(#check))
+                        }
+                        _ => check,
+                    };
+                    quote! {
+                        if let ::core::result::Result::Err(message) = #check {
+                            failures.push(orm::validate::ValidationFailure {
+                                attr_name: #attr_name,
+                                message: ::std::string::ToString::to_string(&message),
+                            });
+                        }
+                    }
+                }))
+        })
+        .collect::<Vec<_>>();
+    let validate = (!checks.is_empty()).then(|| {
+        quote! {
+            fn validate(&self) -> ::core::result::Result<(), orm::validate::ValidationError> {
+                let mut failures = ::std::vec::Vec::new();
+                #(#checks)*
+                orm::validate::ValidationError::check(stringify!(#type_name), failures)
+            }
+        }
+    });
+
+    let expiring = match &container_attrs.expires {
+        Some(field) => {
+            let Some(column_name) = attrs
+                .iter()
+                .position(|attr| *attr == field.value())
+                .map(|i| &column_names[i])
+            else {
+                return Err(syn::Error::new_spanned(
+                    field,
+                    format!("unknown field `{}`", field.value()),
+                ));
+            };
+            Some(quote! {
+                impl #impl_generics orm::object::Expiring for #type_name #ty_generics #where_clause {
+                    const EXPIRES_COLUMN: &'static str = #column_name;
+                }
+            })
+        }
+        None => None,
+    };
+
+    let tree = match &container_attrs.tree_parent {
+        Some(field) => {
+            let Some(i) = attrs.iter().position(|attr| *attr == field.value()) else {
+                return Err(syn::Error::new_spanned(
+                    field,
+                    format!("unknown field `{}`", field.value()),
+                ));
+            };
+            if is_poly_ref(&types[i]).is_some() {
+                return Err(syn::Error::new_spanned(
+                    field,
+                    "the tree parent must be an id field, not a PolyRef",
+                ));
+            }
+            let column_name = &column_names[i];
+            Some(quote! {
+                impl #impl_generics orm::object::Tree for #type_name #ty_generics #where_clause {
+                    const PARENT_COLUMN: &'static str = #column_name;
+                }
+            })
+        }
+        None => None,
+    };
+
+    let debug = container_attrs.debug.then(|| {
+        let id_field = id_attr.iter().map(|field_name| {
+            let name = field_name.to_string();
+            quote!(.field(#name, &self.#field_name))
+        });
+        let fields = attrs.iter().zip(&flags).map(|(attr, flags)| {
+            let name = attr.to_string();
+            if flags.redact {
+                quote!(.field(#name, &::core::format_args!("<redacted>")))
+            } else {
+                quote!(.field(#name, &self.#attr))
+            }
+        });
+        quote! {
+            impl #impl_generics ::core::fmt::Debug for #type_name #ty_generics #where_clause {
+                fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                    f.debug_struct(stringify!(#type_name))
+                        #(#id_field)*
+                        #(#fields)*
+                        .finish()
+                }
+            }
         }
     });
 
-    let attr_names = attrs.iter().map(|attr| attr.to_string());
+    let query = container_attrs
+        .query
+        .then(|| expand_query(&vis, &type_name, &attrs, &column_names, &types, &flags));
 
-    let expanded = quote! {
+    let patch = container_attrs
+        .patch
+        .then(|| expand_patch(&vis, &type_name, &attrs, &types));
+
+    // `comment.target(&tx)` loads whatever a PolyRef field points to.
+    let targets = attrs
+        .iter()
+        .zip(&types)
+        .filter_map(|(attr, ty)| Some((attr, is_poly_ref(ty)?)))
+        .map(|(attr, nullable)| {
+            if nullable {
+                quote! {
+                    #vis fn #attr<'t>(
+                        &self,
+                        tx: &'t orm::Transaction<'_>,
+                    ) -> orm::Result<::core::option::Option<orm::AnyTx<'t>>> {
+                        self.#attr.as_ref().map(|target| tx.get_poly(target)).transpose()
+                    }
+                }
+            } else {
+                quote! {
+                    #vis fn #attr<'t>(&self, tx: &'t orm::Transaction<'_>) -> orm::Result<orm::AnyTx<'t>> {
+                        tx.get_poly(&self.#attr)
+                    }
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+    let targets = (!targets.is_empty()).then(|| {
+        quote! {
+            impl #impl_generics #type_name #ty_generics #where_clause {
+                #(#targets)*
+            }
+        }
+    });
+
+    let attr_names = attrs
+        .iter()
+        .zip(&column_names)
+        .zip(&types)
+        .zip(&flags)
+        .flat_map(|(((attr, name), ty), flags)| {
+            let count = stored_columns(name, ty, flags).len();
+            std::iter::repeat_n(attr.to_string(), count)
+        });
+    let columns = column_names
+        .iter()
+        .zip(&types)
+        .zip(&flags)
+        .zip(&split_tables)
+        .flat_map(|(((name, ty), flags), split_table)| {
+            let split_table = match split_table {
+                Some(table) => quote!(Some(#table)),
+                None => quote!(None),
+            };
+            if is_poly_ref(ty).is_some() {
+                let nullable = quote!(<#ty as orm::object::PolyRefField>::NULLABLE);
+                let stored = stored_columns(name, ty, flags);
+                let table = column_def(
+                    &stored[0],
+                    &parse_quote!(::std::string::String),
+                    flags,
+                    nullable.clone(),
+                    &split_table,
+                );
+                let id = column_def(
+                    &stored[1],
+                    &parse_quote!(i64),
+                    flags,
+                    nullable,
+                    &split_table,
+                );
+                return vec![table, id];
+            }
+            if is_money(ty).is_some() {
+                let nullable = quote!(<#ty as orm::money::MoneyField>::NULLABLE);
+                let stored = stored_columns(name, ty, flags);
+                let mut columns = vec![column_def(
+                    &stored[0],
+                    &parse_quote!(i64),
+                    flags,
+                    nullable.clone(),
+                    &split_table,
+                )];
+                if let Some(currency) = stored.get(1) {
+                    columns.push(column_def(
+                        currency,
+                        &parse_quote!(::std::string::String),
+                        flags,
+                        nullable,
+                        &split_table,
+                    ));
+                }
+                return columns;
+            }
+            // Legacy bool columns may hold NULL, which reads as false.
+            let nullable = match &flags.codec {
+                Some(codec) => quote!(<#codec as orm::codec::ColumnCodec<#ty>>::NULLABLE),
+                None if flags.bool_encoding.is_some() => quote!(true),
+                None => quote!(<#ty as orm::data::DetectDataType>::NULLABLE),
+            };
+            let column = column_def(name, ty, flags, nullable, &split_table);
+            let checksum = flags.checksum.then(|| {
+                let checksum_name = format!("{}_checksum", name);
+                quote! {
+                    orm::object::ColumnDef {
+                        name: #checksum_name,
+                        data_type: orm::data::DataType::Int64,
+                        nullable: true,
+                        unique: false,
+                        primary_key: false,
+                        default: None,
+                        renamed_from: None,
+                        redacted: false,
+                        encrypted: false,
+                        external_blob: false,
+                        checksum_of: Some(#name),
+                        split_table: #split_table,
+                        interned: false,
+                        currency: None,
+                        format: None,
+                        bool_encoding: None,
+                    }
+                }
+            });
+            std::iter::once(column).chain(checksum).collect::<Vec<_>>()
+        });
+
+    let strict = container_attrs.strict;
+    let indexes = index_defs(&index_attrs);
+    let without_rowid = container_attrs.without_rowid;
+    let revisions = container_attrs.revisions;
+    let schema = if type_params.is_empty() {
+        quote! {
+            &orm::object::Schema {
+                table_name: #table_name,
+                type_name: stringify!(#type_name),
+                attrs: &[#(#attr_names),*],
+                columns: &[#(#columns),*],
+                strict: #strict,
+                without_rowid: #without_rowid,
+                revisions: #revisions,
+                indexes: &[#(#indexes),*],
+            }
+        }
+    } else {
+        let table_name = match explicit_table_name {
+            Some(table_name) => quote!(::std::string::String::from(#table_name)),
+            None => quote!(::std::format!("{}_{}", #table_name, params.join("_"))),
+        };
+        quote! {
+            orm::object::schema_for::<Self>(|| {
+                let params = [#(orm::object::type_param_name::<#type_params>()),*];
+                orm::object::Schema {
+                    table_name: orm::object::leak_name(#table_name),
+                    type_name: orm::object::leak_name(::std::format!(
+                        "{}<{}>",
+                        stringify!(#type_name),
+                        params.join(", "),
+                    )),
+                    attrs: &[#(#attr_names),*],
+                    columns: ::std::vec![#(#columns),*].leak(),
+                    strict: #strict,
+                    without_rowid: #without_rowid,
+                    revisions: #revisions,
+                    indexes: &[#(#indexes),*],
+                }
+            })
+        }
+    };
+
+    Ok(quote! {
         impl #impl_generics Object for #type_name #ty_generics #where_clause {
+            fn schema() -> &'static orm::object::Schema {
+                #schema
+            }
+
+            fn try_from_row(row: orm::storage::Row<'_>) -> orm::Result<Self> {
+                Ok(Self {
+                    #(#row_constructors,)*
+                    #(#id_constructor,)*
+                })
+            }
+
+            fn to_row(&self) -> orm::storage::Row<'_> {
+                use orm::data::ValueConvert;
+                vec![
+                    #(#row_values),*
+                ]
+            }
+
+            #(#set_id)*
+
+            #validate
+        }
+
+        #expiring
+
+        #tree
+
+        #debug
+
+        #query
+
+        #patch
+
+        #targets
+    })
+}
+
+// Enums of struct variants share one table: a discriminator column holding the
+// variant name, then the union of all variant fields, NULL where absent.
+// Views are read-only projections of another object. They have no schema of
+// their own, so fields are looked up by column name in the object's schema.
+fn expand_view(input: DeriveInput, object: syn::Path) -> syn::Result<proc_macro2::TokenStream> {
+    let container_attrs = parse_container_attrs(&input.attrs)?;
+    let unsupported = [
+        (container_attrs.query, "query"),
+        (container_attrs.patch, "patch"),
+        (container_attrs.strict, "strict"),
+        (container_attrs.without_rowid, "without_rowid"),
+        (container_attrs.revisions, "revisions"),
+        (container_attrs.expires.is_some(), "expires"),
+        (container_attrs.tree_parent.is_some(), "tree"),
+        (container_attrs.bound.is_some(), "bound"),
+        (!container_attrs.splits.is_empty(), "split"),
+        (container_attrs.discriminator.is_some(), "discriminator"),
+    ];
+    if let Some((_, name)) = unsupported.iter().find(|(set, _)| *set) {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            format!("#[orm({})] can't be combined with #[orm(view_of)]", name),
+        ));
+    }
+    if let Some(attr) = input
+        .attrs
+        .iter()
+        .find(|attr| attr.path().is_ident("table_name") || attr.path().is_ident("index"))
+    {
+        return Err(syn::Error::new_spanned(
+            attr,
+            "views use the table and indexes of their object",
+        ));
+    }
+    if input.generics.type_params().next().is_some()
+        || input.generics.const_params().next().is_some()
+        || input.generics.lifetimes().count() > 1
+    {
+        return Err(syn::Error::new_spanned(
+            &input.generics,
+            "#[orm(view_of)] supports at most one lifetime parameter",
+        ));
+    }
+    let type_name = input.ident;
+    let (self_type, view_type) = match input.generics.lifetimes().next() {
+        Some(_) => (quote!(#type_name<'static>), quote!(#type_name<'r>)),
+        None => (quote!(#type_name), quote!(#type_name)),
+    };
+    let fields = match input.data {
+        Data::Struct(data) => data.fields,
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &type_name,
+                "#[orm(view_of)] is only supported on structs",
+            ))
+        }
+    };
+
+    let mut column_names = Vec::with_capacity(fields.len());
+    let mut constructors = Vec::with_capacity(fields.len());
+    for field in fields {
+        let field_name = field.ident.clone().expect("Unnamed field not supported");
+        if let Some(attr) = field.attrs.iter().find(|attr| attr.path().is_ident("orm")) {
+            return Err(syn::Error::new_spanned(
+                attr,
+                "field attributes are not supported on views",
+            ));
+        }
+        let column_name = field
+            .attrs
+            .iter()
+            .find_map(get_column_name)
+            .map_or_else(|| field_name.to_string(), |lit| lit.value());
+        if column_names.contains(&column_name) {
+            return Err(syn::Error::new_spanned(
+                &field_name,
+                format!("duplicate column name `{}`", column_name),
+            ));
+        }
+        constructors.push(quote! {
+            #field_name: {
+                let (i, value) = orm::object::take_view_value(schema, &mut values, #column_name)?;
+                orm::data::ViewConvert::try_from_view_value(value)
+                    .map_err(|e| orm::object::conversion_error(schema, i, e))?
+            }
+        });
+        column_names.push(column_name);
+    }
+
+    Ok(quote! {
+        impl orm::object::ObjectView for #self_type {
+            type Object = #object;
+            type View<'r> = #view_type;
+
+            fn try_from_row(row: orm::storage::Row<'_>) -> orm::Result<Self::View<'_>> {
+                let schema = <#object as orm::Object>::schema();
+                let mut values = row.into_iter().map(::core::option::Option::Some).collect::<Vec<_>>();
+                Ok(#type_name {
+                    #(#constructors,)*
+                })
+            }
+        }
+    })
+}
+
+fn expand_enum(
+    type_name: syn::Ident,
+    table_name: String,
+    container_attrs: ContainerAttrs,
+    index_attrs: Vec<IndexAttrs>,
+    data: syn::DataEnum,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let unsupported = [
+        (container_attrs.query, "query"),
+        (container_attrs.patch, "patch"),
+        (container_attrs.debug, "debug"),
+        (container_attrs.without_rowid, "without_rowid"),
+        (container_attrs.expires.is_some(), "expires"),
+        (container_attrs.tree_parent.is_some(), "tree"),
+        (container_attrs.bound.is_some(), "bound"),
+        (!container_attrs.splits.is_empty(), "split"),
+    ];
+    if let Some((_, name)) = unsupported.iter().find(|(set, _)| *set) {
+        return Err(syn::Error::new_spanned(
+            &type_name,
+            format!("#[orm({})] is not supported on enums", name),
+        ));
+    }
+    let discriminator = container_attrs
+        .discriminator
+        .as_ref()
+        .map_or_else(|| "variant".to_string(), syn::LitStr::value);
+
+    let mut column_names = vec![discriminator.clone()];
+    let mut columns = vec![column_def(
+        &discriminator,
+        &parse_quote!(::std::string::String),
+        &FieldAttrs::default(),
+        quote!(false),
+        &quote!(None),
+    )];
+    let mut attr_names = vec![discriminator.clone()];
+    let mut column_types = vec![String::new()];
+    let mut variants = Vec::with_capacity(data.variants.len());
+    for variant in &data.variants {
+        let fields = match &variant.fields {
+            syn::Fields::Named(fields) => fields.named.iter().collect(),
+            syn::Fields::Unit => Vec::new(),
+            syn::Fields::Unnamed(_) => {
+                return Err(syn::Error::new_spanned(
+                    variant,
+                    "tuple variants are not supported, use named fields",
+                ))
+            }
+        };
+        // Row position of every field of the variant.
+        let mut positions = Vec::with_capacity(fields.len());
+        for field in fields {
+            let field_name = field.ident.clone().unwrap();
+            let ty = &field.ty;
+            if is_poly_ref(ty).is_some() {
+                return Err(syn::Error::new_spanned(
+                    ty,
+                    "PolyRef fields are not supported on enums",
+                ));
+            }
+            if is_money(ty).is_some() {
+                return Err(syn::Error::new_spanned(
+                    ty,
+                    "Money fields are not supported on enums",
+                ));
+            }
+            let flags = parse_field_attrs(&field.attrs)?;
+            let unsupported = [
+                (flags.is_id, "id"),
+                (flags.primary_key, "primary_key"),
+                (flags.checksum, "checksum"),
+                (flags.codec.is_some(), "codec"),
+                (flags.interned, "interned"),
+                (flags.bool_encoding.is_some(), "bool_encoding"),
+                (!flags.validators.is_empty(), "validate"),
+            ];
+            if let Some((_, name)) = unsupported.iter().find(|(set, _)| *set) {
+                return Err(syn::Error::new_spanned(
+                    &field_name,
+                    format!("#[orm({})] is not supported on enum fields", name),
+                ));
+            }
+            let column_name = field
+                .attrs
+                .iter()
+                .find_map(get_column_name)
+                .map_or_else(|| field_name.to_string(), |lit| lit.value());
+            if column_name.eq_ignore_ascii_case("id")
+                || column_name.eq_ignore_ascii_case(&discriminator)
+            {
+                return Err(syn::Error::new_spanned(
+                    &field_name,
+                    format!("column `{}` is reserved", column_name),
+                ));
+            }
+            // Fields of the same name in different variants share a column.
+            let i = match column_names
+                .iter()
+                .position(|name| name.eq_ignore_ascii_case(&column_name))
+            {
+                Some(i) => {
+                    if column_types[i] != quote!(#ty).to_string() {
+                        return Err(syn::Error::new_spanned(
+                            &field.ty,
+                            format!(
+                                "column `{}` is shared with another variant under a different type",
+                                column_name
+                            ),
+                        ));
+                    }
+                    i
+                }
+                None => {
+                    columns.push(column_def(
+                        &column_name,
+                        ty,
+                        &flags,
+                        quote!(true),
+                        &quote!(None),
+                    ));
+                    column_names.push(column_name);
+                    attr_names.push(field_name.to_string());
+                    column_types.push(quote!(#ty).to_string());
+                    column_names.len() - 1
+                }
+            };
+            positions.push((field_name, i));
+        }
+        variants.push((&variant.ident, positions));
+    }
+    for column in index_attrs.iter().flat_map(|index| &index.columns) {
+        if !column_names.contains(&column.value()) {
+            return Err(syn::Error::new_spanned(
+                column,
+                format!("unknown column `{}`", column.value()),
+            ));
+        }
+    }
+
+    let column_count = column_names.len();
+    let from_row = variants.iter().map(|(variant, positions)| {
+        let name = variant.to_string();
+        let fields = positions.iter().map(|(field_name, i)| {
+            quote! {
+                #field_name: row[#i]
+                    .try_convert()
+                    .map_err(|e| orm::object::conversion_error(Self::schema(), #i, e))?
+            }
+        });
+        quote!(#name => Self::#variant { #(#fields),* })
+    });
+    let to_row = variants.iter().map(|(variant, positions)| {
+        let name = variant.to_string();
+        let fields = positions.iter().map(|(field_name, _)| field_name);
+        let values = positions
+            .iter()
+            .map(|(field_name, i)| quote!(row[#i] = #field_name.to_value_ref();));
+        quote! {
+            Self::#variant { #(#fields),* } => {
+                row[0] = orm::data::Value::String(::std::borrow::Cow::Borrowed(#name));
+                #(#values)*
+            }
+        }
+    });
+
+    let strict = container_attrs.strict;
+    let revisions = container_attrs.revisions;
+    let indexes = index_defs(&index_attrs);
+    Ok(quote! {
+        impl Object for #type_name {
             fn schema() -> &'static orm::object::Schema {
                 &orm::object::Schema {
                     table_name: #table_name,
                     type_name: stringify!(#type_name),
                     attrs: &[#(#attr_names),*],
-                    columns: &[#((#column_names, <#types as orm::data::DetectDataType>::TYPE)),*],
+                    columns: &[#(#columns),*],
+                    strict: #strict,
+                    without_rowid: false,
+                    revisions: #revisions,
+                    indexes: &[#(#indexes),*],
                 }
             }
 
-            fn from_row(row: orm::storage::Row<'_>) -> Self {
-                Self {
-                    #(#row_constructors),*
-                }
+            fn try_from_row(row: orm::storage::Row<'_>) -> orm::Result<Self> {
+                let variant: ::std::string::String = row[0]
+                    .try_convert()
+                    .map_err(|e| orm::object::conversion_error(Self::schema(), 0, e))?;
+                Ok(match variant.as_str() {
+                    #(#from_row,)*
+                    other => {
+                        let error = orm::data::ConversionError::new(
+                            ::std::format!("a variant of {}", stringify!(#type_name)),
+                            ::std::format!("`{}`", other),
+                        );
+                        return Err(orm::object::conversion_error(Self::schema(), 0, error));
+                    }
+                })
             }
 
             fn to_row(&self) -> orm::storage::Row<'_> {
                 use orm::data::ValueConvert;
-                vec![
-                    #(self.#attrs.to_value()),*
-                ]
+                let mut row = vec![orm::data::Value::Null; #column_count];
+                match self {
+                    #(#to_row)*
+                }
+                row
             }
         }
+    })
+}
+
+fn bool_encoding_variant(encoding: &syn::LitStr) -> syn::Ident {
+    let variant = match encoding.value().as_str() {
+        "integer" => "Integer",
+        "text-yn" => "TextYn",
+        _ => "TextTrueFalse",
     };
+    syn::Ident::new(variant, encoding.span())
+}
+
+fn column_def(
+    name: &str,
+    ty: &syn::Type,
+    flags: &FieldAttrs,
+    nullable: proc_macro2::TokenStream,
+    split_table: &proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    let unique = flags.unique;
+    let primary_key = flags.primary_key;
+    let default = match &flags.default {
+        Some(default) => quote!(Some(#default)),
+        None => quote!(None),
+    };
+    let redacted = flags.redact;
+    let encrypted = flags.encrypt;
+    let external_blob = flags.external_blob;
+    let interned = flags.interned;
+    let data_type = match &flags.codec {
+        Some(codec) => quote!(<#codec as orm::codec::ColumnCodec<#ty>>::TYPE),
+        None => quote!(<#ty as orm::data::DetectDataType>::TYPE),
+    };
+    let renamed_from = match &flags.rename_from {
+        Some(old_name) => quote!(Some(#old_name)),
+        None => quote!(None),
+    };
+    let currency = match &flags.currency {
+        Some(currency) => quote!(Some(#currency)),
+        None => quote!(None),
+    };
+    let format = match &flags.codec {
+        Some(_) => quote!(None),
+        None => quote!(<#ty as orm::data::DetectDataType>::FORMAT),
+    };
+    let bool_encoding = match &flags.bool_encoding {
+        Some(encoding) => {
+            let variant = bool_encoding_variant(encoding);
+            quote!(Some(orm::data::BoolEncoding::#variant))
+        }
+        None => quote!(None),
+    };
+    quote! {
+        orm::object::ColumnDef {
+            name: #name,
+            data_type: #data_type,
+            nullable: #nullable,
+            unique: #unique,
+            primary_key: #primary_key,
+            default: #default,
+            renamed_from: #renamed_from,
+            redacted: #redacted,
+            encrypted: #encrypted,
+            external_blob: #external_blob,
+            checksum_of: None,
+            split_table: #split_table,
+            interned: #interned,
+            currency: #currency,
+            format: #format,
+            bool_encoding: #bool_encoding,
+        }
+    }
+}
+
+fn index_defs(index_attrs: &[IndexAttrs]) -> Vec<proc_macro2::TokenStream> {
+    index_attrs
+        .iter()
+        .map(|index| {
+            let columns = &index.columns;
+            let expressions = &index.expressions;
+            let unique = index.unique;
+            let name = match &index.name {
+                Some(name) => quote!(Some(#name)),
+                None => quote!(None),
+            };
+            let predicate = match &index.predicate {
+                Some(predicate) => quote!(Some(#predicate)),
+                None => quote!(None),
+            };
+            quote! {
+                orm::object::IndexDef {
+                    name: #name,
+                    columns: &[#(#columns),*],
+                    expressions: &[#(#expressions),*],
+                    unique: #unique,
+                    predicate: #predicate,
+                }
+            }
+        })
+        .collect()
+}
+
+fn expand_query(
+    vis: &syn::Visibility,
+    type_name: &syn::Ident,
+    attrs: &[syn::Ident],
+    column_names: &[String],
+    types: &[syn::Type],
+    flags: &[FieldAttrs],
+) -> proc_macro2::TokenStream {
+    let query_name = quote::format_ident!("{}Query", type_name);
+    // Codec fields are stored in a form only the codec knows, so they can't
+    // be filtered on by value.
+    let methods = attrs
+        .iter()
+        .zip(column_names)
+        .zip(types)
+        .zip(flags)
+        .filter(|(_, flags)| flags.codec.is_none())
+        .map(|(((attr, column_name), ty), flags)| {
+            let by = quote::format_ident!("by_{}", attr);
+            let like = quote::format_ident!("{}_like", attr);
+            let ignore_case = quote::format_ident!("by_{}_ignore_case", attr);
+            if is_poly_ref(ty).is_some() {
+                let table_column = format!("{}_table", column_name);
+                let id_column = format!("{}_id", column_name);
+                return quote! {
+                    pub fn #by(self, target: &orm::object::PolyRef) -> Self {
+                        Self(
+                            self.0
+                                .filter(#table_column, orm::query::Op::Eq, target.table_name.clone())
+                                .filter(#id_column, orm::query::Op::Eq, target.id.into_i64()),
+                        )
+                    }
+                };
+            }
+            if is_money(ty).is_some() {
+                let currency_column = format!("{}_currency", column_name);
+                let check = match &flags.currency {
+                    Some(currency) => quote! {
+                        assert_eq!(value.currency, #currency, "currency mismatch");
+                    },
+                    None => quote!(),
+                };
+                let currency_filter = flags.currency.is_none().then(|| {
+                    quote!(.filter(#currency_column, orm::query::Op::Eq, value.currency.to_string()))
+                });
+                return quote! {
+                    pub fn #by(self, value: orm::money::Money) -> Self {
+                        #check
+                        Self(
+                            self.0
+                                .filter(#column_name, orm::query::Op::Eq, value.minor_units)
+                                #currency_filter,
+                        )
+                    }
+                };
+            }
+            let like = is_string(ty).then(|| {
+                quote! {
+                    pub fn #like(self, pattern: &str) -> Self {
+                        Self(self.0.filter(
+                            #column_name,
+                            orm::query::Op::Like,
+                            ::std::string::ToString::to_string(pattern),
+                        ))
+                    }
+
+                    pub fn #ignore_case(self, value: &str) -> Self {
+                        Self(self.0.filter(
+                            #column_name,
+                            orm::query::Op::EqIgnoreCase,
+                            ::std::string::ToString::to_string(value),
+                        ))
+                    }
+                }
+            });
+            quote! {
+                pub fn #by(self, value: impl ::core::convert::Into<#ty>) -> Self {
+                    let value: #ty = ::core::convert::Into::into(value);
+                    Self(self.0.filter(#column_name, orm::query::Op::Eq, value))
+                }
+
+                #like
+            }
+        });
+
+    // Typed columns for Transaction::select, e.g. `User::columns().age.gt(18)`.
+    let columns_name = quote::format_ident!("{}Columns", type_name);
+    let (column_attrs, column_defs): (Vec<_>, Vec<_>) = attrs
+        .iter()
+        .zip(column_names)
+        .zip(types)
+        .zip(flags)
+        .filter(|((_, ty), flags)| {
+            flags.codec.is_none() && is_poly_ref(ty).is_none() && is_money(ty).is_none()
+        })
+        .map(|(((attr, column_name), ty), _)| {
+            let column = quote!(orm::query::Column::<#type_name, #ty>::new(#column_name));
+            (
+                quote!(pub #attr: orm::query::Column<#type_name, #ty>),
+                quote!(#attr: #column),
+            )
+        })
+        .unzip();
+
+    quote! {
+        #vis struct #query_name(orm::query::Query<#type_name>);
+
+        #vis struct #columns_name {
+            #(#column_attrs,)*
+        }
+
+        impl #type_name {
+            #vis fn query() -> #query_name {
+                #query_name(orm::query::Query::new())
+            }
+
+            #vis fn columns() -> #columns_name {
+                #columns_name {
+                    #(#column_defs,)*
+                }
+            }
+        }
+
+        impl #query_name {
+            #(#methods)*
+
+            pub fn limit(self, limit: usize) -> Self {
+                Self(self.0.limit(limit))
+            }
 
-    expanded.into()
+            pub fn recursive(self, seed: Self, step: orm::query::Step) -> Self {
+                Self(self.0.recursive(seed.0, step))
+            }
+
+            pub fn use_index(self, name: &str) -> Self {
+                Self(self.0.use_index(name))
+            }
+
+            pub fn no_index(self) -> Self {
+                Self(self.0.no_index())
+            }
+
+            pub fn sample(self, count: usize) -> Self {
+                Self(self.0.sample(count))
+            }
+
+            pub fn sample_fraction(self, fraction: f64) -> Self {
+                Self(self.0.sample_fraction(fraction))
+            }
+
+            pub fn fetch<'t>(
+                &self,
+                tx: &'t orm::Transaction<'_>,
+            ) -> orm::Result<::std::vec::Vec<orm::Tx<'t, #type_name>>> {
+                self.0.fetch(tx)
+            }
+
+            pub fn scan<'t, 'a>(
+                &self,
+                tx: &'t orm::Transaction<'a>,
+            ) -> orm::Result<orm::Scan<'t, 'a, #type_name>> {
+                self.0.scan(tx)
+            }
+
+            pub fn fetch_window<'t>(
+                &self,
+                tx: &'t orm::Transaction<'_>,
+                window: &orm::query::Window,
+            ) -> orm::Result<::std::vec::Vec<(orm::Tx<'t, #type_name>, orm::data::Value<'static>)>> {
+                self.0.fetch_window(tx, window)
+            }
+
+            pub fn sum_money(
+                &self,
+                tx: &orm::Transaction<'_>,
+                column: &str,
+            ) -> orm::Result<::std::vec::Vec<orm::money::Money>> {
+                self.0.sum_money(tx, column)
+            }
+
+            pub fn into_query(self) -> orm::query::Query<#type_name> {
+                self.0
+            }
+        }
+    }
 }
 
-fn add_train_bounds(mut generics: Generics) -> Generics {
+// `UserPatch { name: Some(..), ..Default::default() }` assigns only the name.
+fn expand_patch(
+    vis: &syn::Visibility,
+    type_name: &syn::Ident,
+    attrs: &[syn::Ident],
+    types: &[syn::Type],
+) -> proc_macro2::TokenStream {
+    let patch_name = quote::format_ident!("{}Patch", type_name);
+    quote! {
+        #vis struct #patch_name {
+            #(pub #attrs: ::core::option::Option<#types>,)*
+        }
+
+        impl ::core::default::Default for #patch_name {
+            fn default() -> Self {
+                Self {
+                    #(#attrs: ::core::option::Option::None,)*
+                }
+            }
+        }
+
+        impl orm::object::Patch for #patch_name {
+            type Object = #type_name;
+
+            fn is_empty(&self) -> bool {
+                true #(&& self.#attrs.is_none())*
+            }
+
+            fn apply_to(self, object: &mut #type_name) {
+                #(
+                    if let ::core::option::Option::Some(value) = self.#attrs {
+                        object.#attrs = value;
+                    }
+                )*
+            }
+        }
+    }
+}
+
+fn snake_case(name: &str) -> String {
+    let chars = name.chars().collect::<Vec<_>>();
+    let mut result = String::with_capacity(name.len() + 4);
+    for (i, c) in chars.iter().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            let prev_lower = chars[i - 1].is_lowercase() || chars[i - 1].is_ascii_digit();
+            let next_lower = chars.get(i + 1).is_some_and(|c| c.is_lowercase());
+            if prev_lower || (chars[i - 1].is_uppercase() && next_lower) {
+                result.push('_');
+            }
+        }
+        result.extend(c.to_lowercase());
+    }
+    result
+}
+
+fn is_string(ty: &syn::Type) -> bool {
+    let syn::Type::Path(path) = ty else {
+        return false;
+    };
+    let Some(segment) = path.path.segments.last() else {
+        return false;
+    };
+    match &segment.arguments {
+        syn::PathArguments::None => segment.ident == "String",
+        syn::PathArguments::AngleBracketed(args) if segment.ident == "Option" => {
+            matches!(args.args.first(), Some(syn::GenericArgument::Type(ty)) if is_string(ty))
+        }
+        _ => false,
+    }
+}
+
+// Some(nullable) for `PolyRef` and `Option<PolyRef>` fields.
+fn is_poly_ref(ty: &syn::Type) -> Option<bool> {
+    let syn::Type::Path(path) = ty else {
+        return None;
+    };
+    let segment = path.path.segments.last()?;
+    match &segment.arguments {
+        syn::PathArguments::None if segment.ident == "PolyRef" => Some(false),
+        syn::PathArguments::AngleBracketed(args) if segment.ident == "Option" => {
+            match args.args.first() {
+                Some(syn::GenericArgument::Type(ty)) if is_poly_ref(ty) == Some(false) => {
+                    Some(true)
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+// Some(nullable) for `Money` and `Option<Money>` fields.
+fn is_money(ty: &syn::Type) -> Option<bool> {
+    let syn::Type::Path(path) = ty else {
+        return None;
+    };
+    let segment = path.path.segments.last()?;
+    match &segment.arguments {
+        syn::PathArguments::None if segment.ident == "Money" => Some(false),
+        syn::PathArguments::AngleBracketed(args) if segment.ident == "Option" => {
+            match args.args.first() {
+                Some(syn::GenericArgument::Type(ty)) if is_money(ty) == Some(false) => Some(true),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+// Names of the columns a field is stored in, in row order.
+fn stored_columns(name: &str, ty: &syn::Type, flags: &FieldAttrs) -> Vec<String> {
+    if is_poly_ref(ty).is_some() {
+        vec![format!("{}_table", name), format!("{}_id", name)]
+    } else if is_money(ty).is_some() && flags.currency.is_none() {
+        vec![name.to_string(), format!("{}_currency", name)]
+    } else if flags.checksum {
+        vec![name.to_string(), format!("{}_checksum", name)]
+    } else {
+        vec![name.to_string()]
+    }
+}
+
+fn is_bytes(ty: &syn::Type) -> bool {
+    let syn::Type::Path(path) = ty else {
+        return false;
+    };
+    let Some(segment) = path.path.segments.last() else {
+        return false;
+    };
+    let syn::PathArguments::AngleBracketed(args) = &segment.arguments else {
+        return false;
+    };
+    match args.args.first() {
+        Some(syn::GenericArgument::Type(syn::Type::Path(inner))) if segment.ident == "Vec" => {
+            inner.path.is_ident("u8")
+        }
+        Some(syn::GenericArgument::Type(ty)) if segment.ident == "Option" => is_bytes(ty),
+        _ => false,
+    }
+}
+
+#[derive(Default)]
+struct ContainerAttrs {
+    query: bool,
+    patch: bool,
+    naming: Naming,
+    bound: Option<Vec<WherePredicate>>,
+    debug: bool,
+    expires: Option<syn::LitStr>,
+    tree_parent: Option<syn::LitStr>,
+    strict: bool,
+    without_rowid: bool,
+    revisions: bool,
+    splits: Vec<SplitAttrs>,
+    discriminator: Option<syn::LitStr>,
+    view_of: Option<syn::Path>,
+}
+
+struct SplitAttrs {
+    table: syn::LitStr,
+    fields: Vec<syn::Ident>,
+}
+
+#[derive(Default)]
+enum Naming {
+    #[default]
+    Default,
+    Diesel,
+}
+
+fn parse_container_attrs(attrs: &[Attribute]) -> syn::Result<ContainerAttrs> {
+    let mut result = ContainerAttrs::default();
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("orm")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("query") {
+                result.query = true;
+                Ok(())
+            } else if meta.path.is_ident("patch") {
+                result.patch = true;
+                Ok(())
+            } else if meta.path.is_ident("debug") {
+                result.debug = true;
+                Ok(())
+            } else if meta.path.is_ident("strict") {
+                result.strict = true;
+                Ok(())
+            } else if meta.path.is_ident("without_rowid") {
+                result.without_rowid = true;
+                Ok(())
+            } else if meta.path.is_ident("revisions") {
+                result.revisions = true;
+                Ok(())
+            } else if meta.path.is_ident("discriminator") {
+                result.discriminator = Some(meta.value()?.parse()?);
+                Ok(())
+            } else if meta.path.is_ident("view_of") {
+                result.view_of = Some(meta.value()?.parse()?);
+                Ok(())
+            } else if meta.path.is_ident("split") {
+                let (mut table, mut fields) = (None, Vec::new());
+                meta.parse_nested_meta(|meta| {
+                    if meta.path.is_ident("table") {
+                        table = Some(meta.value()?.parse()?);
+                        Ok(())
+                    } else if meta.path.is_ident("fields") {
+                        let content;
+                        syn::parenthesized!(content in meta.input);
+                        let idents =
+                            Punctuated::<syn::Ident, Token![,]>::parse_terminated(&content)?;
+                        fields.extend(idents);
+                        Ok(())
+                    } else {
+                        Err(meta.error("expected `table` or `fields`"))
+                    }
+                })?;
+                let Some(table) = table else {
+                    return Err(meta.error("#[orm(split)] requires a `table`"));
+                };
+                result.splits.push(SplitAttrs { table, fields });
+                Ok(())
+            } else if meta.path.is_ident("expires") {
+                meta.parse_nested_meta(|meta| {
+                    if meta.path.is_ident("field") {
+                        result.expires = Some(meta.value()?.parse()?);
+                        Ok(())
+                    } else {
+                        Err(meta.error("expected `field`"))
+                    }
+                })
+            } else if meta.path.is_ident("tree") {
+                meta.parse_nested_meta(|meta| {
+                    if meta.path.is_ident("parent") {
+                        result.tree_parent = Some(meta.value()?.parse()?);
+                        Ok(())
+                    } else {
+                        Err(meta.error("expected `parent`"))
+                    }
+                })
+            } else if meta.path.is_ident("bound") {
+                let bound: syn::LitStr = meta.value()?.parse()?;
+                let predicates =
+                    bound.parse_with(Punctuated::<WherePredicate, Token![,]>::parse_terminated)?;
+                result.bound = Some(predicates.into_iter().collect());
+                Ok(())
+            } else if meta.path.is_ident("naming") {
+                let naming: syn::LitStr = meta.value()?.parse()?;
+                result.naming = match naming.value().as_str() {
+                    "diesel" | "sea_orm" => Naming::Diesel,
+                    _ => {
+                        return Err(syn::Error::new_spanned(
+                            naming,
+                            "expected \"diesel\" or \"sea_orm\"",
+                        ))
+                    }
+                };
+                Ok(())
+            } else {
+                Err(meta.error("unsupported orm attribute"))
+            }
+        })?;
+    }
+    Ok(result)
+}
+
+#[derive(Default)]
+struct IndexAttrs {
+    columns: Vec<syn::LitStr>,
+    expressions: Vec<syn::LitStr>,
+    unique: bool,
+    name: Option<syn::LitStr>,
+    predicate: Option<syn::LitStr>,
+}
+
+fn parse_index_attrs(attrs: &[Attribute]) -> syn::Result<Vec<IndexAttrs>> {
+    let mut result = Vec::new();
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("index")) {
+        let mut index = IndexAttrs::default();
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("columns") {
+                let content;
+                syn::parenthesized!(content in meta.input);
+                let columns = Punctuated::<syn::LitStr, Token![,]>::parse_terminated(&content)?;
+                index.columns.extend(columns);
+                Ok(())
+            } else if meta.path.is_ident("expressions") {
+                let content;
+                syn::parenthesized!(content in meta.input);
+                let expressions = Punctuated::<syn::LitStr, Token![,]>::parse_terminated(&content)?;
+                index.expressions.extend(expressions);
+                Ok(())
+            } else if meta.path.is_ident("unique") {
+                index.unique = true;
+                Ok(())
+            } else if meta.path.is_ident("name") {
+                index.name = Some(meta.value()?.parse()?);
+                Ok(())
+            } else if meta.path.is_ident("where") {
+                index.predicate = Some(meta.value()?.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("unsupported index attribute"))
+            }
+        })?;
+        if index.columns.is_empty() && index.expressions.is_empty() {
+            return Err(syn::Error::new_spanned(
+                attr,
+                "#[index] requires at least one column or expression",
+            ));
+        }
+        result.push(index);
+    }
+    Ok(result)
+}
+
+#[derive(Default)]
+struct FieldAttrs {
+    is_id: bool,
+    unique: bool,
+    primary_key: bool,
+    default: Option<syn::LitStr>,
+    rename_from: Option<syn::LitStr>,
+    redact: bool,
+    encrypt: bool,
+    checksum: bool,
+    external_blob: bool,
+    codec: Option<syn::Path>,
+    interned: bool,
+    currency: Option<syn::LitStr>,
+    bool_encoding: Option<syn::LitStr>,
+    validators: Vec<Validator>,
+}
+
+enum Validator {
+    Length(Option<syn::Expr>, Option<syn::Expr>),
+    Range(Option<syn::Expr>, Option<syn::Expr>),
+    Custom(syn::Path),
+}
+
+fn parse_bounds(
+    meta: syn::meta::ParseNestedMeta,
+) -> syn::Result<(Option<syn::Expr>, Option<syn::Expr>)> {
+    let (mut min, mut max) = (None, None);
+    meta.parse_nested_meta(|meta| {
+        if meta.path.is_ident("min") {
+            min = Some(meta.value()?.parse()?);
+            Ok(())
+        } else if meta.path.is_ident("max") {
+            max = Some(meta.value()?.parse()?);
+            Ok(())
+        } else {
+            Err(meta.error("expected `min` or `max`"))
+        }
+    })?;
+    Ok((min, max))
+}
+
+fn parse_validators(
+    meta: syn::meta::ParseNestedMeta,
+    validators: &mut Vec<Validator>,
+) -> syn::Result<()> {
+    meta.parse_nested_meta(|meta| {
+        if meta.path.is_ident("length") {
+            let (min, max) = parse_bounds(meta)?;
+            validators.push(Validator::Length(min, max));
+            Ok(())
+        } else if meta.path.is_ident("range") {
+            let (min, max) = parse_bounds(meta)?;
+            validators.push(Validator::Range(min, max));
+            Ok(())
+        } else if meta.path.is_ident("custom") {
+            validators.push(Validator::Custom(meta.value()?.parse()?));
+            Ok(())
+        } else if meta.path.is_ident("regex") {
+            Err(meta.error("regex validators are not supported, use `custom` instead"))
+        } else {
+            Err(meta.error("unsupported validator"))
+        }
+    })
+}
+
+fn parse_field_attrs(attrs: &[Attribute]) -> syn::Result<FieldAttrs> {
+    let mut result = FieldAttrs::default();
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("orm")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("id") {
+                result.is_id = true;
+                Ok(())
+            } else if meta.path.is_ident("unique") {
+                result.unique = true;
+                Ok(())
+            } else if meta.path.is_ident("primary_key") {
+                result.primary_key = true;
+                Ok(())
+            } else if meta.path.is_ident("default") {
+                result.default = Some(meta.value()?.parse()?);
+                Ok(())
+            } else if meta.path.is_ident("redact") {
+                result.redact = true;
+                Ok(())
+            } else if meta.path.is_ident("checksum") {
+                result.checksum = true;
+                Ok(())
+            } else if meta.path.is_ident("external_blob") {
+                result.external_blob = true;
+                Ok(())
+            } else if meta.path.is_ident("encrypt") {
+                result.encrypt = true;
+                Ok(())
+            } else if meta.path.is_ident("interned") {
+                result.interned = true;
+                Ok(())
+            } else if meta.path.is_ident("currency") {
+                result.currency = Some(meta.value()?.parse()?);
+                Ok(())
+            } else if meta.path.is_ident("bool_encoding") {
+                let encoding: syn::LitStr = meta.value()?.parse()?;
+                if !["integer", "text-yn", "text-truefalse"].contains(&encoding.value().as_str()) {
+                    return Err(syn::Error::new_spanned(
+                        encoding,
+                        "expected \"integer\", \"text-yn\" or \"text-truefalse\"",
+                    ));
+                }
+                result.bool_encoding = Some(encoding);
+                Ok(())
+            } else if meta.path.is_ident("codec") {
+                result.codec = Some(meta.value()?.parse()?);
+                Ok(())
+            } else if meta.path.is_ident("rename_from") {
+                result.rename_from = Some(meta.value()?.parse()?);
+                Ok(())
+            } else if meta.path.is_ident("validate") {
+                parse_validators(meta, &mut result.validators)
+            } else {
+                Err(meta.error("unsupported orm attribute"))
+            }
+        })?;
+    }
+    Ok(result)
+}
+
+fn option_expr(expr: &Option<syn::Expr>) -> proc_macro2::TokenStream {
+    match expr {
+        Some(expr) => quote!(::core::option::Option::Some(#expr)),
+        None => quote!(::core::option::Option::None),
+    }
+}
+
+fn add_train_bounds(mut generics: Generics, bound: Option<&Vec<WherePredicate>>) -> Generics {
     for param in &mut generics.params {
         if let syn::GenericParam::Type(ref mut type_param) = *param {
-            type_param.bounds.push(parse_quote!(ValueConvert));
-            type_param.bounds.push(parse_quote!(DetectDataType));
+            type_param.bounds.push(parse_quote!('static));
+            if bound.is_none() {
+                type_param
+                    .bounds
+                    .push(parse_quote!(orm::data::ValueConvert));
+                type_param
+                    .bounds
+                    .push(parse_quote!(orm::data::DetectDataType));
+            }
         }
     }
+    if let Some(bound) = bound {
+        generics
+            .make_where_clause()
+            .predicates
+            .extend(bound.iter().cloned());
+    }
     generics
 }
 
@@ -95,19 +1835,23 @@ fn get_table_name(attr: &Attribute) -> Option<String> {
     }
 }
 
-fn get_column_name(attr: &Attribute) -> Option<String> {
+fn get_column_name(attr: &Attribute) -> Option<syn::LitStr> {
     if attr.path().is_ident("column_name") {
-        parse_name(attr).into()
+        parse_lit(attr).into()
     } else {
         None
     }
 }
 
 fn parse_name(attr: &Attribute) -> String {
+    parse_lit(attr).value()
+}
+
+fn parse_lit(attr: &Attribute) -> syn::LitStr {
     let a: syn::Lit = attr.parse_args().unwrap();
 
     match a {
-        syn::Lit::Str(s) => s.value(),
+        syn::Lit::Str(s) => s,
         _ => panic!("Expected string literal"),
     }
 }
@@ -0,0 +1,66 @@
+#![forbid(unsafe_code)]
+
+use std::fmt;
+
+////////////////////////////////////////////////////////////////////////////////
+
+// What transactions of a connection may do with objects of a type, see
+// Connection::restrict. Objects created in the same transaction are exempt
+// from AppendOnly, since no stored row changes until they're committed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Access {
+    #[default]
+    ReadWrite,
+    AppendOnly,
+    ReadOnly,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Operation {
+    Create,
+    Update,
+    Delete,
+}
+
+impl Access {
+    pub fn allows(&self, operation: Operation) -> bool {
+        match self {
+            Access::ReadWrite => true,
+            Access::AppendOnly => operation == Operation::Create,
+            Access::ReadOnly => false,
+        }
+    }
+}
+
+impl Operation {
+    fn verb(&self) -> &'static str {
+        match self {
+            Operation::Create => "create",
+            Operation::Update => "modify",
+            Operation::Delete => "delete",
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug)]
+pub struct AccessDeniedError {
+    pub type_name: &'static str,
+    pub operation: Operation,
+    pub access: Access,
+}
+
+impl fmt::Display for AccessDeniedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "can't {} objects of type '{}': access is {:?}",
+            self.operation.verb(),
+            self.type_name,
+            self.access
+        )
+    }
+}
+
+impl std::error::Error for AccessDeniedError {}
@@ -0,0 +1,151 @@
+#![forbid(unsafe_code)]
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::{
+    object::Schema,
+    query::{Filter, Op},
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+// The columns a query filtered on, equality lookups first. Case-insensitive
+// lookups are keyed by the expression an index would have to cover.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct QueryShape {
+    pub schema: &'static Schema,
+    pub keys: Vec<String>,
+}
+
+impl QueryShape {
+    pub fn new(schema: &'static Schema, filters: &[Filter]) -> Option<Self> {
+        let mut equalities = Vec::new();
+        let mut ranges = Vec::new();
+        for filter in filters {
+            match filter.op {
+                Op::Eq => equalities.push(filter.column.to_string()),
+                Op::EqIgnoreCase => equalities.push(format!("lower({})", filter.column)),
+                Op::Lt | Op::Le | Op::Gt | Op::Ge => ranges.push(filter.column.to_string()),
+                Op::Ne | Op::Like => {}
+            }
+        }
+        let mut keys = Vec::new();
+        for key in equalities.into_iter().chain(ranges) {
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+        (!keys.is_empty()).then_some(Self { schema, keys })
+    }
+
+    // An index is usable as long as it starts with one of the filtered keys.
+    fn is_indexed(&self) -> bool {
+        let implicit = self
+            .schema
+            .columns
+            .iter()
+            .filter(|column| column.unique || column.primary_key)
+            .map(|column| normalize(column.name));
+        let declared = self.schema.indexes.iter().filter_map(|index| {
+            let leading = index.columns.first().or(index.expressions.first())?;
+            index.predicate.is_none().then(|| normalize(leading))
+        });
+        implicit
+            .chain(declared)
+            .any(|leading| self.keys.iter().any(|key| normalize(key) == leading))
+    }
+}
+
+fn normalize(key: &str) -> String {
+    key.chars()
+        .filter(|c| !c.is_whitespace() && !matches!(c, '`' | '"'))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IndexSuggestion {
+    pub type_name: &'static str,
+    pub table_name: &'static str,
+    pub columns: Vec<String>,
+    pub expressions: Vec<String>,
+    pub queries: u64,
+}
+
+impl IndexSuggestion {
+    pub fn attribute(&self) -> String {
+        let list = |keys: &[String]| {
+            keys.iter()
+                .map(|key| format!("{:?}", key))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let mut parts = Vec::new();
+        if !self.columns.is_empty() {
+            parts.push(format!("columns({})", list(&self.columns)));
+        }
+        if !self.expressions.is_empty() {
+            parts.push(format!("expressions({})", list(&self.expressions)));
+        }
+        format!("#[index({})]", parts.join(", "))
+    }
+}
+
+impl fmt::Display for IndexSuggestion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} ({} unindexed queries)",
+            self.type_name,
+            self.attribute(),
+            self.queries
+        )
+    }
+}
+
+pub(crate) fn suggest_indexes(
+    queries: &HashMap<QueryShape, u64>,
+    min_queries: u64,
+) -> Vec<IndexSuggestion> {
+    // A composite index also serves lookups by any prefix of its keys, so those
+    // are folded into the longer shape instead of being suggested separately.
+    let mut unindexed: HashMap<&QueryShape, u64> = HashMap::new();
+    for (shape, &count) in queries.iter().filter(|(shape, _)| !shape.is_indexed()) {
+        let longer = queries.keys().find(|other| {
+            other.schema == shape.schema
+                && other.keys.len() > shape.keys.len()
+                && other.keys.starts_with(&shape.keys)
+                && !other.is_indexed()
+        });
+        *unindexed.entry(longer.unwrap_or(shape)).or_default() += count;
+    }
+
+    let mut suggestions = unindexed
+        .into_iter()
+        .filter(|(_, count)| *count >= min_queries)
+        .map(|(shape, count)| {
+            let (expressions, columns) = shape
+                .keys
+                .iter()
+                .cloned()
+                .partition(|key| key.contains('('));
+            IndexSuggestion {
+                type_name: shape.schema.type_name,
+                table_name: shape.schema.table_name,
+                columns,
+                expressions,
+                queries: count,
+            }
+        })
+        .collect::<Vec<_>>();
+    suggestions.sort_by(|a, b| {
+        b.queries
+            .cmp(&a.queries)
+            .then(a.type_name.cmp(b.type_name))
+            .then(a.columns.cmp(&b.columns))
+    });
+    suggestions
+}
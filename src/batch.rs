@@ -0,0 +1,51 @@
+#![forbid(unsafe_code)]
+
+use crate::{data::ObjectId, error::Error};
+
+////////////////////////////////////////////////////////////////////////////////
+
+// One write of an import, see Transaction::apply_batch.
+pub enum BatchOp<T> {
+    Create(T),
+    // Replaces every field of the stored object.
+    Update(ObjectId, T),
+    Delete(ObjectId),
+}
+
+// What happened to each op, in order: the id it wrote, or why it was skipped.
+pub struct BatchResult {
+    pub results: Vec<Result<ObjectId, Error>>,
+}
+
+impl BatchResult {
+    pub fn is_ok(&self) -> bool {
+        self.results.iter().all(Result::is_ok)
+    }
+
+    pub fn succeeded(&self) -> usize {
+        self.results.iter().filter(|result| result.is_ok()).count()
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = (usize, &Error)> {
+        self.results
+            .iter()
+            .enumerate()
+            .filter_map(|(i, result)| Some((i, result.as_ref().err()?)))
+    }
+}
+
+// Problems with the op itself. Anything else, such as a lock conflict or a
+// broken connection, would fail every remaining op too, so it ends the batch.
+pub(crate) fn is_item_error(err: &Error) -> bool {
+    matches!(
+        err.inner(),
+        Error::NotFound(_)
+            | Error::Validation(_)
+            | Error::InvalidText(_)
+            | Error::AccessDenied(_)
+            | Error::UniqueViolation(_)
+            | Error::ForeignKeyViolation(_)
+            | Error::CheckViolation(_)
+            | Error::NotNullViolation(_)
+    )
+}
@@ -0,0 +1,157 @@
+#![forbid(unsafe_code)]
+
+use std::borrow::Cow;
+use std::fmt;
+
+use crate::{
+    data::Value,
+    error::Error,
+    object::Schema,
+    storage::{Row, StorageTransaction},
+    ObjectId, Result,
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+fn compress(state: &mut [u32; 8], block: &[u8]) {
+    let mut w = [0u32; 64];
+    for (word, bytes) in w.iter_mut().zip(block.chunks_exact(4)) {
+        *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+    for i in 16..64 {
+        let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+        let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+        w[i] = w[i - 16]
+            .wrapping_add(s0)
+            .wrapping_add(w[i - 7])
+            .wrapping_add(s1);
+    }
+
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *state;
+    for (k, w) in K.iter().zip(w) {
+        let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+        let ch = (e & f) ^ (!e & g);
+        let t1 = h
+            .wrapping_add(s1)
+            .wrapping_add(ch)
+            .wrapping_add(*k)
+            .wrapping_add(w);
+        let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+        let maj = (a & b) ^ (a & c) ^ (b & c);
+        let t2 = s0.wrapping_add(maj);
+        h = g;
+        g = f;
+        f = e;
+        e = d.wrapping_add(t1);
+        d = c;
+        c = b;
+        b = a;
+        a = t1.wrapping_add(t2);
+    }
+    for (word, value) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+        *word = word.wrapping_add(value);
+    }
+}
+
+// SHA-256 of the content, which is the key blobs are deduplicated by.
+pub fn content_hash(data: &[u8]) -> [u8; 32] {
+    let mut state = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+    let mut blocks = data.chunks_exact(64);
+    for block in blocks.by_ref() {
+        compress(&mut state, block);
+    }
+
+    let mut tail = blocks.remainder().to_vec();
+    tail.push(0x80);
+    tail.resize(if tail.len() > 56 { 120 } else { 56 }, 0);
+    tail.extend_from_slice(&(data.len() as u64).wrapping_mul(8).to_be_bytes());
+    for block in tail.chunks_exact(64) {
+        compress(&mut state, block);
+    }
+
+    let mut hash = [0; 32];
+    for (bytes, word) in hash.chunks_exact_mut(4).zip(state) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    hash
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug)]
+pub struct MissingBlobError {
+    pub object_id: ObjectId,
+    pub type_name: &'static str,
+    pub column_name: &'static str,
+    pub hash: Vec<u8>,
+}
+
+impl fmt::Display for MissingBlobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "blob referenced by {}::{} of object {} is missing: ",
+            self.type_name, self.column_name, self.object_id
+        )?;
+        self.hash
+            .iter()
+            .try_for_each(|byte| write!(f, "{:02x}", byte))
+    }
+}
+
+impl std::error::Error for MissingBlobError {}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub(crate) fn store_blobs<'r>(
+    tx: &dyn StorageTransaction,
+    schema: &Schema,
+    mut row: Row<'r>,
+) -> Result<Row<'r>> {
+    for (column, value) in schema.columns.iter().zip(row.iter_mut()) {
+        let (true, Value::Bytes(data)) = (column.external_blob, &*value) else {
+            continue;
+        };
+        let hash = content_hash(data);
+        tx.put_blob(&hash, data)?;
+        *value = Value::Bytes(Cow::Owned(hash.to_vec()));
+    }
+    Ok(row)
+}
+
+pub(crate) fn load_blobs<'r>(
+    tx: &dyn StorageTransaction,
+    id: ObjectId,
+    schema: &Schema,
+    mut row: Row<'r>,
+) -> Result<Row<'r>> {
+    for (column, value) in schema.columns.iter().zip(row.iter_mut()) {
+        let (true, Value::Bytes(hash)) = (column.external_blob, &*value) else {
+            continue;
+        };
+        let Some(data) = tx.get_blob(hash)? else {
+            return Err(Error::MissingBlob(Box::new(MissingBlobError {
+                object_id: id,
+                type_name: schema.type_name,
+                column_name: column.name,
+                hash: hash.to_vec(),
+            })));
+        };
+        *value = Value::Bytes(Cow::Owned(data));
+    }
+    Ok(row)
+}
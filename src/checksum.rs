@@ -0,0 +1,74 @@
+#![forbid(unsafe_code)]
+
+use std::fmt;
+
+use crate::{data::Value, error::Error, object::Schema, storage::RowSlice, ObjectId, Result};
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+pub fn checksum_value(value: &Value<'_>) -> Value<'static> {
+    match value {
+        Value::Bytes(b) => Value::Int64(crc32(b) as i64),
+        _ => Value::Null,
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug)]
+pub struct CorruptionError {
+    pub object_id: ObjectId,
+    pub type_name: &'static str,
+    pub column_name: &'static str,
+    pub stored: u32,
+    pub computed: u32,
+}
+
+impl fmt::Display for CorruptionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "checksum mismatch for {}::{} of object {}: stored {:08x}, computed {:08x}",
+            self.type_name, self.column_name, self.object_id, self.stored, self.computed
+        )
+    }
+}
+
+impl std::error::Error for CorruptionError {}
+
+// A NULL checksum means the row was written before the column was checksummed,
+// so there's nothing to verify it against yet.
+pub(crate) fn verify_row(id: ObjectId, schema: &Schema, row: &RowSlice) -> Result<()> {
+    for (column, stored) in schema.columns.iter().zip(row) {
+        let (Some(source), Value::Int64(stored)) = (column.checksum_of, stored) else {
+            continue;
+        };
+        let Some(index) = schema.columns.iter().position(|c| c.name == source) else {
+            continue;
+        };
+        let Value::Int64(computed) = checksum_value(&row[index]) else {
+            continue;
+        };
+        if computed != *stored {
+            return Err(Error::Corruption(Box::new(CorruptionError {
+                object_id: id,
+                type_name: schema.type_name,
+                column_name: source,
+                stored: *stored as u32,
+                computed: computed as u32,
+            })));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,160 @@
+#![forbid(unsafe_code)]
+
+use std::borrow::Cow;
+use std::fmt;
+
+use crate::{
+    data::Value,
+    error::Error,
+    object::{ColumnDef, Schema},
+    query::{Filter, Op},
+    storage::Row,
+    Result,
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+// `decrypt` must return `None` for ciphertexts produced under another key, so
+// that rows written before a key rotation can still be read.
+pub trait ColumnCipher: Send {
+    fn encrypt(&self, plaintext: &[u8]) -> Vec<u8>;
+    fn decrypt(&self, ciphertext: &[u8]) -> Option<Vec<u8>>;
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CipherOperation {
+    Encrypt,
+    Decrypt,
+}
+
+#[derive(Debug)]
+pub struct CipherError {
+    pub operation: CipherOperation,
+    pub type_name: &'static str,
+    pub column_name: &'static str,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for CipherError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let operation = match self.operation {
+            CipherOperation::Encrypt => "encrypt",
+            CipherOperation::Decrypt => "decrypt",
+        };
+        write!(
+            f,
+            "failed to {} {}::{}: {}",
+            operation, self.type_name, self.column_name, self.reason
+        )
+    }
+}
+
+impl std::error::Error for CipherError {}
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn cipher_error(
+    operation: CipherOperation,
+    schema: &Schema,
+    column: &ColumnDef,
+    reason: &'static str,
+) -> Error {
+    Error::Cipher(Box::new(CipherError {
+        operation,
+        type_name: schema.type_name,
+        column_name: column.name,
+        reason,
+    }))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// Ciphertexts are randomized, so the only filters that can match an encrypted
+// column are NULL checks.
+pub(crate) fn check_filter(schema: &Schema, filter: &Filter) -> Result<()> {
+    let encrypted = schema
+        .column(filter.column)
+        .is_some_and(|column| column.encrypted);
+    if encrypted && !(matches!(filter.op, Op::Eq | Op::Ne) && filter.value.is_null()) {
+        return Err(Error::invalid_request(
+            schema.type_name,
+            format!("can't filter on encrypted column '{}'", filter.column),
+        ));
+    }
+    Ok(())
+}
+
+// Text ciphertexts are stored hex-encoded, so encrypted columns keep their
+// declared type and the rest of the storage layer doesn't need to know.
+pub(crate) fn encrypt_row<'r>(
+    ciphers: &[Box<dyn ColumnCipher>],
+    schema: &Schema,
+    mut row: Row<'r>,
+) -> Result<Row<'r>> {
+    for (column, value) in schema.columns.iter().zip(row.iter_mut()) {
+        if !column.encrypted || value.is_null() {
+            continue;
+        }
+        let Some(cipher) = ciphers.first() else {
+            return Err(cipher_error(
+                CipherOperation::Encrypt,
+                schema,
+                column,
+                "no cipher is configured",
+            ));
+        };
+        *value = match value {
+            Value::String(s) => Value::String(Cow::Owned(to_hex(&cipher.encrypt(s.as_bytes())))),
+            Value::Bytes(b) => Value::Bytes(Cow::Owned(cipher.encrypt(b))),
+            _ => continue,
+        };
+    }
+    Ok(row)
+}
+
+pub(crate) fn decrypt_row<'r>(
+    ciphers: &[Box<dyn ColumnCipher>],
+    schema: &Schema,
+    mut row: Row<'r>,
+) -> Result<Row<'r>> {
+    for (column, value) in schema.columns.iter().zip(row.iter_mut()) {
+        if !column.encrypted || value.is_null() {
+            continue;
+        }
+        let error = |reason| cipher_error(CipherOperation::Decrypt, schema, column, reason);
+        if ciphers.is_empty() {
+            return Err(error("no cipher is configured"));
+        }
+        let decrypt = |ciphertext: &[u8]| {
+            ciphers
+                .iter()
+                .find_map(|cipher| cipher.decrypt(ciphertext))
+                .ok_or_else(|| error("no cipher accepted the ciphertext"))
+        };
+        *value = match value {
+            Value::String(s) => {
+                let ciphertext = from_hex(s).ok_or_else(|| error("malformed ciphertext"))?;
+                let plaintext = String::from_utf8(decrypt(&ciphertext)?)
+                    .map_err(|_| error("plaintext is not valid UTF-8"))?;
+                Value::String(Cow::Owned(plaintext))
+            }
+            Value::Bytes(b) => Value::Bytes(Cow::Owned(decrypt(b)?)),
+            _ => continue,
+        };
+    }
+    Ok(row)
+}
@@ -0,0 +1,20 @@
+#![forbid(unsafe_code)]
+
+use crate::data::{ConversionError, DataType, Value};
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Stores a field of type `T` in a single column, for field types that don't
+// implement ValueConvert themselves, e.g. structs serialized into bytes.
+// Attached to a field with #[orm(codec = SomeCodec)].
+pub trait ColumnCodec<T> {
+    const TYPE: DataType;
+    const NULLABLE: bool = false;
+
+    fn encode(value: &T) -> Value<'static>;
+    fn decode(value: &Value<'_>) -> T;
+
+    fn try_decode(value: &Value<'_>) -> Result<T, ConversionError> {
+        Ok(Self::decode(value))
+    }
+}
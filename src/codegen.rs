@@ -0,0 +1,116 @@
+#![forbid(unsafe_code)]
+
+use std::fmt::Write;
+
+use crate::{ddl::affinity, storage::ColumnInfo};
+
+////////////////////////////////////////////////////////////////////////////////
+
+const KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "self", "static", "struct", "super", "trait", "true", "type", "unsafe", "use",
+    "where", "while", "abstract", "become", "box", "do", "final", "macro", "override", "priv",
+    "try", "typeof", "unsized", "virtual", "yield",
+];
+
+fn words(name: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut current = String::new();
+    let mut prev_lower = false;
+    for c in name.chars() {
+        if !c.is_ascii_alphanumeric() {
+            if !current.is_empty() {
+                words.push(std::mem::take(&mut current));
+            }
+            prev_lower = false;
+            continue;
+        }
+        if c.is_ascii_uppercase() && prev_lower && !current.is_empty() {
+            words.push(std::mem::take(&mut current));
+        }
+        prev_lower = c.is_ascii_lowercase() || c.is_ascii_digit();
+        current.push(c.to_ascii_lowercase());
+    }
+    if !current.is_empty() {
+        words.push(current);
+    }
+    words
+}
+
+fn type_name(table: &str) -> String {
+    let mut name: String = words(table)
+        .iter()
+        .map(|word| {
+            let mut chars = word.chars();
+            let first = chars.next().unwrap().to_ascii_uppercase();
+            std::iter::once(first).chain(chars).collect::<String>()
+        })
+        .collect();
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        name.insert(0, 'T');
+    }
+    name
+}
+
+fn field_name(column: &str) -> String {
+    let mut name = words(column).join("_");
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        name.insert(0, '_');
+    }
+    if KEYWORDS.contains(&name.as_str()) {
+        name.push('_');
+    }
+    name
+}
+
+fn rust_type(column: &ColumnInfo) -> String {
+    let declared = column.sql_type.to_ascii_uppercase();
+    let ty = if declared.contains("BOOL") {
+        "bool"
+    } else {
+        match affinity(&column.sql_type) {
+            "INTEGER" => "i64",
+            "TEXT" => "String",
+            "BLOB" => "Vec<u8>",
+            _ => "f64",
+        }
+    };
+    if column.not_null || column.primary_key {
+        ty.to_string()
+    } else {
+        format!("Option<{}>", ty)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub fn generate_struct(table: &str, columns: &[ColumnInfo]) -> String {
+    let name = type_name(table);
+    let mut out = String::new();
+    if !columns.iter().any(|column| column.name == "id") {
+        writeln!(
+            out,
+            "// NOTE: table `{}` has no `id` column; add an INTEGER PRIMARY KEY `id` before use.",
+            table
+        )
+        .unwrap();
+    }
+    writeln!(out, "#[derive(Object)]").unwrap();
+    if name != table {
+        writeln!(out, "#[table_name({:?})]", table).unwrap();
+    }
+    writeln!(out, "pub struct {} {{", name).unwrap();
+    for column in columns.iter().filter(|column| column.name != "id") {
+        let field = field_name(&column.name);
+        if field != column.name {
+            writeln!(out, "    #[column_name({:?})]", column.name).unwrap();
+        }
+        if let Some(default) = &column.default {
+            writeln!(out, "    #[orm(default = {:?})]", default).unwrap();
+        }
+        writeln!(out, "    pub {}: {},", field, rust_type(column)).unwrap();
+    }
+    writeln!(out, "}}").unwrap();
+    out
+}
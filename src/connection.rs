@@ -1,16 +1,291 @@
 #![forbid(unsafe_code)]
-use crate::{storage::StorageTransaction, Result, Transaction};
+use crate::{
+    access::{Access, AccessDeniedError, Operation},
+    advisor::{self, IndexSuggestion, QueryShape},
+    blob,
+    cipher::{self, ColumnCipher},
+    codegen,
+    data::{Coercion, TextDecoding},
+    ddl::{self, ChangeKind, Dialect, SchemaChange, SchemaDrift},
+    error::{ChunkedError, Error},
+    migrations::{Migration, Migrator},
+    object::{Expiring, Object, Schema},
+    query::Filter,
+    quota::{Quota, QuotaAction, QuotaError},
+    remote::RemoteConnection,
+    stats::TableStats,
+    storage::{
+        self, Checkpoint, CheckpointMode, LockState, SqliteTransaction, StorageTransaction,
+        BLOBS_TABLE,
+    },
+    transaction::{self, AnyTx},
+    watchdog::{LongTransaction, Monitor, Watchdog},
+    ObjectChange, ObjectId, Result, Transaction,
+};
+use rusqlite::types::ValueRef;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::net::ToSocketAddrs;
 use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 ////////////////////////////////////////////////////////////////////////////////
 
-trait StorageConnection {
-    fn new_transaction(&mut self) -> Result<Box<dyn StorageTransaction + '_>>;
+pub(crate) type CommitHook = Box<dyn Fn(&[ObjectChange]) + Send>;
+pub(crate) type QuotaPolicy = Box<dyn Fn(&QuotaError) -> QuotaAction + Send>;
+pub(crate) type RowFilter = Box<dyn Fn() -> Vec<Filter> + Send>;
+pub(crate) type Loader = for<'t> fn(&'t Transaction<'_>, ObjectId) -> Result<AnyTx<'t>>;
+
+#[derive(Default)]
+pub(crate) struct Settings {
+    pub debug_sql: bool,
+    pub error_context: bool,
+    pub max_objects: Option<usize>,
+    pub after_commit: Vec<CommitHook>,
+    pub quotas: HashMap<&'static str, Quota>,
+    pub access: HashMap<&'static str, Access>,
+    pub row_filters: HashMap<&'static str, RowFilter>,
+    pub quota_policy: Option<QuotaPolicy>,
+    pub ciphers: Vec<Box<dyn ColumnCipher>>,
+    pub stats: RefCell<HashMap<&'static Schema, TableStats>>,
+    pub coercion: Coercion,
+    pub text_decoding: TextDecoding,
+    pub hydrate_missing_columns: bool,
+    pub auto_migrate: bool,
+    pub strict_tables: bool,
+    pub log_queries: bool,
+    pub queries: RefCell<HashMap<QueryShape, u64>>,
+    pub loaders: HashMap<&'static str, Loader>,
+    pub watchdog: Option<Monitor>,
+}
+
+impl Settings {
+    pub fn access_of(&self, schema: &Schema) -> Access {
+        let access = self.access.get(schema.table_name);
+        access.copied().unwrap_or_default()
+    }
+
+    pub fn check_access(&self, schema: &Schema, operation: Operation) -> Result<()> {
+        let access = self.access_of(schema);
+        if access.allows(operation) {
+            return Ok(());
+        }
+        Err(Error::AccessDenied(Box::new(AccessDeniedError {
+            type_name: schema.type_name,
+            operation,
+            access,
+        })))
+    }
+
+    pub fn record(&self, schema: &'static Schema, f: impl FnOnce(&mut TableStats)) {
+        f(self.stats.borrow_mut().entry(schema).or_default());
+    }
+
+    pub fn log_query(&self, shape: impl FnOnce() -> Option<QueryShape>) {
+        if !self.log_queries {
+            return;
+        }
+        if let Some(shape) = shape() {
+            *self.queries.borrow_mut().entry(shape).or_default() += 1;
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub(crate) trait StorageConnection: Send {
+    fn new_transaction<'a>(
+        &'a mut self,
+        settings: &'a Settings,
+    ) -> Result<Box<dyn StorageTransaction + 'a>>;
+
+    fn ping(&mut self) -> Result<()> {
+        self.new_transaction(&Settings::default())?.rollback()
+    }
+
+    fn checkpoint(&mut self, _mode: CheckpointMode) -> Result<Checkpoint> {
+        Err(Error::Storage(
+            "checkpoints are not available for this connection".into(),
+        ))
+    }
+
+    fn set_auto_checkpoint(&mut self, _pages: u32) -> Result<()> {
+        Err(Error::Storage(
+            "checkpoints are not available for this connection".into(),
+        ))
+    }
+
+    fn wal_size(&mut self) -> Result<u64> {
+        Err(Error::Storage(
+            "WAL size is not available for this connection".into(),
+        ))
+    }
+
+    fn set_statement_cache_capacity(&mut self, _capacity: usize) {}
+
+    // Leaves the database as a restart should find it, whatever other
+    // connections are still doing.
+    fn prepare_shutdown(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn close(self: Box<Self>) -> Result<()> {
+        Ok(())
+    }
+
+    fn replace_pragmas(
+        &mut self,
+        _pragmas: &[(&'static str, &str)],
+    ) -> Result<Vec<(&'static str, String)>> {
+        Ok(Vec::new())
+    }
+
+    fn restore_pragmas(&mut self, _previous: Vec<(&'static str, String)>) -> Result<()> {
+        Ok(())
+    }
+
+    fn lock_state(&mut self) -> Result<LockState> {
+        Err(Error::Storage(
+            "lock state is not available for this connection".into(),
+        ))
+    }
 }
 
+const BULK_PRAGMAS: [(&str, &str); 3] = [
+    ("synchronous", "OFF"),
+    ("journal_mode", "MEMORY"),
+    ("defer_foreign_keys", "ON"),
+];
+
+const PURGE_BATCH_SIZE: usize = 1000;
+
+const REENCRYPT_BATCH_SIZE: usize = 1000;
+
+const NO_FOREIGN_KEYS_PRAGMAS: [(&str, &str); 1] = [("foreign_keys", "OFF")];
+
+const NO_WAIT_PRAGMAS: [(&str, &str); 1] = [("busy_timeout", "0")];
+
 impl StorageConnection for rusqlite::Connection {
-    fn new_transaction(&mut self) -> Result<Box<dyn StorageTransaction + '_>> {
-        Ok(Box::new(self.transaction()?))
+    fn new_transaction<'a>(
+        &'a mut self,
+        settings: &'a Settings,
+    ) -> Result<Box<dyn StorageTransaction + 'a>> {
+        Ok(Box::new(SqliteTransaction::new(
+            self.transaction()?,
+            settings,
+        )))
+    }
+
+    fn ping(&mut self) -> Result<()> {
+        self.query_row("SELECT 1", [], |_| Ok(()))?;
+        Ok(())
+    }
+
+    // Without WAL this does nothing and SQLite reports -1 frames.
+    fn checkpoint(&mut self, mode: CheckpointMode) -> Result<Checkpoint> {
+        let sql = format!("PRAGMA wal_checkpoint({})", mode.sql());
+        let (busy, wal_frames, checkpointed_frames): (i64, i64, i64) =
+            self.query_row(&sql, [], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+        let frames = |count: i64| count.max(0) as u64;
+        Ok(Checkpoint {
+            busy: busy != 0,
+            wal_frames: frames(wal_frames),
+            checkpointed_frames: frames(checkpointed_frames),
+        })
+    }
+
+    fn set_auto_checkpoint(&mut self, pages: u32) -> Result<()> {
+        self.pragma_update(None, "wal_autocheckpoint", pages)?;
+        Ok(())
+    }
+
+    // The WAL file next to the database; in-memory databases have none.
+    fn wal_size(&mut self) -> Result<u64> {
+        let Some(path) = self.path().filter(|path| !path.is_empty()) else {
+            return Ok(0);
+        };
+        match std::fs::metadata(format!("{}-wal", path)) {
+            Ok(metadata) => Ok(metadata.len()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(Error::Storage(Box::new(e))),
+        }
+    }
+
+    fn set_statement_cache_capacity(&mut self, capacity: usize) {
+        self.set_prepared_statement_cache_capacity(capacity);
+    }
+
+    // Folds the WAL back into the database and truncates it, so a restart
+    // doesn't find it hot.
+    fn prepare_shutdown(&mut self) -> Result<()> {
+        self.checkpoint(CheckpointMode::Truncate)?;
+        Ok(())
+    }
+
+    fn close(mut self: Box<Self>) -> Result<()> {
+        self.prepare_shutdown()?;
+        (*self).close().map_err(|(_, e)| e.into())
+    }
+
+    fn replace_pragmas(
+        &mut self,
+        pragmas: &[(&'static str, &str)],
+    ) -> Result<Vec<(&'static str, String)>> {
+        let mut previous = Vec::new();
+        for &(name, value) in pragmas {
+            let old = self.pragma_query_value(None, name, |row| {
+                Ok(match row.get_ref(0)? {
+                    ValueRef::Integer(i) => i.to_string(),
+                    value => value.as_str()?.to_string(),
+                })
+            })?;
+            // Switching a WAL database away from WAL needs every other
+            // connection closed, and it already writes cheaply.
+            if name == "journal_mode" && old.eq_ignore_ascii_case("wal") {
+                continue;
+            }
+            if let Err(e) = self.pragma_update(None, name, value) {
+                let _ = self.restore_pragmas(previous);
+                return Err(e.into());
+            }
+            previous.push((name, old));
+        }
+        Ok(previous)
+    }
+
+    fn restore_pragmas(&mut self, previous: Vec<(&'static str, String)>) -> Result<()> {
+        for (name, value) in previous.into_iter().rev() {
+            self.pragma_update(None, name, value)?;
+        }
+        Ok(())
+    }
+
+    // SQLite doesn't report other connections' locks, so probe for them:
+    // taking the write lock fails under a writer, reading fails under a commit.
+    fn lock_state(&mut self) -> Result<LockState> {
+        let previous = self.replace_pragmas(&NO_WAIT_PRAGMAS)?;
+        let probe = || -> Result<LockState> {
+            match self.execute_batch("BEGIN IMMEDIATE") {
+                Ok(()) => {
+                    self.execute_batch("ROLLBACK")?;
+                    return Ok(LockState::Unlocked);
+                }
+                Err(e) => match Error::from(e) {
+                    Error::LockConflict => {}
+                    e => return Err(e),
+                },
+            }
+            match self.query_row("SELECT COUNT(*) FROM sqlite_master", [], |_| Ok(())) {
+                Ok(()) => Ok(LockState::Reserved),
+                Err(e) => match Error::from(e) {
+                    Error::LockConflict => Ok(LockState::Exclusive),
+                    e => Err(e),
+                },
+            }
+        };
+        let result = probe();
+        self.restore_pragmas(previous)?;
+        result
     }
 }
 
@@ -18,22 +293,550 @@ impl StorageConnection for rusqlite::Connection {
 
 pub struct Connection {
     inner: Box<dyn StorageConnection>,
+    schemas: Vec<&'static Schema>,
+    settings: Settings,
 }
 
 impl Connection {
+    fn new(inner: Box<dyn StorageConnection>) -> Self {
+        Self {
+            inner,
+            schemas: Vec::new(),
+            settings: Settings::default(),
+        }
+    }
+
     pub fn open_sqlite_file<P: AsRef<Path>>(path: P) -> Result<Self> {
-        Ok(Self {
-            inner: Box::new(rusqlite::Connection::open(path)?),
-        })
+        let conn = rusqlite::Connection::open(path)?;
+        Ok(Self::new(Box::new(storage::open_sqlite(conn))))
     }
 
     pub fn open_in_memory() -> Result<Self> {
-        Ok(Self {
-            inner: Box::new(rusqlite::Connection::open_in_memory()?),
-        })
+        let conn = rusqlite::Connection::open_in_memory()?;
+        Ok(Self::new(Box::new(storage::open_sqlite(conn))))
+    }
+
+    pub fn connect_remote<A: ToSocketAddrs>(addr: A) -> Result<Self> {
+        Ok(Self::new(Box::new(RemoteConnection::connect(addr)?)))
     }
 
     pub fn new_transaction(&mut self) -> Result<Transaction<'_>> {
-        Ok(Transaction::new(self.inner.new_transaction()?))
+        Ok(Transaction::new(
+            self.inner.new_transaction(&self.settings)?,
+            &self.settings,
+        ))
+    }
+
+    // How many compiled statements SQLite connections keep, see
+    // storage::STATEMENT_CACHE_CAPACITY for the default.
+    pub fn set_statement_cache_capacity(&mut self, capacity: usize) -> &mut Self {
+        self.inner.set_statement_cache_capacity(capacity);
+        self
+    }
+
+    pub fn set_debug_sql(&mut self, enabled: bool) -> &mut Self {
+        self.settings.debug_sql = enabled;
+        self
+    }
+
+    pub fn set_error_context(&mut self, enabled: bool) -> &mut Self {
+        self.settings.error_context = enabled;
+        self
+    }
+
+    pub fn set_max_objects(&mut self, limit: Option<usize>) -> &mut Self {
+        self.settings.max_objects = limit;
+        self
+    }
+
+    pub fn set_coercion(&mut self, coercion: Coercion) -> &mut Self {
+        self.settings.coercion = coercion;
+        self
+    }
+
+    pub fn set_text_decoding(&mut self, decoding: TextDecoding) -> &mut Self {
+        self.settings.text_decoding = decoding;
+        self
+    }
+
+    pub fn set_hydrate_missing_columns(&mut self, enabled: bool) -> &mut Self {
+        self.settings.hydrate_missing_columns = enabled;
+        self
+    }
+
+    // Tables missing columns of their schema get them added on first use in a
+    // transaction, see Transaction::ensure_table.
+    pub fn set_auto_migrate(&mut self, enabled: bool) -> &mut Self {
+        self.settings.auto_migrate = enabled;
+        self
+    }
+
+    pub fn set_strict_tables(&mut self, enabled: bool) -> &mut Self {
+        self.settings.strict_tables = enabled;
+        self
+    }
+
+    pub fn set_quota<T: Object>(&mut self, quota: Option<Quota>) -> &mut Self {
+        match quota {
+            Some(quota) => self.settings.quotas.insert(T::schema().table_name, quota),
+            None => self.settings.quotas.remove(T::schema().table_name),
+        };
+        self
+    }
+
+    pub fn restrict<T: Object>(&mut self, access: Access) -> &mut Self {
+        match access {
+            Access::ReadWrite => self.settings.access.remove(T::schema().table_name),
+            access => self.settings.access.insert(T::schema().table_name, access),
+        };
+        self
+    }
+
+    // Filters ANDed into every query of T by this connection's transactions,
+    // e.g. to only show rows of the current user. Rows that don't match can't
+    // be loaded, so they can't be updated or deleted either.
+    pub fn set_row_filter<T: Object, F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn() -> Vec<Filter> + Send + 'static,
+    {
+        self.settings
+            .row_filters
+            .insert(T::schema().table_name, Box::new(f));
+        self
+    }
+
+    pub fn clear_row_filter<T: Object>(&mut self) -> &mut Self {
+        self.settings.row_filters.remove(T::schema().table_name);
+        self
+    }
+
+    pub fn on_quota_exceeded<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(&QuotaError) -> QuotaAction + Send + 'static,
+    {
+        self.settings.quota_policy = Some(Box::new(f));
+        self
+    }
+
+    pub fn set_cipher<C: ColumnCipher + 'static>(&mut self, cipher: C) -> &mut Self {
+        self.settings.ciphers = vec![Box::new(cipher)];
+        self
+    }
+
+    // The new cipher encrypts everything written from now on; the previous
+    // ones are kept to decrypt rows until `reencrypt` has rewritten them.
+    pub fn rotate_cipher<C: ColumnCipher + 'static>(&mut self, cipher: C) -> &mut Self {
+        self.settings.ciphers.insert(0, Box::new(cipher));
+        self
+    }
+
+    pub fn retire_old_ciphers(&mut self) -> &mut Self {
+        self.settings.ciphers.truncate(1);
+        self
+    }
+
+    pub fn after_commit<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(&[ObjectChange]) + Send + 'static,
+    {
+        self.settings.after_commit.push(Box::new(f));
+        self
+    }
+
+    // Reports transactions of this connection that stay open, or hold the
+    // write lock, longer than the watchdog allows.
+    pub fn set_watchdog<F>(&mut self, watchdog: Watchdog, f: F) -> &mut Self
+    where
+        F: Fn(&LongTransaction) + Send + Sync + 'static,
+    {
+        self.settings.watchdog = Some(Monitor::new(watchdog, Box::new(f)));
+        self
+    }
+
+    pub fn clear_watchdog(&mut self) -> &mut Self {
+        self.settings.watchdog = None;
+        self
+    }
+
+    pub fn stats_of<T: Object>(&self) -> TableStats {
+        self.settings
+            .stats
+            .borrow()
+            .get(T::schema())
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn stats(&self) -> Vec<(&'static str, TableStats)> {
+        let mut stats = self
+            .settings
+            .stats
+            .borrow()
+            .iter()
+            .map(|(schema, stats)| (schema.type_name, *stats))
+            .collect::<Vec<_>>();
+        stats.sort_by_key(|(type_name, _)| *type_name);
+        stats
+    }
+
+    pub fn reset_stats(&mut self) -> &mut Self {
+        self.settings.stats.borrow_mut().clear();
+        self.settings.queries.borrow_mut().clear();
+        self
+    }
+
+    // Meant for development: queries are only recorded while logging is on.
+    pub fn set_query_log(&mut self, enabled: bool) -> &mut Self {
+        self.settings.log_queries = enabled;
+        self
+    }
+
+    pub fn suggest_indexes(&self, min_queries: u64) -> Vec<IndexSuggestion> {
+        advisor::suggest_indexes(&self.settings.queries.borrow(), min_queries)
+    }
+
+    pub fn lock_state(&mut self) -> Result<LockState> {
+        self.inner.lock_state()
+    }
+
+    pub fn ping(&mut self) -> Result<()> {
+        self.inner.ping()
+    }
+
+    pub fn checkpoint(&mut self, mode: CheckpointMode) -> Result<Checkpoint> {
+        self.inner.checkpoint(mode)
+    }
+
+    pub(crate) fn prepare_shutdown(&mut self) -> Result<()> {
+        self.inner.prepare_shutdown()
+    }
+
+    // SQLite checkpoints on commit once the WAL holds this many pages, 1000 by
+    // default. Zero turns that off, leaving it to `checkpoint`.
+    pub fn set_auto_checkpoint(&mut self, pages: u32) -> Result<()> {
+        self.inner.set_auto_checkpoint(pages)
+    }
+
+    // In bytes. Checkpoints only shrink the file in Truncate mode; otherwise
+    // it's reused from the start once readers have moved past it.
+    pub fn wal_size(&mut self) -> Result<u64> {
+        self.inner.wal_size()
+    }
+
+    // Unlike dropping the connection, reports what went wrong closing it.
+    pub fn close(self) -> Result<()> {
+        self.inner.close()
+    }
+
+    fn with_pragmas<R, F>(&mut self, pragmas: &[(&'static str, &str)], f: F) -> Result<R>
+    where
+        F: FnOnce(&mut Self) -> Result<R>,
+    {
+        let previous = self.inner.replace_pragmas(pragmas)?;
+        let result = f(self);
+        let restored = self.inner.restore_pragmas(previous);
+        let result = result?;
+        restored?;
+        Ok(result)
+    }
+
+    pub fn bulk_import<R, F>(&mut self, f: F) -> Result<R>
+    where
+        F: FnOnce(&Transaction<'_>) -> Result<R>,
+    {
+        self.with_pragmas(&BULK_PRAGMAS, |conn| {
+            let tx = conn.new_transaction()?;
+            match f(&tx) {
+                Ok(result) => {
+                    tx.commit()?;
+                    Ok(result)
+                }
+                Err(e) => {
+                    tx.rollback()?;
+                    Err(e)
+                }
+            }
+        })
+    }
+
+    pub fn chunked_transaction<I, F>(&mut self, batch_size: usize, items: I, f: F) -> Result<usize>
+    where
+        I: IntoIterator,
+        F: FnMut(&Transaction<'_>, I::Item) -> Result<()>,
+    {
+        self.chunked_transaction_with_progress(batch_size, items, f, |_, _| {})
+    }
+
+    // Like chunked_transaction, calling `progress` after each chunk commits
+    // with the number of items committed so far and the size of that chunk.
+    pub fn chunked_transaction_with_progress<I, F, P>(
+        &mut self,
+        batch_size: usize,
+        items: I,
+        mut f: F,
+        mut progress: P,
+    ) -> Result<usize>
+    where
+        I: IntoIterator,
+        F: FnMut(&Transaction<'_>, I::Item) -> Result<()>,
+        P: FnMut(usize, usize),
+    {
+        assert!(batch_size != 0, "batch size must be non-zero");
+        let mut items = items.into_iter().peekable();
+        let mut committed = 0;
+        while items.peek().is_some() {
+            let result = (|| {
+                let tx = self.new_transaction()?;
+                let mut count = 0;
+                for item in items.by_ref().take(batch_size) {
+                    if let Err(e) = f(&tx, item) {
+                        tx.rollback()?;
+                        return Err(e);
+                    }
+                    count += 1;
+                }
+                tx.commit()?;
+                Ok(count)
+            })();
+            match result {
+                Ok(count) => {
+                    committed += count;
+                    progress(committed, count);
+                }
+                Err(source) => {
+                    return Err(Error::Chunked(Box::new(ChunkedError { committed, source })))
+                }
+            }
+        }
+        Ok(committed)
+    }
+
+    pub fn without_foreign_keys<R, F>(&mut self, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut Self) -> Result<R>,
+    {
+        self.with_pragmas(&NO_FOREIGN_KEYS_PRAGMAS, f)
+    }
+
+    pub fn purge_expired<T: Expiring>(&mut self) -> Result<usize> {
+        self.settings.check_access(T::schema(), Operation::Delete)?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs() as i64);
+        let mut purged = 0;
+        loop {
+            let tx = self.inner.new_transaction(&self.settings)?;
+            if !tx.table_exists(T::schema().table_name)? {
+                tx.rollback()?;
+                return Ok(purged);
+            }
+            let deleted =
+                tx.delete_expired(T::schema(), T::EXPIRES_COLUMN, now, PURGE_BATCH_SIZE)?;
+            tx.commit()?;
+            self.settings
+                .record(T::schema(), |stats| stats.deletes += deleted as u64);
+            purged += deleted;
+            if deleted < PURGE_BATCH_SIZE {
+                return Ok(purged);
+            }
+        }
+    }
+
+    pub fn reencrypt<T: Object>(&mut self) -> Result<usize> {
+        let schema = T::schema();
+        if !schema.columns.iter().any(|column| column.encrypted) {
+            return Ok(0);
+        }
+        self.settings.check_access(schema, Operation::Update)?;
+        let mut after = None;
+        let mut rewritten = 0;
+        loop {
+            let tx = self.inner.new_transaction(&self.settings)?;
+            if !tx.table_exists(schema.table_name)? {
+                tx.rollback()?;
+                return Ok(rewritten);
+            }
+            let rows = tx.select_page(schema, after, REENCRYPT_BATCH_SIZE)?;
+            for (id, row) in &rows {
+                let row = blob::load_blobs(tx.as_ref(), *id, schema, row.clone())?;
+                let row = cipher::decrypt_row(&self.settings.ciphers, schema, row)?;
+                let row = cipher::encrypt_row(&self.settings.ciphers, schema, row)?;
+                let row = blob::store_blobs(tx.as_ref(), schema, row)?;
+                tx.update_row(*id, schema, &row)?;
+            }
+            tx.commit()?;
+            self.settings
+                .record(schema, |stats| stats.updates += rows.len() as u64);
+            rewritten += rows.len();
+            if rows.len() < REENCRYPT_BATCH_SIZE {
+                return Ok(rewritten);
+            }
+            after = rows.last().map(|(id, _)| *id);
+        }
+    }
+
+    // Blobs referenced only from tables missing in `schemas` are deleted too.
+    pub fn purge_unreferenced_blobs(&mut self, schemas: &[&'static Schema]) -> Result<usize> {
+        let tx = self.inner.new_transaction(&self.settings)?;
+        if !tx.table_exists(BLOBS_TABLE)? {
+            tx.rollback()?;
+            return Ok(0);
+        }
+        let references = blob_references(&*tx, schemas.iter().copied())?;
+        let deleted = tx.delete_unreferenced_blobs(&references)?;
+        tx.commit()?;
+        Ok(deleted)
+    }
+
+    pub fn next_in_sequence(&mut self, name: &str) -> Result<i64> {
+        let tx = self.inner.new_transaction(&self.settings)?;
+        let value = tx.next_in_sequence(name)?;
+        tx.commit()?;
+        Ok(value)
+    }
+
+    pub fn register<T: Object>(&mut self) -> &mut Self {
+        if !self.schemas.contains(&T::schema()) {
+            self.schemas.push(T::schema());
+        }
+        self.settings
+            .loaders
+            .insert(T::schema().table_name, transaction::load_any::<T>);
+        self
+    }
+
+    pub fn diff_schemas(&mut self, schemas: &[&'static Schema]) -> Result<Vec<SchemaChange>> {
+        let tx = self.inner.new_transaction(&self.settings)?;
+        let mut changes = Vec::new();
+        for schema in schemas {
+            changes.extend(schema_changes(tx.as_ref(), schema)?);
+        }
+        tx.rollback()?;
+
+        changes.sort_by_key(|change| (change.destructive, change.kind));
+        Ok(changes)
+    }
+
+    // Compares the stored table with the schema without changing anything,
+    // so drift is found up front rather than by a failing query.
+    pub fn validate_schema<T: Object>(&mut self) -> Result<SchemaDrift> {
+        let mut drifts = self.validate_all(&[T::schema()])?;
+        Ok(drifts.remove(0))
+    }
+
+    // One entry per schema, in order.
+    pub fn validate_all(&mut self, schemas: &[&'static Schema]) -> Result<Vec<SchemaDrift>> {
+        let tx = self.inner.new_transaction(&self.settings)?;
+        let mut drifts = Vec::new();
+        for schema in schemas {
+            let mut existing = HashMap::new();
+            for table in std::iter::once(schema.table_name).chain(schema.split_tables()) {
+                if tx.table_exists(table)? {
+                    existing.insert(table, tx.table_columns(table)?);
+                }
+            }
+            drifts.push(ddl::schema_drift(schema, &existing));
+        }
+        tx.rollback()?;
+        Ok(drifts)
+    }
+
+    // Takes over a table created elsewhere without the `id` column, see
+    // ChangeKind::AdoptTable. Columns the schema doesn't know are dropped.
+    // Returns false if there's nothing to adopt.
+    pub fn adopt_table<T: Object>(&mut self) -> Result<bool> {
+        let schema = T::schema();
+        let tx = self.inner.new_transaction(&self.settings)?;
+        if !tx.table_exists(schema.table_name)? {
+            tx.rollback()?;
+            return Ok(false);
+        }
+        let existing = tx.table_columns(schema.table_name)?;
+        let adoption = ddl::diff_table(schema, Some(&existing))
+            .into_iter()
+            .find(|change| change.kind == ChangeKind::AdoptTable);
+        let Some(adoption) = adoption else {
+            tx.rollback()?;
+            return Ok(false);
+        };
+        tx.execute_ddl(&adoption.statements)?;
+        tx.commit()?;
+        Ok(true)
+    }
+
+    // Applies the migrations not recorded yet, see Migrator.
+    pub fn migrate(&mut self, migrations: &[&dyn Migration]) -> Result<Vec<i64>> {
+        Migrator::new(migrations).run(self)
+    }
+
+    pub fn generate_objects(&mut self) -> Result<String> {
+        let tx = self.inner.new_transaction(&self.settings)?;
+        let mut structs = Vec::new();
+        for table in tx.table_names()? {
+            let columns = tx.table_columns(&table)?;
+            structs.push(codegen::generate_struct(&table, &columns));
+        }
+        tx.rollback()?;
+        Ok(structs.join("\n"))
+    }
+
+    pub fn dump_ddl(&self) -> Vec<String> {
+        self.schemas
+            .iter()
+            .flat_map(|&schema| {
+                Schema {
+                    strict: schema.strict || self.settings.strict_tables,
+                    ..*schema
+                }
+                .create_statements(Dialect::Sqlite)
+            })
+            .collect()
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// The (table, column) pairs of existing tables that may reference stored blobs.
+pub(crate) fn blob_references(
+    tx: &dyn StorageTransaction,
+    schemas: impl IntoIterator<Item = &'static Schema>,
+) -> Result<Vec<(&'static str, &'static str)>> {
+    let mut references = Vec::new();
+    for schema in schemas {
+        for column in schema.columns.iter().filter(|column| column.external_blob) {
+            let table = schema.table_of(column);
+            if tx.table_exists(table)? {
+                references.push((table, column.name));
+            }
+        }
+    }
+    Ok(references)
+}
+
+pub(crate) fn schema_changes(
+    tx: &dyn StorageTransaction,
+    schema: &Schema,
+) -> Result<Vec<SchemaChange>> {
+    let existing = if tx.table_exists(schema.table_name)? {
+        Some(tx.table_columns(schema.table_name)?)
+    } else {
+        None
+    };
+    let mut changes = Vec::new();
+    if existing.is_some() {
+        let splits = schema.split_tables().into_iter();
+        for (table, sql) in splits.zip(schema.create_split_tables_sql(Dialect::Sqlite)) {
+            if !tx.table_exists(table)? {
+                changes.push(SchemaChange {
+                    kind: ChangeKind::CreateTable,
+                    table_name: table.to_string(),
+                    column_name: None,
+                    statements: vec![sql],
+                    destructive: false,
+                });
+            }
+        }
     }
+    changes.extend(ddl::diff_table(schema, existing.as_deref()));
+    Ok(changes)
 }
@@ -1,14 +1,17 @@
 #![forbid(unsafe_code)]
 
 use std::fmt::{Display, Formatter};
+use std::str::Utf8Error;
 use std::{borrow::Cow, fmt};
 
-use rusqlite::types::{FromSql, ToSqlOutput};
+use rusqlite::types::{FromSql, FromSqlError, ToSqlOutput, ValueRef};
 use rusqlite::ToSql;
 
+use crate::format::TextFormat;
+
 ////////////////////////////////////////////////////////////////////////////////
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
 pub struct ObjectId(i64);
 
 impl ToSql for ObjectId {
@@ -70,6 +73,8 @@ impl DataType {
 
 pub trait DetectDataType {
     const TYPE: DataType;
+    const NULLABLE: bool = false;
+    const FORMAT: Option<TextFormat> = None;
 }
 
 macro_rules! impl_detect_data_type {
@@ -86,6 +91,12 @@ impl_detect_data_type!(i64, Int64);
 impl_detect_data_type!(f64, Float64);
 impl_detect_data_type!(bool, Bool);
 
+impl<T: DetectDataType> DetectDataType for Option<T> {
+    const TYPE: DataType = T::TYPE;
+    const NULLABLE: bool = true;
+    const FORMAT: Option<TextFormat> = T::FORMAT;
+}
+
 impl Display for DataType {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.name())
@@ -94,6 +105,106 @@ impl Display for DataType {
 
 ////////////////////////////////////////////////////////////////////////////////
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Coercion {
+    #[default]
+    Strict,
+    Lenient,
+}
+
+fn parse_bool(text: &str) -> Option<bool> {
+    match text.trim().to_ascii_lowercase().as_str() {
+        "1" | "true" | "t" | "yes" | "y" | "on" => Some(true),
+        "0" | "false" | "f" | "no" | "n" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+// How a bool field is laid out in a legacy column. Such columns are nullable
+// and NULL reads as false.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BoolEncoding {
+    Integer,
+    // 'Y' and 'N'.
+    TextYn,
+    // 'true' and 'false'.
+    TextTrueFalse,
+}
+
+impl BoolEncoding {
+    pub fn stored_type(&self) -> DataType {
+        match self {
+            BoolEncoding::Integer => DataType::Bool,
+            BoolEncoding::TextYn | BoolEncoding::TextTrueFalse => DataType::String,
+        }
+    }
+
+    pub fn encode(&self, value: bool) -> Value<'static> {
+        match self {
+            BoolEncoding::Integer => Value::Bool(value),
+            _ => Value::String(Cow::Borrowed(self.text(value))),
+        }
+    }
+
+    pub fn decode(&self, value: &Value<'_>) -> Option<bool> {
+        match (self, value) {
+            (_, Value::Null) => Some(false),
+            (BoolEncoding::Integer, Value::Bool(value)) => Some(*value),
+            (BoolEncoding::TextYn | BoolEncoding::TextTrueFalse, Value::String(text)) => {
+                [true, false]
+                    .into_iter()
+                    .find(|value| text == self.text(*value))
+            }
+            _ => None,
+        }
+    }
+
+    // SQL literal of the encoded value, used in filters.
+    pub fn sql_literal(&self, value: bool) -> &'static str {
+        match (self, value) {
+            (BoolEncoding::Integer, true) => "1",
+            (BoolEncoding::Integer, false) => "0",
+            (BoolEncoding::TextYn, true) => "'Y'",
+            (BoolEncoding::TextYn, false) => "'N'",
+            (BoolEncoding::TextTrueFalse, true) => "'true'",
+            (BoolEncoding::TextTrueFalse, false) => "'false'",
+        }
+    }
+
+    fn text(&self, value: bool) -> &'static str {
+        match (self, value) {
+            (BoolEncoding::TextYn, true) => "Y",
+            (BoolEncoding::TextYn, false) => "N",
+            (_, true) => "true",
+            (_, false) => "false",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TextDecoding {
+    #[default]
+    Strict,
+    Lossy,
+    // Text that isn't valid UTF-8 is assumed to be ISO-8859-1.
+    Latin1Fallback,
+}
+
+impl TextDecoding {
+    pub fn decode<'a>(&self, bytes: &'a [u8]) -> Result<Cow<'a, str>, Utf8Error> {
+        match (self, std::str::from_utf8(bytes)) {
+            (_, Ok(text)) => Ok(Cow::Borrowed(text)),
+            (TextDecoding::Strict, Err(e)) => Err(e),
+            (TextDecoding::Lossy, Err(_)) => Ok(String::from_utf8_lossy(bytes)),
+            (TextDecoding::Latin1Fallback, Err(_)) => {
+                Ok(Cow::Owned(bytes.iter().map(|&byte| byte as char).collect()))
+            }
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum Value<'a> {
     String(Cow<'a, str>),
@@ -101,12 +212,62 @@ pub enum Value<'a> {
     Int64(i64),
     Float64(f64),
     Bool(bool),
+    Null,
 }
 
 pub trait ValueConvert: Sized {
     fn to_value(&self) -> Value<'static>;
 
+    fn to_value_ref(&self) -> Value<'_> {
+        self.to_value()
+    }
+
     fn from_value(value: &Value<'_>) -> Self;
+
+    // Like from_value, but reports values that don't convert instead of
+    // panicking. Conversions that can't fail only need from_value.
+    fn try_from_value(value: &Value<'_>) -> Result<Self, ConversionError> {
+        Ok(Self::from_value(value))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConversionError {
+    pub expected: String,
+    pub got: String,
+    // Which of the columns of a multi-column field, such as the currency of
+    // a Money amount, holds the bad value.
+    pub offset: usize,
+}
+
+impl ConversionError {
+    pub fn new(expected: impl Into<String>, got: impl Into<String>) -> Self {
+        Self {
+            expected: expected.into(),
+            got: got.into(),
+            offset: 0,
+        }
+    }
+
+    pub fn unexpected(expected: DataType, value: &Value<'_>) -> Self {
+        Self::new(format!("{:?}", expected), value.sql_type())
+    }
+
+    pub fn at_offset(self, offset: usize) -> Self {
+        Self { offset, ..self }
+    }
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "expected {}, got {}", self.expected, self.got)
+    }
+}
+
+impl std::error::Error for ConversionError {}
+
+pub(crate) fn converted<T>(result: Result<T, ConversionError>) -> T {
+    result.unwrap_or_else(|e| panic!("Invalid value type: {}", e))
 }
 
 impl ValueConvert for i64 {
@@ -115,9 +276,13 @@ impl ValueConvert for i64 {
     }
 
     fn from_value(value: &Value<'_>) -> Self {
+        converted(Self::try_from_value(value))
+    }
+
+    fn try_from_value(value: &Value<'_>) -> Result<Self, ConversionError> {
         match value {
-            Value::Int64(i) => *i,
-            _ => panic!("Invalid value type"),
+            Value::Int64(i) => Ok(*i),
+            _ => Err(ConversionError::unexpected(DataType::Int64, value)),
         }
     }
 }
@@ -128,9 +293,13 @@ impl ValueConvert for f64 {
     }
 
     fn from_value(value: &Value<'_>) -> Self {
+        converted(Self::try_from_value(value))
+    }
+
+    fn try_from_value(value: &Value<'_>) -> Result<Self, ConversionError> {
         match value {
-            Value::Float64(f) => *f,
-            _ => panic!("Invalid value type"),
+            Value::Float64(f) => Ok(*f),
+            _ => Err(ConversionError::unexpected(DataType::Float64, value)),
         }
     }
 }
@@ -141,9 +310,13 @@ impl ValueConvert for bool {
     }
 
     fn from_value(value: &Value<'_>) -> Self {
+        converted(Self::try_from_value(value))
+    }
+
+    fn try_from_value(value: &Value<'_>) -> Result<Self, ConversionError> {
         match value {
-            Value::Bool(b) => *b,
-            _ => panic!("Invalid value type"),
+            Value::Bool(b) => Ok(*b),
+            _ => Err(ConversionError::unexpected(DataType::Bool, value)),
         }
     }
 }
@@ -153,10 +326,18 @@ impl ValueConvert for String {
         Value::String(Cow::Owned(self.clone()))
     }
 
+    fn to_value_ref(&self) -> Value<'_> {
+        Value::String(Cow::Borrowed(self))
+    }
+
     fn from_value(value: &Value<'_>) -> Self {
+        converted(Self::try_from_value(value))
+    }
+
+    fn try_from_value(value: &Value<'_>) -> Result<Self, ConversionError> {
         match value {
-            Value::String(s) => s.to_string(),
-            _ => panic!("Invalid value type"),
+            Value::String(s) => Ok(s.to_string()),
+            _ => Err(ConversionError::unexpected(DataType::String, value)),
         }
     }
 }
@@ -166,10 +347,100 @@ impl ValueConvert for Vec<u8> {
         Value::Bytes(Cow::Owned(self.clone()))
     }
 
+    fn to_value_ref(&self) -> Value<'_> {
+        Value::Bytes(Cow::Borrowed(self))
+    }
+
+    fn from_value(value: &Value<'_>) -> Self {
+        converted(Self::try_from_value(value))
+    }
+
+    fn try_from_value(value: &Value<'_>) -> Result<Self, ConversionError> {
+        match value {
+            Value::Bytes(b) => Ok(b.to_vec()),
+            _ => Err(ConversionError::unexpected(DataType::Bytes, value)),
+        }
+    }
+}
+
+impl<T: ValueConvert> ValueConvert for Option<T> {
+    fn to_value(&self) -> Value<'static> {
+        match self {
+            Some(v) => v.to_value(),
+            None => Value::Null,
+        }
+    }
+
+    fn to_value_ref(&self) -> Value<'_> {
+        match self {
+            Some(v) => v.to_value_ref(),
+            None => Value::Null,
+        }
+    }
+
     fn from_value(value: &Value<'_>) -> Self {
         match value {
-            Value::Bytes(b) => b.to_vec(),
-            _ => panic!("Invalid value type"),
+            Value::Null => None,
+            v => Some(T::from_value(v)),
+        }
+    }
+
+    fn try_from_value(value: &Value<'_>) -> Result<Self, ConversionError> {
+        match value {
+            Value::Null => Ok(None),
+            v => T::try_from_value(v).map(Some),
+        }
+    }
+}
+
+// Conversion for fields of object views, which may borrow text and bytes
+// straight from the row instead of copying them.
+pub trait ViewConvert<'a>: Sized {
+    fn try_from_view_value(value: Value<'a>) -> Result<Self, ConversionError>;
+
+    fn from_view_value(value: Value<'a>) -> Self {
+        converted(Self::try_from_view_value(value))
+    }
+}
+
+impl<'a, T: ValueConvert> ViewConvert<'a> for T {
+    fn try_from_view_value(value: Value<'a>) -> Result<Self, ConversionError> {
+        T::try_from_value(&value)
+    }
+}
+
+impl<'a> ViewConvert<'a> for Cow<'a, str> {
+    fn try_from_view_value(value: Value<'a>) -> Result<Self, ConversionError> {
+        match value {
+            Value::String(s) => Ok(s),
+            v => Err(ConversionError::unexpected(DataType::String, &v)),
+        }
+    }
+}
+
+impl<'a> ViewConvert<'a> for Cow<'a, [u8]> {
+    fn try_from_view_value(value: Value<'a>) -> Result<Self, ConversionError> {
+        match value {
+            Value::Bytes(b) => Ok(b),
+            v => Err(ConversionError::unexpected(DataType::Bytes, &v)),
+        }
+    }
+}
+
+impl<'a> ViewConvert<'a> for Option<Cow<'a, str>> {
+    fn try_from_view_value(value: Value<'a>) -> Result<Self, ConversionError> {
+        match value {
+            Value::Null => Ok(None),
+            v => Cow::try_from_view_value(v).map(Some),
+        }
+    }
+}
+
+impl<'a> ViewConvert<'a> for Option<Cow<'a, [u8]>> {
+    fn try_from_view_value(value: Value<'a>) -> Result<Self, ConversionError> {
+        match value {
+            Value::Null => Ok(None),
+            v => Cow::try_from_view_value(v).map(Some),
         }
     }
 }
@@ -182,6 +453,7 @@ impl<'a> ToSql for Value<'a> {
             Value::Int64(i) => Ok(ToSqlOutput::from(*i)),
             Value::Float64(f) => Ok(ToSqlOutput::from(*f)),
             Value::Bool(b) => Ok(ToSqlOutput::from(*b)),
+            Value::Null => Ok(ToSqlOutput::from(rusqlite::types::Null)),
         }
     }
 }
@@ -189,38 +461,84 @@ impl<'a> ToSql for Value<'a> {
 impl<'a> FromSql for Value<'a> {
     fn column_result(value: rusqlite::types::ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
         match value {
-            rusqlite::types::ValueRef::Text(s) => {
-                Ok(String::from_utf8(s.to_vec()).unwrap().to_value())
-            }
+            rusqlite::types::ValueRef::Text(s) => String::from_utf8(s.to_vec())
+                .map(|s| s.to_value())
+                .map_err(|e| FromSqlError::Other(Box::new(e))),
             rusqlite::types::ValueRef::Blob(b) => Ok(b.to_vec().to_value()),
             rusqlite::types::ValueRef::Integer(i) => Ok(i.to_value()),
             rusqlite::types::ValueRef::Real(f) => Ok(f.to_value()),
-            rusqlite::types::ValueRef::Null => Err(rusqlite::types::FromSqlError::InvalidType),
+            rusqlite::types::ValueRef::Null => Ok(Value::Null),
         }
     }
 }
 
 impl<'a> Value<'a> {
-    pub fn data_type(&self) -> DataType {
+    pub(crate) fn borrow_sql(
+        value: ValueRef<'a>,
+        decoding: TextDecoding,
+    ) -> Result<Self, Utf8Error> {
+        match value {
+            ValueRef::Text(s) => decoding.decode(s).map(Value::String),
+            ValueRef::Blob(b) => Ok(Value::Bytes(Cow::Borrowed(b))),
+            ValueRef::Integer(i) => Ok(Value::Int64(i)),
+            ValueRef::Real(f) => Ok(Value::Float64(f)),
+            ValueRef::Null => Ok(Value::Null),
+        }
+    }
+
+    pub fn data_type(&self) -> Option<DataType> {
+        match self {
+            Value::String(_) => Some(DataType::String),
+            Value::Bytes(_) => Some(DataType::Bytes),
+            Value::Int64(_) => Some(DataType::Int64),
+            Value::Float64(_) => Some(DataType::Float64),
+            Value::Bool(_) => Some(DataType::Bool),
+            Value::Null => None,
+        }
+    }
+
+    pub fn into_owned(self) -> Value<'static> {
         match self {
-            Value::String(_) => DataType::String,
-            Value::Bytes(_) => DataType::Bytes,
-            Value::Int64(_) => DataType::Int64,
-            Value::Float64(_) => DataType::Float64,
-            Value::Bool(_) => DataType::Bool,
+            Value::String(s) => Value::String(Cow::Owned(s.into_owned())),
+            Value::Bytes(b) => Value::Bytes(Cow::Owned(b.into_owned())),
+            Value::Int64(i) => Value::Int64(i),
+            Value::Float64(f) => Value::Float64(f),
+            Value::Bool(b) => Value::Bool(b),
+            Value::Null => Value::Null,
         }
     }
 
+    pub fn coerce(&self, ty: DataType) -> Option<Value<'static>> {
+        Some(match (ty, self) {
+            (DataType::Float64, Value::Int64(i)) => Value::Float64(*i as f64),
+            (DataType::Float64, Value::String(s)) => Value::Float64(s.trim().parse().ok()?),
+            (DataType::Int64, Value::String(s)) => Value::Int64(s.trim().parse().ok()?),
+            (DataType::Bool, Value::Int64(i)) => Value::Bool(*i != 0),
+            (DataType::Bool, Value::Float64(f)) => Value::Bool(*f != 0.0),
+            (DataType::Bool, Value::String(s)) => Value::Bool(parse_bool(s)?),
+            _ => return None,
+        })
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
     pub fn sql_type(&self) -> &'static str {
         match self {
             Value::String(_) => "Text",
             Value::Bytes(_) => "Blob",
             Value::Int64(_) | Value::Bool(_) => "Integer",
             Value::Float64(_) => "Real",
+            Value::Null => "Null",
         }
     }
 
     pub fn convert<T: ValueConvert>(&self) -> T {
         T::from_value(self)
     }
+
+    pub fn try_convert<T: ValueConvert>(&self) -> Result<T, ConversionError> {
+        T::try_from_value(self)
+    }
 }
@@ -0,0 +1,613 @@
+#![forbid(unsafe_code)]
+
+use std::collections::HashMap;
+
+use crate::{
+    data::DataType,
+    object::{ColumnDef, IndexDef, Schema, REVISION_COLUMN, REVISION_INDEX},
+    storage::{ColumnInfo, INTERNED_TABLE},
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Dialect {
+    #[default]
+    Sqlite,
+    Postgres,
+}
+
+impl Dialect {
+    pub fn quote_ident(&self, name: &str) -> String {
+        match self {
+            Dialect::Sqlite => format!("`{}`", name.replace('`', "``")),
+            Dialect::Postgres => format!("\"{}\"", name.replace('"', "\"\"")),
+        }
+    }
+
+    pub fn column_type(&self, ty: DataType) -> &'static str {
+        match (self, ty) {
+            (_, DataType::String) => "TEXT",
+            (Dialect::Sqlite, DataType::Bytes) => "BLOB",
+            (Dialect::Postgres, DataType::Bytes) => "BYTEA",
+            (Dialect::Sqlite, DataType::Int64 | DataType::Bool) => "INTEGER",
+            (Dialect::Postgres, DataType::Int64) => "BIGINT",
+            (Dialect::Postgres, DataType::Bool) => "BOOLEAN",
+            (Dialect::Sqlite, DataType::Float64) => "REAL",
+            (Dialect::Postgres, DataType::Float64) => "DOUBLE PRECISION",
+        }
+    }
+
+    fn id_column(&self) -> &'static str {
+        match self {
+            Dialect::Sqlite => "id INTEGER PRIMARY KEY AUTOINCREMENT",
+            Dialect::Postgres => "id BIGSERIAL PRIMARY KEY",
+        }
+    }
+
+    pub fn column_sql(&self, column: &ColumnDef) -> String {
+        let mut sql = format!(
+            "{} {}",
+            self.quote_ident(column.name),
+            self.column_type(column.stored_type())
+        );
+        if !column.nullable {
+            sql.push_str(" NOT NULL");
+        }
+        if column.unique || column.primary_key {
+            sql.push_str(" UNIQUE");
+        }
+        if let Some(default) = column.default {
+            sql.push_str(" DEFAULT ");
+            sql.push_str(default);
+        }
+        sql
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+impl Schema {
+    pub fn create_table_sql(&self, dialect: Dialect) -> String {
+        self.create_table_sql_as(dialect, self.table_name)
+    }
+
+    // The table of this schema, created under another name.
+    fn create_table_sql_as(&self, dialect: Dialect, table: &str) -> String {
+        if dialect != Dialect::Sqlite || !self.without_rowid {
+            let mut columns = vec![dialect.id_column().to_string()];
+            columns.extend(
+                self.columns_in(self.table_name)
+                    .map(|(_, column)| dialect.column_sql(column)),
+            );
+            columns.extend(self.revision_column_sql(dialect));
+            return create_table_sql(dialect, table, columns, false, self.strict);
+        }
+
+        // Clustered on the declared primary key, with `id` as a secondary key.
+        let mut columns = vec!["id INTEGER NOT NULL UNIQUE".to_string()];
+        columns.extend(self.columns_in(self.table_name).map(|(_, column)| {
+            dialect.column_sql(&ColumnDef {
+                primary_key: false,
+                ..*column
+            })
+        }));
+        columns.extend(self.revision_column_sql(dialect));
+        let primary_key = self
+            .columns_in(self.table_name)
+            .filter(|(_, column)| column.primary_key)
+            .map(|(_, column)| dialect.quote_ident(column.name))
+            .collect::<Vec<_>>();
+        columns.push(format!("PRIMARY KEY ({})", primary_key.join(", ")));
+        create_table_sql(dialect, table, columns, true, self.strict)
+    }
+
+    fn revision_column_sql(&self, dialect: Dialect) -> Option<String> {
+        self.revisions.then(|| dialect.column_sql(&REVISION_COLUMN))
+    }
+
+    pub fn create_split_tables_sql(&self, dialect: Dialect) -> Vec<String> {
+        let id_type = match dialect {
+            Dialect::Sqlite => "INTEGER",
+            Dialect::Postgres => "BIGINT",
+        };
+        self.split_tables()
+            .into_iter()
+            .map(|table| {
+                let mut columns = vec![format!("id {} PRIMARY KEY", id_type)];
+                columns.extend(
+                    self.columns_in(table)
+                        .map(|(_, column)| dialect.column_sql(column)),
+                );
+                create_table_sql(dialect, table, columns, false, self.strict)
+            })
+            .collect()
+    }
+
+    // Everything needed to create the object's storage from scratch.
+    pub fn create_statements(&self, dialect: Dialect) -> Vec<String> {
+        let mut statements = vec![self.create_table_sql(dialect)];
+        statements.extend(self.create_split_tables_sql(dialect));
+        statements.extend(self.create_index_sql(dialect));
+        if self.columns.iter().any(|column| column.interned) {
+            statements.extend(create_interned_table_sql(dialect));
+        }
+        statements
+    }
+
+    // The same tables in the connection's TEMP schema, where they shadow the
+    // stored ones for every statement that doesn't name a schema.
+    pub fn create_temp_statements(&self, dialect: Dialect) -> Vec<String> {
+        let temp = |sql: String| sql.replacen("CREATE TABLE", "CREATE TEMP TABLE", 1);
+        let mut statements = vec![temp(self.create_table_sql(dialect))];
+        statements.extend(self.create_split_tables_sql(dialect).into_iter().map(temp));
+        statements.extend(self.create_index_sql(dialect));
+        if self.columns.iter().any(|column| column.interned) {
+            statements.extend(create_interned_table_sql(dialect));
+        }
+        statements
+    }
+
+    pub fn drop_temp_statements(&self, dialect: Dialect) -> Vec<String> {
+        let temp_schema = match dialect {
+            Dialect::Sqlite => "temp",
+            Dialect::Postgres => "pg_temp",
+        };
+        std::iter::once(self.table_name)
+            .chain(self.split_tables())
+            .map(|table| format!("DROP TABLE {}.{}", temp_schema, dialect.quote_ident(table)))
+            .collect()
+    }
+
+    pub fn create_index_sql(&self, dialect: Dialect) -> Vec<String> {
+        let revisions = self.revisions.then_some(&REVISION_INDEX);
+        self.indexes
+            .iter()
+            .chain(revisions)
+            .map(|index| index.create_sql(dialect, self.table_name))
+            .collect()
+    }
+}
+
+// Reference 0 is the empty string, so that's what zero defaults resolve to.
+pub(crate) fn create_interned_table_sql(dialect: Dialect) -> Vec<String> {
+    let table = dialect.quote_ident(INTERNED_TABLE);
+    vec![
+        format!(
+            "CREATE TABLE IF NOT EXISTS {} ({}, value TEXT NOT NULL UNIQUE)",
+            table,
+            dialect.id_column()
+        ),
+        format!(
+            "INSERT INTO {} (id, value) VALUES (0, '') ON CONFLICT DO NOTHING",
+            table
+        ),
+    ]
+}
+
+fn create_table_sql(
+    dialect: Dialect,
+    table: &str,
+    columns: Vec<String>,
+    without_rowid: bool,
+    strict: bool,
+) -> String {
+    let mut sql = format!(
+        "CREATE TABLE {} ({})",
+        dialect.quote_ident(table),
+        columns.join(", ")
+    );
+    if dialect == Dialect::Sqlite {
+        let options = [(without_rowid, "WITHOUT ROWID"), (strict, "STRICT")]
+            .into_iter()
+            .filter_map(|(enabled, option)| enabled.then_some(option))
+            .collect::<Vec<_>>();
+        if !options.is_empty() {
+            sql.push(' ');
+            sql.push_str(&options.join(", "));
+        }
+    }
+    sql
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+impl IndexDef {
+    // Expressions contribute their identifiers, so `lower(email)` becomes
+    // `lower_email`.
+    pub fn name(&self, table: &str) -> String {
+        if let Some(name) = self.name {
+            return name.to_string();
+        }
+        let mut parts = vec![table.to_string()];
+        parts.extend(self.columns.iter().map(|column| column.to_string()));
+        parts.extend(self.expressions.iter().map(|expression| {
+            expression
+                .split(|c: char| !c.is_alphanumeric() && c != '_')
+                .filter(|word| !word.is_empty())
+                .collect::<Vec<_>>()
+                .join("_")
+        }));
+        parts.push("idx".to_string());
+        parts.join("_")
+    }
+
+    pub fn create_sql(&self, dialect: Dialect, table: &str) -> String {
+        let mut columns = self
+            .columns
+            .iter()
+            .map(|column| dialect.quote_ident(column))
+            .collect::<Vec<_>>();
+        columns.extend(
+            self.expressions
+                .iter()
+                .map(|expression| expression.to_string()),
+        );
+        let mut sql = format!(
+            "CREATE {}INDEX IF NOT EXISTS {} ON {} ({})",
+            if self.unique { "UNIQUE " } else { "" },
+            dialect.quote_ident(&self.name(table)),
+            dialect.quote_ident(table),
+            columns.join(", ")
+        );
+        if let Some(predicate) = self.predicate {
+            sql.push_str(" WHERE ");
+            sql.push_str(predicate);
+        }
+        sql
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ChangeKind {
+    CreateTable,
+    AdoptTable,
+    RenameColumn,
+    AddColumn,
+    ChangeType,
+    DropColumn,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SchemaChange {
+    pub kind: ChangeKind,
+    pub table_name: String,
+    pub column_name: Option<String>,
+    pub statements: Vec<String>,
+    pub destructive: bool,
+}
+
+// How a schema's stored tables differ from what it expects, see
+// Connection::validate_schema. Renamed columns show up as both missing and
+// extra until the rename is applied.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SchemaDrift {
+    pub type_name: &'static str,
+    pub missing_tables: Vec<&'static str>,
+    pub missing_columns: Vec<ColumnRef>,
+    pub extra_columns: Vec<ColumnRef>,
+    pub type_mismatches: Vec<TypeMismatch>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ColumnRef {
+    pub table_name: &'static str,
+    pub column_name: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TypeMismatch {
+    pub table_name: &'static str,
+    pub column_name: &'static str,
+    // Affinities, see affinity.
+    pub expected: &'static str,
+    pub found: &'static str,
+    pub declared_type: String,
+}
+
+impl SchemaDrift {
+    pub fn is_empty(&self) -> bool {
+        self.missing_tables.is_empty()
+            && self.missing_columns.is_empty()
+            && self.extra_columns.is_empty()
+            && self.type_mismatches.is_empty()
+    }
+}
+
+pub fn affinity(declared_type: &str) -> &'static str {
+    let ty = declared_type.to_ascii_uppercase();
+    if ty.contains("INT") {
+        "INTEGER"
+    } else if ty.contains("CHAR") || ty.contains("CLOB") || ty.contains("TEXT") {
+        "TEXT"
+    } else if ty.is_empty() || ty.contains("BLOB") {
+        "BLOB"
+    } else if ty.contains("REAL") || ty.contains("FLOA") || ty.contains("DOUB") {
+        "REAL"
+    } else {
+        "NUMERIC"
+    }
+}
+
+fn zero_default(ty: DataType) -> &'static str {
+    match ty {
+        DataType::String => "''",
+        DataType::Bytes => "x''",
+        DataType::Int64 | DataType::Bool => "0",
+        DataType::Float64 => "0.0",
+    }
+}
+
+// The value a column holds in rows written before it was added.
+pub(crate) fn missing_column_sql(column: &ColumnDef) -> &'static str {
+    match column.default {
+        Some(default) => default,
+        None if column.nullable => "NULL",
+        None => zero_default(column.stored_type()),
+    }
+}
+
+pub(crate) fn add_column_sql(dialect: Dialect, table: &str, column: &ColumnDef) -> Vec<String> {
+    let unique = column.unique || column.primary_key;
+    let plain = ColumnDef {
+        unique: false,
+        primary_key: false,
+        ..*column
+    };
+    let mut sql = format!(
+        "ALTER TABLE {} ADD COLUMN {}",
+        dialect.quote_ident(table),
+        dialect.column_sql(&plain)
+    );
+    if !column.nullable && column.default.is_none() {
+        sql.push_str(" DEFAULT ");
+        sql.push_str(zero_default(column.stored_type()));
+    }
+
+    let mut statements = vec![sql];
+    if column.interned {
+        statements.extend(create_interned_table_sql(dialect));
+    }
+    if unique {
+        statements.push(format!(
+            "CREATE UNIQUE INDEX {} ON {} ({})",
+            dialect.quote_ident(&format!("{}_{}_unique", table, column.name)),
+            dialect.quote_ident(table),
+            dialect.quote_ident(column.name)
+        ));
+    }
+    statements
+}
+
+pub fn diff_table(schema: &Schema, existing: Option<&[ColumnInfo]>) -> Vec<SchemaChange> {
+    let dialect = Dialect::Sqlite;
+    let change = |kind, column_name: Option<&str>, statements, destructive| SchemaChange {
+        kind,
+        table_name: schema.table_name.to_string(),
+        column_name: column_name.map(str::to_string),
+        statements,
+        destructive,
+    };
+
+    let Some(existing) = existing else {
+        let statements = schema.create_statements(dialect);
+        return vec![change(ChangeKind::CreateTable, None, statements, false)];
+    };
+
+    if !existing.iter().any(|info| info.name == "id") {
+        let (statements, dropped) = adopt_table_sql(dialect, schema, existing);
+        return vec![change(ChangeKind::AdoptTable, None, statements, dropped)];
+    }
+
+    let find = |name: &str| existing.iter().find(|info| info.name == name);
+    let rename_source = |column: &ColumnDef| {
+        if find(column.name).is_some() {
+            return None;
+        }
+        column
+            .renamed_from
+            .filter(|old| schema.column(old).is_none())
+            .and_then(find)
+    };
+
+    let mut changes = Vec::new();
+    for (_, column) in schema.columns_in(schema.table_name) {
+        let info = match (find(column.name), rename_source(column)) {
+            (Some(info), _) => info,
+            (None, Some(info)) => {
+                changes.push(change(
+                    ChangeKind::RenameColumn,
+                    Some(column.name),
+                    vec![format!(
+                        "ALTER TABLE {} RENAME COLUMN {} TO {}",
+                        dialect.quote_ident(schema.table_name),
+                        dialect.quote_ident(&info.name),
+                        dialect.quote_ident(column.name)
+                    )],
+                    false,
+                ));
+                info
+            }
+            (None, None) => {
+                changes.push(change(
+                    ChangeKind::AddColumn,
+                    Some(column.name),
+                    add_column_sql(dialect, schema.table_name, column),
+                    false,
+                ));
+                continue;
+            }
+        };
+        let expected = dialect.column_type(column.stored_type());
+        if affinity(&info.sql_type) != affinity(expected) {
+            changes.push(change(
+                ChangeKind::ChangeType,
+                Some(column.name),
+                Vec::new(),
+                true,
+            ));
+        }
+    }
+
+    if schema.revisions && find(REVISION_COLUMN.name).is_none() {
+        let mut statements = add_column_sql(dialect, schema.table_name, &REVISION_COLUMN);
+        statements.push(REVISION_INDEX.create_sql(dialect, schema.table_name));
+        changes.push(change(
+            ChangeKind::AddColumn,
+            Some(REVISION_COLUMN.name),
+            statements,
+            false,
+        ));
+    }
+
+    for info in existing {
+        let known = info.name == "id"
+            || (schema.revisions && info.name == REVISION_COLUMN.name)
+            || schema.columns_in(schema.table_name).any(|(_, column)| {
+                column.name == info.name
+                    || rename_source(column).is_some_and(|source| source.name == info.name)
+            });
+        if !known {
+            changes.push(change(
+                ChangeKind::DropColumn,
+                Some(&info.name),
+                vec![format!(
+                    "ALTER TABLE {} DROP COLUMN {}",
+                    dialect.quote_ident(schema.table_name),
+                    dialect.quote_ident(&info.name)
+                )],
+                true,
+            ));
+        }
+    }
+
+    changes
+}
+
+// `existing` holds the columns of each of the schema's tables that exist.
+pub fn schema_drift(schema: &Schema, existing: &HashMap<&str, Vec<ColumnInfo>>) -> SchemaDrift {
+    let dialect = Dialect::Sqlite;
+    let mut drift = SchemaDrift {
+        type_name: schema.type_name,
+        missing_tables: Vec::new(),
+        missing_columns: Vec::new(),
+        extra_columns: Vec::new(),
+        type_mismatches: Vec::new(),
+    };
+    for table in std::iter::once(schema.table_name).chain(schema.split_tables()) {
+        let Some(existing) = existing.get(table) else {
+            drift.missing_tables.push(table);
+            continue;
+        };
+        let find = |name: &str| {
+            existing
+                .iter()
+                .find(|info| info.name.eq_ignore_ascii_case(name))
+        };
+        let revision = (schema.revisions && table == schema.table_name).then_some(&REVISION_COLUMN);
+        let columns = schema
+            .columns_in(table)
+            .map(|(_, column)| column)
+            .chain(revision)
+            .collect::<Vec<_>>();
+
+        for name in std::iter::once("id").chain(columns.iter().map(|column| column.name)) {
+            if find(name).is_none() {
+                drift.missing_columns.push(ColumnRef {
+                    table_name: table,
+                    column_name: name.to_string(),
+                });
+            }
+        }
+        for column in &columns {
+            let Some(info) = find(column.name) else {
+                continue;
+            };
+            let expected = affinity(dialect.column_type(column.stored_type()));
+            let found = affinity(&info.sql_type);
+            if expected != found {
+                drift.type_mismatches.push(TypeMismatch {
+                    table_name: table,
+                    column_name: column.name,
+                    expected,
+                    found,
+                    declared_type: info.sql_type.clone(),
+                });
+            }
+        }
+        for info in existing {
+            let known = info.name.eq_ignore_ascii_case("id")
+                || columns
+                    .iter()
+                    .any(|column| info.name.eq_ignore_ascii_case(column.name));
+            if !known {
+                drift.extra_columns.push(ColumnRef {
+                    table_name: table,
+                    column_name: info.name.clone(),
+                });
+            }
+        }
+    }
+    drift
+}
+
+// A table created elsewhere, without the `id` column. SQLite can't add a
+// primary key to an existing table, so it's rebuilt with the rows copied over,
+// their ids taken from the rowid. Also reports whether any column was dropped.
+fn adopt_table_sql(
+    dialect: Dialect,
+    schema: &Schema,
+    existing: &[ColumnInfo],
+) -> (Vec<String>, bool) {
+    let find = |name: &str| existing.iter().find(|info| info.name == name);
+    let table = dialect.quote_ident(schema.table_name);
+    let staging = format!("_orm_adopt_{}", schema.table_name);
+
+    let revision = schema.revisions.then_some(&REVISION_COLUMN);
+    let columns = schema
+        .columns_in(schema.table_name)
+        .map(|(_, column)| column)
+        .chain(revision)
+        .collect::<Vec<_>>();
+    let mut names = vec!["id".to_string()];
+    let mut values = vec!["rowid".to_string()];
+    let mut copied = Vec::new();
+    for column in &columns {
+        names.push(dialect.quote_ident(column.name));
+        let source = find(column.name).or_else(|| column.renamed_from.and_then(find));
+        match source {
+            Some(info) => {
+                values.push(dialect.quote_ident(&info.name));
+                copied.push(info.name.as_str());
+            }
+            None => values.push(missing_column_sql(column).to_string()),
+        }
+    }
+    let dropped = existing
+        .iter()
+        .any(|info| !copied.contains(&info.name.as_str()));
+
+    let mut statements = vec![
+        schema.create_table_sql_as(dialect, &staging),
+        format!(
+            "INSERT INTO {} ({}) SELECT {} FROM {} ORDER BY rowid",
+            dialect.quote_ident(&staging),
+            names.join(", "),
+            values.join(", "),
+            table
+        ),
+        format!("DROP TABLE {}", table),
+        format!(
+            "ALTER TABLE {} RENAME TO {}",
+            dialect.quote_ident(&staging),
+            table
+        ),
+    ];
+    statements.extend(schema.create_index_sql(dialect));
+    if schema.columns.iter().any(|column| column.interned) {
+        statements.extend(create_interned_table_sql(dialect));
+    }
+    (statements, dropped)
+}
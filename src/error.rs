@@ -1,8 +1,17 @@
 #![forbid(unsafe_code)]
 
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::fmt;
+use std::time::Duration;
+
+use rusqlite::ffi;
 use thiserror::Error;
 
-use crate::{data::DataType, ObjectId};
+use crate::{
+    access::AccessDeniedError, blob::MissingBlobError, checksum::CorruptionError,
+    cipher::CipherError, data::DataType, intern::MissingInternedError, migrations::MigrationError,
+    quota::QuotaError, validate::ValidationError, ObjectId,
+};
 
 ////////////////////////////////////////////////////////////////////////////////
 
@@ -11,15 +20,164 @@ pub enum Error {
     #[error(transparent)]
     NotFound(Box<NotFoundError>),
     #[error(transparent)]
+    UnregisteredType(Box<UnregisteredTypeError>),
+    #[error(transparent)]
     UnexpectedType(Box<UnexpectedTypeError>),
     #[error(transparent)]
     MissingColumn(Box<MissingColumnError>),
+    #[error(transparent)]
+    InvalidText(Box<InvalidTextError>),
+    #[error(transparent)]
+    Validation(Box<ValidationError>),
+    #[error("unique constraint violated: {0}")]
+    UniqueViolation(Box<ConstraintError>),
+    #[error("foreign key constraint violated: {0}")]
+    ForeignKeyViolation(Box<ConstraintError>),
+    #[error("check constraint violated: {0}")]
+    CheckViolation(Box<ConstraintError>),
+    #[error("not null constraint violated: {0}")]
+    NotNullViolation(Box<ConstraintError>),
+    #[error(transparent)]
+    QuotaExceeded(Box<QuotaError>),
+    #[error(transparent)]
+    AccessDenied(Box<AccessDeniedError>),
+    #[error(transparent)]
+    Cipher(Box<CipherError>),
+    #[error(transparent)]
+    Corruption(Box<CorruptionError>),
+    #[error(transparent)]
+    MissingBlob(Box<MissingBlobError>),
+    #[error(transparent)]
+    MissingInterned(Box<MissingInternedError>),
+    #[error(transparent)]
+    Unsupported(Box<UnsupportedError>),
+    #[error(transparent)]
+    InvalidRequest(Box<InvalidRequestError>),
     #[error("database is locked")]
     LockConflict,
     #[error("storage error: {0}")]
-    Storage(#[source] Box<dyn std::error::Error>),
+    Storage(#[source] Box<dyn std::error::Error + Send + Sync>),
+    #[error(transparent)]
+    Chunked(Box<ChunkedError>),
+    #[error(transparent)]
+    Migration(Box<MigrationError>),
+    #[error(transparent)]
+    Context(Box<ContextError>),
 }
 
+impl Error {
+    pub(crate) fn with_operation(self, operation: String) -> Self {
+        match self {
+            Error::Context(_) => self,
+            source => Error::Context(Box::new(ContextError {
+                operation,
+                backtrace: Backtrace::capture(),
+                conflict: None,
+                source,
+            })),
+        }
+    }
+
+    // Lock conflicts get the context even when error context is off, as it's
+    // the only place to carry the conflict's details.
+    pub(crate) fn with_conflict(
+        self,
+        operation: impl FnOnce() -> String,
+        conflict: impl FnOnce() -> LockConflictInfo,
+    ) -> Self {
+        let mut err = match self {
+            Error::LockConflict => self.with_operation(operation()),
+            err => err,
+        };
+        if let Error::Context(ctx) = &mut err {
+            if matches!(ctx.source, Error::LockConflict) {
+                ctx.conflict = Some(conflict());
+            }
+        }
+        err
+    }
+
+    pub fn inner(&self) -> &Error {
+        match self {
+            Error::Context(ctx) => &ctx.source,
+            _ => self,
+        }
+    }
+
+    pub fn operation(&self) -> Option<&str> {
+        match self {
+            Error::Context(ctx) => Some(&ctx.operation),
+            _ => None,
+        }
+    }
+
+    pub fn lock_conflict(&self) -> Option<&LockConflictInfo> {
+        match self {
+            Error::Context(ctx) => ctx.conflict.as_ref(),
+            _ => None,
+        }
+    }
+
+    pub fn backtrace(&self) -> Option<&Backtrace> {
+        match self {
+            Error::Context(ctx) if ctx.backtrace.status() == BacktraceStatus::Captured => {
+                Some(&ctx.backtrace)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Context(ctx) => ctx.source.is_retryable(),
+            Error::Chunked(err) => err.source.is_retryable(),
+            Error::LockConflict => true,
+            Error::Storage(source) => is_transient(source.as_ref()),
+            _ => false,
+        }
+    }
+
+    pub fn is_conflict(&self) -> bool {
+        match self {
+            Error::Context(ctx) => ctx.source.is_conflict(),
+            Error::Chunked(err) => err.source.is_conflict(),
+            Error::LockConflict | Error::UniqueViolation(_) => true,
+            Error::Storage(source) => is_transient(source.as_ref()) && !is_io(source.as_ref()),
+            _ => false,
+        }
+    }
+}
+
+fn is_transient(err: &(dyn std::error::Error + Send + Sync + 'static)) -> bool {
+    if let Some(err) = err.downcast_ref::<StatementError>() {
+        return is_transient(err.source.as_ref());
+    }
+    if let Some(err) = err.downcast_ref::<rusqlite::Error>() {
+        return matches!(
+            err.sqlite_error_code(),
+            Some(rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked)
+        );
+    }
+    if let Some(err) = err.downcast_ref::<std::io::Error>() {
+        return matches!(
+            err.kind(),
+            std::io::ErrorKind::Interrupted
+                | std::io::ErrorKind::TimedOut
+                | std::io::ErrorKind::WouldBlock
+        );
+    }
+    false
+}
+
+fn is_io(err: &(dyn std::error::Error + Send + Sync + 'static)) -> bool {
+    match err.downcast_ref::<StatementError>() {
+        Some(err) => is_io(err.source.as_ref()),
+        None => err.is::<std::io::Error>(),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
 #[derive(Default)]
 pub(crate) struct ErrorCtx {
     pub object_id: Option<ObjectId>,
@@ -57,47 +215,75 @@ impl<'a, E> ErrorWithCtx<'a, E> {
     }
 }
 
-impl<'a> From<ErrorWithCtx<'a, rusqlite::Error>> for Error {
-    fn from(err: ErrorWithCtx<rusqlite::Error>) -> Self {
-        let ctx = err.ctx;
-        match err.error {
-            rusqlite::Error::QueryReturnedNoRows => Error::NotFound(Box::new(NotFoundError {
-                object_id: ctx.object_id.unwrap(),
-                type_name: ctx.type_name.unwrap(),
-            })),
-            rusqlite::Error::InvalidColumnType(..) => {
-                Error::UnexpectedType(Box::new(UnexpectedTypeError {
-                    type_name: ctx.type_name.unwrap(),
-                    attr_name: ctx.attr_name.unwrap(),
-                    table_name: ctx.table_name.unwrap(),
-                    column_name: ctx.column_name.unwrap(),
-                    expected_type: ctx.expected_type.unwrap(),
-                    got_type: ctx.got_type.unwrap(),
-                }))
-            }
-            rusqlite::Error::SqliteFailure(
-                rusqlite::ffi::Error {
-                    code: rusqlite::ErrorCode::DatabaseBusy,
-                    ..
-                },
-                _,
-            ) => Error::LockConflict,
-
-            rusqlite::Error::SqliteFailure(_, text)
-                if text.as_ref().is_some_and(|text| {
-                    text.contains("no such column:") || text.contains("has no column named")
-                }) =>
-            {
-                Error::MissingColumn(Box::new(MissingColumnError {
-                    type_name: ctx.type_name.unwrap(),
-                    attr_name: ctx.attr_name.unwrap(),
-                    table_name: ctx.table_name.unwrap(),
-                    column_name: ctx.column_name.unwrap(),
-                }))
+fn typed_error(error: &rusqlite::Error, ctx: ErrorCtx) -> Option<Error> {
+    Some(match error {
+        rusqlite::Error::QueryReturnedNoRows => Error::NotFound(Box::new(NotFoundError {
+            object_id: ctx.object_id?,
+            type_name: ctx.type_name?,
+        })),
+        rusqlite::Error::InvalidColumnType(..) => {
+            Error::UnexpectedType(Box::new(UnexpectedTypeError {
+                type_name: ctx.type_name?,
+                attr_name: ctx.attr_name?,
+                table_name: ctx.table_name?,
+                column_name: ctx.column_name?,
+                expected_type: ctx.expected_type?,
+                got_type: ctx.got_type?,
+            }))
+        }
+        rusqlite::Error::SqliteFailure(
+            ffi::Error {
+                code: rusqlite::ErrorCode::DatabaseBusy,
+                ..
+            },
+            _,
+        ) => Error::LockConflict,
+
+        rusqlite::Error::SqliteFailure(
+            ffi::Error {
+                code: rusqlite::ErrorCode::ConstraintViolation,
+                extended_code,
+            },
+            text,
+        ) => {
+            let err = Box::new(ConstraintError {
+                type_name: ctx.type_name,
+                attr_name: ctx.attr_name,
+                table_name: ctx.table_name,
+                column_name: ctx.column_name,
+                message: text.clone().unwrap_or_else(|| error.to_string()),
+            });
+            match *extended_code {
+                ffi::SQLITE_CONSTRAINT_UNIQUE | ffi::SQLITE_CONSTRAINT_PRIMARYKEY => {
+                    Error::UniqueViolation(err)
+                }
+                ffi::SQLITE_CONSTRAINT_FOREIGNKEY => Error::ForeignKeyViolation(err),
+                ffi::SQLITE_CONSTRAINT_CHECK => Error::CheckViolation(err),
+                ffi::SQLITE_CONSTRAINT_NOTNULL => Error::NotNullViolation(err),
+                _ => return None,
             }
+        }
 
-            _ => unimplemented!("error: {:?}", err.error),
+        rusqlite::Error::SqliteFailure(_, text)
+            if text.as_ref().is_some_and(|text| {
+                text.contains("no such column:") || text.contains("has no column named")
+            }) =>
+        {
+            Error::MissingColumn(Box::new(MissingColumnError {
+                type_name: ctx.type_name?,
+                attr_name: ctx.attr_name?,
+                table_name: ctx.table_name?,
+                column_name: ctx.column_name?,
+            }))
         }
+
+        _ => return None,
+    })
+}
+
+impl<'a> From<ErrorWithCtx<'a, rusqlite::Error>> for Error {
+    fn from(err: ErrorWithCtx<rusqlite::Error>) -> Self {
+        typed_error(&err.error, err.ctx).unwrap_or_else(|| Error::Storage(Box::new(err.error)))
     }
 }
 
@@ -116,6 +302,13 @@ pub struct NotFoundError {
     pub type_name: &'static str,
 }
 
+#[derive(Error, Debug)]
+#[error("no registered type is stored in table '{table_name}' (id {object_id})")]
+pub struct UnregisteredTypeError {
+    pub object_id: ObjectId,
+    pub table_name: String,
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 #[derive(Error, Debug)]
@@ -148,4 +341,145 @@ pub struct MissingColumnError {
 
 ////////////////////////////////////////////////////////////////////////////////
 
+#[derive(Error, Debug)]
+#[error(
+    "invalid text for {type_name}::{attr_name}: {message} \
+    (table: {table_name}, column: {column_name})"
+)]
+pub struct InvalidTextError {
+    pub type_name: &'static str,
+    pub attr_name: &'static str,
+    pub table_name: &'static str,
+    pub column_name: &'static str,
+    pub message: String,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Error, Debug)]
+#[error("{feature} require SQLite {required} or newer, found {found}")]
+pub struct UnsupportedError {
+    pub feature: String,
+    pub required: String,
+    pub found: String,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// A call the arguments or the transaction's state make impossible, e.g. an
+// unknown column name.
+#[derive(Error, Debug)]
+#[error("invalid request for type '{type_name}': {message}")]
+pub struct InvalidRequestError {
+    pub type_name: &'static str,
+    pub message: String,
+}
+
+impl Error {
+    pub(crate) fn invalid_request(type_name: &'static str, message: impl Into<String>) -> Self {
+        Error::InvalidRequest(Box::new(InvalidRequestError {
+            type_name,
+            message: message.into(),
+        }))
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug)]
+pub struct ConstraintError {
+    pub type_name: Option<&'static str>,
+    pub attr_name: Option<&'static str>,
+    pub table_name: Option<&'static str>,
+    pub column_name: Option<&'static str>,
+    pub message: String,
+}
+
+impl fmt::Display for ConstraintError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        let ctx = [
+            ("type", self.type_name),
+            ("attr", self.attr_name),
+            ("table", self.table_name),
+            ("column", self.column_name),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| Some(format!("{}: {}", key, value?)))
+        .collect::<Vec<_>>();
+        if !ctx.is_empty() {
+            write!(f, " ({})", ctx.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConstraintError {}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Error, Debug)]
+#[error("{source} (sql: {sql}; params: [{}])", params.join(", "))]
+pub struct StatementError {
+    pub source: Box<dyn std::error::Error + Send + Sync>,
+    pub sql: String,
+    pub params: Vec<String>,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Error, Debug)]
+#[error("chunked transaction failed after {committed} committed items: {source}")]
+pub struct ChunkedError {
+    pub committed: usize,
+    pub source: Error,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LockConflictInfo {
+    pub tables: Vec<&'static str>,
+    // Time from the start of the commit until it gave up, including any
+    // writes made before the lock was requested.
+    pub elapsed: Duration,
+}
+
+impl fmt::Display for LockConflictInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.tables.is_empty() {
+            write!(f, "writing {}; ", self.tables.join(", "))?;
+        }
+        write!(f, "gave up after {:?}", self.elapsed)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug)]
+pub struct ContextError {
+    pub operation: String,
+    pub backtrace: Backtrace,
+    pub conflict: Option<LockConflictInfo>,
+    pub source: Error,
+}
+
+impl fmt::Display for ContextError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.operation, self.source)?;
+        if let Some(conflict) = &self.conflict {
+            write!(f, " ({})", conflict)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ContextError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
 pub type Result<T> = std::result::Result<T, Error>;
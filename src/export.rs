@@ -0,0 +1,67 @@
+#![forbid(unsafe_code)]
+
+use std::fmt::Write;
+
+use crate::{
+    data::DataType,
+    object::{ColumnDef, Schema},
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn json_type(column: &ColumnDef) -> String {
+    let (ty, extra) = match column.data_type {
+        DataType::String => ("string", ""),
+        DataType::Bytes => ("string", ", \"contentEncoding\": \"base64\""),
+        DataType::Int64 => ("integer", ""),
+        DataType::Float64 => ("number", ""),
+        DataType::Bool => ("boolean", ""),
+    };
+    if column.nullable {
+        format!("{{\"type\": [\"{}\", \"null\"]{}}}", ty, extra)
+    } else {
+        format!("{{\"type\": \"{}\"{}}}", ty, extra)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+impl Schema {
+    pub fn to_json_schema(&self) -> String {
+        let mut properties = vec![format!("{}: {{\"type\": \"integer\"}}", json_string("id"))];
+        let mut required = vec![json_string("id")];
+        for (column, attr) in self.columns.iter().zip(self.attrs) {
+            properties.push(format!("{}: {}", json_string(attr), json_type(column)));
+            if !column.nullable {
+                required.push(json_string(attr));
+            }
+        }
+
+        format!(
+            "{{\"$schema\": \"https://json-schema.org/draft/2020-12/schema\", \
+            \"title\": {}, \"type\": \"object\", \"properties\": {{{}}}, \
+            \"required\": [{}], \"additionalProperties\": false}}",
+            json_string(self.type_name),
+            properties.join(", "),
+            required.join(", ")
+        )
+    }
+}
@@ -0,0 +1,244 @@
+#![forbid(unsafe_code)]
+
+use std::borrow::Cow;
+use std::fmt;
+use std::ops::Deref;
+
+use crate::{
+    data::{converted, ConversionError, DataType, DetectDataType, Value, ValueConvert},
+    error::{Error, InvalidTextError},
+    object::Schema,
+    storage::{Row, RowSlice},
+    validate::Validated,
+    Result,
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Text columns that only hold values of a known shape. Rows written by other
+// programs are checked when read, before any object is built from them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextFormat {
+    Email,
+    PhoneNumber,
+}
+
+impl TextFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TextFormat::Email => "email address",
+            TextFormat::PhoneNumber => "phone number",
+        }
+    }
+
+    pub fn is_valid(&self, text: &str) -> bool {
+        match self {
+            TextFormat::Email => is_email(text),
+            TextFormat::PhoneNumber => normalize_phone(text).is_some(),
+        }
+    }
+}
+
+fn normalize_email(text: &str) -> Option<String> {
+    is_email(text).then(|| text.to_string())
+}
+
+fn is_email(text: &str) -> bool {
+    let Some((local, domain)) = text.rsplit_once('@') else {
+        return false;
+    };
+    let local_ok = !local.is_empty()
+        && local.len() <= 64
+        && !local.starts_with('.')
+        && !local.ends_with('.')
+        && !local.contains("..")
+        && local
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+/=?^_`{|}~.-".contains(c));
+    let labels = domain.split('.').collect::<Vec<_>>();
+    let domain_ok = domain.len() <= 253
+        && labels.len() > 1
+        && labels.iter().all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        });
+    text.len() <= 254 && local_ok && domain_ok
+}
+
+// Strips the usual separators, keeping a leading `+`. E.164 allows at most 15
+// digits; anything shorter than 7 is an extension or a typo.
+fn normalize_phone(text: &str) -> Option<String> {
+    let (plus, rest) = match text.strip_prefix('+') {
+        Some(rest) => ("+", rest),
+        None => ("", text),
+    };
+    let mut digits = String::with_capacity(rest.len());
+    for c in rest.chars() {
+        match c {
+            '0'..='9' => digits.push(c),
+            ' ' | '-' | '.' | '(' | ')' => {}
+            _ => return None,
+        }
+    }
+    (7..=15)
+        .contains(&digits.len())
+        .then(|| format!("{}{}", plus, digits))
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug)]
+pub struct InvalidFormatError {
+    pub format: TextFormat,
+    pub text: String,
+}
+
+impl fmt::Display for InvalidFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} is not a valid {}", self.text, self.format.name())
+    }
+}
+
+impl std::error::Error for InvalidFormatError {}
+
+////////////////////////////////////////////////////////////////////////////////
+
+macro_rules! formatted_text {
+    ($name:ident, $format:ident, $normalize:expr) => {
+        #[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        pub struct $name(String);
+
+        impl $name {
+            pub fn new(text: impl Into<String>) -> std::result::Result<Self, InvalidFormatError> {
+                let text = text.into();
+                match $normalize(&text) {
+                    Some(normalized) => Ok(Self(normalized)),
+                    None => Err(InvalidFormatError {
+                        format: TextFormat::$format,
+                        text,
+                    }),
+                }
+            }
+
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl Deref for $name {
+            type Target = str;
+
+            fn deref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+
+        impl DetectDataType for $name {
+            const TYPE: DataType = DataType::String;
+            const FORMAT: Option<TextFormat> = Some(TextFormat::$format);
+        }
+
+        impl ValueConvert for $name {
+            fn to_value(&self) -> Value<'static> {
+                Value::String(Cow::Owned(self.0.clone()))
+            }
+
+            fn to_value_ref(&self) -> Value<'_> {
+                Value::String(Cow::Borrowed(&self.0))
+            }
+
+            fn from_value(value: &Value<'_>) -> Self {
+                converted(Self::try_from_value(value))
+            }
+
+            fn try_from_value(value: &Value<'_>) -> std::result::Result<Self, ConversionError> {
+                match value {
+                    Value::String(text) => Self::new(text.as_ref()).map_err(|e| {
+                        ConversionError::new(e.format.name(), format!("{:?}", e.text))
+                    }),
+                    _ => Err(ConversionError::unexpected(DataType::String, value)),
+                }
+            }
+        }
+
+        impl Validated for $name {
+            type Inner = str;
+
+            fn validated(&self) -> Option<&str> {
+                Some(&self.0)
+            }
+        }
+    };
+}
+
+formatted_text!(Email, Email, normalize_email);
+formatted_text!(PhoneNumber, PhoneNumber, normalize_phone);
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub(crate) fn verify_row(schema: &Schema, row: &RowSlice) -> Result<()> {
+    for (i, (column, value)) in schema.columns.iter().zip(row).enumerate() {
+        let (Some(format), Value::String(text)) = (column.format, value) else {
+            continue;
+        };
+        if !format.is_valid(text) {
+            return Err(Error::InvalidText(Box::new(InvalidTextError {
+                type_name: schema.type_name,
+                attr_name: schema.attrs[i],
+                table_name: schema.table_of(column),
+                column_name: column.name,
+                message: format!("not a valid {}", format.name()),
+            })));
+        }
+    }
+    Ok(())
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Bool fields with a legacy encoding, see BoolEncoding.
+pub(crate) fn decode_bools<'r>(schema: &Schema, mut row: Row<'r>) -> Result<Row<'r>> {
+    for (i, (column, value)) in schema.columns.iter().zip(row.iter_mut()).enumerate() {
+        let Some(encoding) = column.bool_encoding else {
+            continue;
+        };
+        let Some(decoded) = encoding.decode(value) else {
+            let got = match value {
+                Value::String(text) => format!("'{}'", text),
+                value => value.sql_type().to_string(),
+            };
+            return Err(Error::InvalidText(Box::new(InvalidTextError {
+                type_name: schema.type_name,
+                attr_name: schema.attrs[i],
+                table_name: schema.table_of(column),
+                column_name: column.name,
+                message: format!(
+                    "expected {} or {}, got {}",
+                    encoding.sql_literal(true),
+                    encoding.sql_literal(false),
+                    got
+                ),
+            })));
+        };
+        *value = Value::Bool(decoded);
+    }
+    Ok(row)
+}
+
+pub(crate) fn encode_bools<'r>(schema: &Schema, mut row: Row<'r>) -> Row<'r> {
+    for (column, value) in schema.columns.iter().zip(row.iter_mut()) {
+        if let (Some(encoding), Value::Bool(flag)) = (column.bool_encoding, &*value) {
+            *value = encoding.encode(*flag);
+        }
+    }
+    row
+}
@@ -0,0 +1,109 @@
+#![forbid(unsafe_code)]
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::{
+    data::Value,
+    error::Error,
+    object::Schema,
+    storage::{Row, StorageTransaction},
+    ObjectId, Result,
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+// References resolved within one transaction. Ids handed out by it can't be
+// shared with later ones, since a rollback frees them for other values.
+#[derive(Default)]
+pub(crate) struct InternCache {
+    ids: HashMap<String, i64>,
+    values: HashMap<i64, String>,
+}
+
+impl InternCache {
+    fn insert(&mut self, id: i64, value: String) {
+        self.ids.insert(value.clone(), id);
+        self.values.insert(id, value);
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug)]
+pub struct MissingInternedError {
+    pub object_id: ObjectId,
+    pub type_name: &'static str,
+    pub column_name: &'static str,
+    pub reference: i64,
+}
+
+impl fmt::Display for MissingInternedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "interned value {} referenced by {}::{} of object {} is missing",
+            self.reference, self.type_name, self.column_name, self.object_id
+        )
+    }
+}
+
+impl std::error::Error for MissingInternedError {}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub(crate) fn store_interned<'r>(
+    tx: &dyn StorageTransaction,
+    cache: &mut InternCache,
+    schema: &Schema,
+    mut row: Row<'r>,
+) -> Result<Row<'r>> {
+    for (column, value) in schema.columns.iter().zip(row.iter_mut()) {
+        let (true, Value::String(text)) = (column.interned, &*value) else {
+            continue;
+        };
+        let id = match cache.ids.get(text.as_ref()) {
+            Some(id) => *id,
+            None => {
+                let id = tx.intern(text)?;
+                cache.insert(id, text.to_string());
+                id
+            }
+        };
+        *value = Value::Int64(id);
+    }
+    Ok(row)
+}
+
+pub(crate) fn load_interned<'r>(
+    tx: &dyn StorageTransaction,
+    cache: &mut InternCache,
+    id: ObjectId,
+    schema: &Schema,
+    mut row: Row<'r>,
+) -> Result<Row<'r>> {
+    for (column, value) in schema.columns.iter().zip(row.iter_mut()) {
+        let (true, Value::Int64(reference)) = (column.interned, &*value) else {
+            continue;
+        };
+        let reference = *reference;
+        let text = match cache.values.get(&reference) {
+            Some(text) => text.clone(),
+            None => {
+                let Some(text) = tx.interned_value(reference)? else {
+                    return Err(Error::MissingInterned(Box::new(MissingInternedError {
+                        object_id: id,
+                        type_name: schema.type_name,
+                        column_name: column.name,
+                        reference,
+                    })));
+                };
+                cache.insert(reference, text.clone());
+                text
+            }
+        };
+        *value = Value::String(Cow::Owned(text));
+    }
+    Ok(row)
+}
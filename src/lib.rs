@@ -6,12 +6,38 @@ pub use data::ValueConvert;
 pub use error::{Error, Result};
 pub use object::Object;
 pub use orm_derive::Object;
-pub use transaction::{ObjectState, Transaction, Tx};
+pub use sync_connection::{BlockingTask, ShutdownReport, SyncConnection, WriteBehind};
+pub use transaction::{
+    AnyTx, Chunks, ObjectChange, ObjectState, ParseCursorError, PreparedQuery, Scan, ScanCursor,
+    Transaction, Tx,
+};
 
 mod connection;
 mod error;
+mod sync_connection;
 mod transaction;
 
+pub mod access;
+pub mod advisor;
+pub mod batch;
+pub mod blob;
+pub mod checksum;
+pub mod cipher;
+pub mod codec;
+pub mod codegen;
 pub mod data;
+pub mod ddl;
+pub mod export;
+pub mod format;
+pub mod intern;
+pub mod merge;
+pub mod migrations;
+pub mod money;
 pub mod object;
+pub mod query;
+pub mod quota;
+pub mod remote;
+pub mod stats;
 pub mod storage;
+pub mod validate;
+pub mod watchdog;
@@ -0,0 +1,118 @@
+#![forbid(unsafe_code)]
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use crate::{
+    data::Value,
+    object::{Object, Schema},
+    storage::{Row, RowSlice},
+    Result,
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+// When each field of one copy of an object was last written, by field name.
+// Fields without an entry count as never written.
+pub type FieldTimes = HashMap<String, i64>;
+
+// Last writer wins, field by field. Columns stored for the same field, such as
+// a Money amount and its currency, always come from the same side.
+//
+// Equal timestamps with different values go to the greater value, so every
+// replica merging the same two copies ends up with the same row no matter
+// which one it considers its own.
+pub fn merge_rows<'r>(
+    schema: &Schema,
+    ours: &RowSlice<'r>,
+    our_times: &FieldTimes,
+    theirs: &RowSlice<'r>,
+    their_times: &FieldTimes,
+) -> (Row<'r>, FieldTimes) {
+    assert_eq!(
+        ours.len(),
+        schema.columns.len(),
+        "row doesn't match the schema"
+    );
+    assert_eq!(
+        theirs.len(),
+        schema.columns.len(),
+        "row doesn't match the schema"
+    );
+
+    let mut row = ours.to_vec();
+    let mut times = our_times.clone();
+    let mut start = 0;
+    while start < schema.attrs.len() {
+        let attr = schema.attrs[start];
+        let end = start
+            + schema.attrs[start..]
+                .iter()
+                .take_while(|name| **name == attr)
+                .count();
+        let our_time = our_times.get(attr).copied();
+        let their_time = their_times.get(attr).copied();
+        let take_theirs = match their_time.cmp(&our_time) {
+            Ordering::Greater => true,
+            Ordering::Less => false,
+            Ordering::Equal => compare_rows(&theirs[start..end], &ours[start..end]).is_gt(),
+        };
+        if take_theirs {
+            row[start..end].clone_from_slice(&theirs[start..end]);
+            if let Some(time) = their_time {
+                times.insert(attr.to_string(), time);
+            }
+        }
+        start = end;
+    }
+    (row, times)
+}
+
+pub fn merge<T: Object>(
+    ours: &T,
+    our_times: &FieldTimes,
+    theirs: &T,
+    their_times: &FieldTimes,
+) -> Result<(T, FieldTimes)> {
+    let (row, times) = merge_rows(
+        T::schema(),
+        &ours.to_row(),
+        our_times,
+        &theirs.to_row(),
+        their_times,
+    );
+    Ok((T::try_from_row(row)?, times))
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Any total order works for breaking ties as long as all replicas share it.
+fn compare_rows(left: &RowSlice, right: &RowSlice) -> Ordering {
+    left.iter()
+        .zip(right)
+        .map(|(left, right)| compare_values(left, right))
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+fn compare_values(left: &Value, right: &Value) -> Ordering {
+    fn rank(value: &Value) -> u8 {
+        match value {
+            Value::Null => 0,
+            Value::Bool(_) => 1,
+            Value::Int64(_) => 2,
+            Value::Float64(_) => 3,
+            Value::String(_) => 4,
+            Value::Bytes(_) => 5,
+        }
+    }
+
+    match (left, right) {
+        (Value::Bool(left), Value::Bool(right)) => left.cmp(right),
+        (Value::Int64(left), Value::Int64(right)) => left.cmp(right),
+        (Value::Float64(left), Value::Float64(right)) => left.total_cmp(right),
+        (Value::String(left), Value::String(right)) => left.cmp(right),
+        (Value::Bytes(left), Value::Bytes(right)) => left.cmp(right),
+        (left, right) => rank(left).cmp(&rank(right)),
+    }
+}
@@ -0,0 +1,135 @@
+#![forbid(unsafe_code)]
+
+use std::fmt;
+
+use crate::{Connection, Error, Result, Transaction};
+
+////////////////////////////////////////////////////////////////////////////////
+
+// One step of a schema's history, see Connection::migrate. Versions order the
+// steps and are recorded once applied, so they must never be reused.
+pub trait Migration {
+    fn version(&self) -> i64;
+    fn name(&self) -> &str;
+    fn up(&self, tx: &Transaction<'_>) -> Result<()>;
+}
+
+// A migration that only runs statements.
+#[derive(Clone, Copy, Debug)]
+pub struct SqlMigration {
+    pub version: i64,
+    pub name: &'static str,
+    pub statements: &'static [&'static str],
+}
+
+impl Migration for SqlMigration {
+    fn version(&self) -> i64 {
+        self.version
+    }
+
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn up(&self, tx: &Transaction<'_>) -> Result<()> {
+        tx.execute_ddl(self.statements)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug)]
+pub struct MigrationError {
+    pub version: i64,
+    pub name: String,
+    // Versions committed by the same run before this one failed.
+    pub applied: Vec<i64>,
+    pub source: Error,
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "migration {} ({}) failed: {}",
+            self.version, self.name, self.source
+        )
+    }
+}
+
+impl std::error::Error for MigrationError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Applied versions are kept in the `_orm_migrations` table. Each migration
+// runs in its own transaction together with its record, so a failed one
+// leaves no trace and the ones before it stay applied. Versions missing from
+// the table run even if later ones are applied, e.g. after merging branches.
+pub struct Migrator<'m> {
+    migrations: Vec<&'m dyn Migration>,
+}
+
+impl<'m> Migrator<'m> {
+    pub fn new(migrations: &[&'m dyn Migration]) -> Self {
+        let mut migrations = migrations.to_vec();
+        migrations.sort_by_key(|migration| migration.version());
+        for pair in migrations.windows(2) {
+            assert!(
+                pair[0].version() != pair[1].version(),
+                "migrations '{}' and '{}' have the same version {}",
+                pair[0].name(),
+                pair[1].name(),
+                pair[0].version()
+            );
+        }
+        Self { migrations }
+    }
+
+    pub fn applied(&self, conn: &mut Connection) -> Result<Vec<i64>> {
+        let tx = conn.new_transaction()?;
+        let applied = tx.applied_migrations()?;
+        tx.rollback()?;
+        Ok(applied)
+    }
+
+    pub fn pending(&self, conn: &mut Connection) -> Result<Vec<i64>> {
+        let applied = self.applied(conn)?;
+        Ok(self
+            .migrations
+            .iter()
+            .map(|migration| migration.version())
+            .filter(|version| !applied.contains(version))
+            .collect())
+    }
+
+    // Returns the versions applied, in order.
+    pub fn run(&self, conn: &mut Connection) -> Result<Vec<i64>> {
+        let applied = self.applied(conn)?;
+        let mut done = Vec::new();
+        for migration in &self.migrations {
+            let version = migration.version();
+            if applied.contains(&version) {
+                continue;
+            }
+            let result = conn.new_transaction().and_then(|tx| {
+                migration.up(&tx)?;
+                tx.record_migration(version, migration.name())?;
+                tx.commit()
+            });
+            if let Err(source) = result {
+                return Err(Error::Migration(Box::new(MigrationError {
+                    version,
+                    name: migration.name().to_string(),
+                    applied: done,
+                    source,
+                })));
+            }
+            done.push(version);
+        }
+        Ok(done)
+    }
+}
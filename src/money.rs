@@ -0,0 +1,271 @@
+#![forbid(unsafe_code)]
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::{Mutex, OnceLock};
+
+use crate::data::{converted, ConversionError, DataType, Value, ValueConvert};
+
+////////////////////////////////////////////////////////////////////////////////
+
+// An exact amount in the smallest unit of its currency, e.g. cents for USD.
+// Stored as an integer column plus a currency column, or as a single integer
+// column when the field is declared with #[orm(currency = "...")].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Money {
+    pub minor_units: i64,
+    pub currency: &'static str,
+}
+
+impl Money {
+    pub const fn new(minor_units: i64, currency: &'static str) -> Self {
+        Self {
+            minor_units,
+            currency,
+        }
+    }
+
+    pub const fn zero(currency: &'static str) -> Self {
+        Self::new(0, currency)
+    }
+
+    // Parses a decimal amount such as "-12.34" without going through floats.
+    // More fractional digits than the currency has are rejected, not rounded.
+    pub fn parse(amount: &str, currency: &'static str) -> Result<Self, MoneyError> {
+        let invalid = || MoneyError::InvalidAmount(amount.to_string());
+        let (negative, digits) = match amount.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, amount),
+        };
+        let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        let scale = minor_digits(currency);
+        let is_digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+        if whole.is_empty() || !is_digits(whole) || !is_digits(fraction) {
+            return Err(invalid());
+        }
+        if fraction.len() > scale as usize || (digits.contains('.') && fraction.is_empty()) {
+            return Err(invalid());
+        }
+
+        let mut minor_units: i64 = 0;
+        let padding = scale as usize - fraction.len();
+        for digit in whole.bytes().chain(fraction.bytes()) {
+            minor_units = minor_units
+                .checked_mul(10)
+                .and_then(|units| units.checked_add(i64::from(digit - b'0')))
+                .ok_or(MoneyError::Overflow)?;
+        }
+        minor_units = 10i64
+            .checked_pow(padding as u32)
+            .and_then(|factor| minor_units.checked_mul(factor))
+            .ok_or(MoneyError::Overflow)?;
+        if negative {
+            minor_units = -minor_units;
+        }
+        Ok(Self::new(minor_units, currency))
+    }
+
+    pub fn checked_add(self, other: Money) -> Result<Self, MoneyError> {
+        self.same_currency(&other)?;
+        let minor_units = self
+            .minor_units
+            .checked_add(other.minor_units)
+            .ok_or(MoneyError::Overflow)?;
+        Ok(Self::new(minor_units, self.currency))
+    }
+
+    pub fn checked_sub(self, other: Money) -> Result<Self, MoneyError> {
+        self.same_currency(&other)?;
+        let minor_units = self
+            .minor_units
+            .checked_sub(other.minor_units)
+            .ok_or(MoneyError::Overflow)?;
+        Ok(Self::new(minor_units, self.currency))
+    }
+
+    pub fn checked_mul(self, factor: i64) -> Result<Self, MoneyError> {
+        let minor_units = self
+            .minor_units
+            .checked_mul(factor)
+            .ok_or(MoneyError::Overflow)?;
+        Ok(Self::new(minor_units, self.currency))
+    }
+
+    // Totals of one currency; an empty input sums to None.
+    pub fn checked_sum(
+        amounts: impl IntoIterator<Item = Money>,
+    ) -> Result<Option<Self>, MoneyError> {
+        let mut amounts = amounts.into_iter();
+        let Some(first) = amounts.next() else {
+            return Ok(None);
+        };
+        amounts.try_fold(first, Money::checked_add).map(Some)
+    }
+
+    fn same_currency(&self, other: &Money) -> Result<(), MoneyError> {
+        if self.currency == other.currency {
+            Ok(())
+        } else {
+            Err(MoneyError::CurrencyMismatch(self.currency, other.currency))
+        }
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scale = minor_digits(self.currency);
+        let sign = if self.minor_units < 0 { "-" } else { "" };
+        let units = self.minor_units.unsigned_abs();
+        if scale == 0 {
+            return write!(f, "{}{} {}", sign, units, self.currency);
+        }
+        let factor = 10u64.pow(scale);
+        write!(
+            f,
+            "{}{}.{:0width$} {}",
+            sign,
+            units / factor,
+            units % factor,
+            self.currency,
+            width = scale as usize
+        )
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MoneyError {
+    CurrencyMismatch(&'static str, &'static str),
+    Overflow,
+    InvalidAmount(String),
+}
+
+impl fmt::Display for MoneyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MoneyError::CurrencyMismatch(left, right) => {
+                write!(f, "can't combine amounts in {} and {}", left, right)
+            }
+            MoneyError::Overflow => write!(f, "amount is out of range"),
+            MoneyError::InvalidAmount(amount) => write!(f, "invalid amount {:?}", amount),
+        }
+    }
+}
+
+impl std::error::Error for MoneyError {}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Number of digits after the decimal point in ISO 4217, 2 unless listed.
+pub fn minor_digits(currency: &str) -> u32 {
+    match currency {
+        "BIF" | "CLP" | "DJF" | "GNF" | "ISK" | "JPY" | "KMF" | "KRW" | "PYG" | "RWF" | "UGX"
+        | "VND" | "VUV" | "XAF" | "XOF" | "XPF" => 0,
+        "BHD" | "IQD" | "JOD" | "KWD" | "LYD" | "OMR" | "TND" => 3,
+        _ => 2,
+    }
+}
+
+// Currency codes read from storage are leaked once and shared afterwards, so
+// decoding a row doesn't allocate for every amount.
+pub fn currency(code: &str) -> &'static str {
+    static CODES: OnceLock<Mutex<HashSet<&'static str>>> = OnceLock::new();
+
+    let mut codes = CODES
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    match codes.get(code) {
+        Some(code) => code,
+        None => {
+            let code: &'static str = Box::leak(code.to_string().into_boxed_str());
+            codes.insert(code);
+            code
+        }
+    }
+}
+
+// Validator of single column fields, which can only hold their declared currency.
+pub fn check_currency<T: MoneyField>(value: &T, currency: &str) -> Result<(), String> {
+    match value.currency() {
+        Some(found) if found != currency => {
+            Err(format!("currency must be {}, got {}", currency, found))
+        }
+        _ => Ok(()),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub trait MoneyField: Sized {
+    const NULLABLE: bool;
+
+    fn minor_units_value(&self) -> Value<'_>;
+    fn currency_value(&self) -> Value<'_>;
+    fn currency(&self) -> Option<&'static str>;
+    fn try_from_values(
+        minor_units: &Value<'_>,
+        currency: &Value<'_>,
+    ) -> Result<Self, ConversionError>;
+
+    fn from_values(minor_units: &Value<'_>, currency: &Value<'_>) -> Self {
+        converted(Self::try_from_values(minor_units, currency))
+    }
+}
+
+impl MoneyField for Money {
+    const NULLABLE: bool = false;
+
+    fn minor_units_value(&self) -> Value<'_> {
+        Value::Int64(self.minor_units)
+    }
+
+    fn currency_value(&self) -> Value<'_> {
+        Value::String(Cow::Borrowed(self.currency))
+    }
+
+    fn currency(&self) -> Option<&'static str> {
+        Some(self.currency)
+    }
+
+    fn try_from_values(
+        minor_units: &Value<'_>,
+        currency: &Value<'_>,
+    ) -> Result<Self, ConversionError> {
+        let Value::String(code) = currency else {
+            return Err(ConversionError::unexpected(DataType::String, currency).at_offset(1));
+        };
+        Ok(Self::new(
+            i64::try_from_value(minor_units)?,
+            self::currency(code),
+        ))
+    }
+}
+
+impl MoneyField for Option<Money> {
+    const NULLABLE: bool = true;
+
+    fn minor_units_value(&self) -> Value<'_> {
+        self.as_ref().map_or(Value::Null, Money::minor_units_value)
+    }
+
+    fn currency_value(&self) -> Value<'_> {
+        self.as_ref().map_or(Value::Null, Money::currency_value)
+    }
+
+    fn currency(&self) -> Option<&'static str> {
+        self.map(|money| money.currency)
+    }
+
+    fn try_from_values(
+        minor_units: &Value<'_>,
+        currency: &Value<'_>,
+    ) -> Result<Self, ConversionError> {
+        match minor_units {
+            Value::Null => Ok(None),
+            minor_units => Money::try_from_values(minor_units, currency).map(Some),
+        }
+    }
+}
@@ -1,15 +1,68 @@
 #![forbid(unsafe_code)]
-use crate::{data::DataType, storage::Row};
-use std::any::Any;
+use crate::{
+    data::{converted, BoolEncoding, ConversionError, DataType, Value, ValueConvert},
+    error::{Error, UnexpectedTypeError},
+    format::TextFormat,
+    storage::Row,
+    validate::ValidationError,
+    ObjectId,
+};
+use std::any::{Any, TypeId};
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
+use std::sync::{Mutex, OnceLock};
 
 ////////////////////////////////////////////////////////////////////////////////
 
 pub trait Object: Any + Sized {
     fn schema() -> &'static Schema;
 
-    fn from_row(row: Row<'_>) -> Self;
+    // Transactions build objects with this, so values that don't convert are
+    // reported as errors instead of panics. Derived objects check every field.
+    fn try_from_row(row: Row<'_>) -> crate::Result<Self>;
     fn to_row(&self) -> Row<'_>;
+
+    fn from_row(row: Row<'_>) -> Self {
+        Self::try_from_row(row).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    fn set_id(&mut self, _id: ObjectId) {}
+
+    fn validate(&self) -> Result<(), ValidationError> {
+        Ok(())
+    }
+}
+
+// A read-only projection of an object whose fields may borrow from the
+// storage row, see Transaction::with_view.
+pub trait ObjectView {
+    type Object: Object;
+    type View<'r>;
+
+    fn try_from_row(row: Row<'_>) -> crate::Result<Self::View<'_>>;
+
+    fn from_row(row: Row<'_>) -> Self::View<'_> {
+        Self::try_from_row(row).unwrap_or_else(|e| panic!("{}", e))
+    }
+}
+
+pub trait Expiring: Object {
+    const EXPIRES_COLUMN: &'static str;
+}
+
+// Objects linked into a hierarchy by a nullable parent id column.
+pub trait Tree: Object {
+    const PARENT_COLUMN: &'static str;
+}
+
+// A partial update generated by #[orm(patch)]: fields left as None keep
+// their current value, see Tx::apply.
+pub trait Patch {
+    type Object: Object;
+
+    fn is_empty(&self) -> bool;
+    fn apply_to(self, object: &mut Self::Object);
 }
 
 pub trait Store: Any {
@@ -18,6 +71,8 @@ pub trait Store: Any {
     fn as_mut_any(&mut self) -> &mut dyn Any;
 
     fn to_row(&self) -> Row<'_>;
+
+    fn validate(&self) -> Result<(), ValidationError>;
 }
 
 impl<T: Object> Store for T {
@@ -32,6 +87,10 @@ impl<T: Object> Store for T {
     fn to_row(&self) -> Row<'_> {
         self.to_row()
     }
+
+    fn validate(&self) -> Result<(), ValidationError> {
+        Object::validate(self)
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -40,12 +99,271 @@ impl<T: Object> Store for T {
 pub struct Schema {
     pub table_name: &'static str,
     pub type_name: &'static str,
-    pub columns: &'static [(&'static str, DataType)],
+    pub columns: &'static [ColumnDef],
     pub attrs: &'static [&'static str],
+    pub strict: bool,
+    pub without_rowid: bool,
+    // Keeps a `_rev` column bumped on every write, see Transaction::changed_since.
+    pub revisions: bool,
+    pub indexes: &'static [IndexDef],
+}
+
+impl Schema {
+    pub fn column_pairs(&self) -> impl Iterator<Item = (&'static str, DataType)> {
+        self.columns.iter().map(ColumnDef::as_pair)
+    }
+
+    pub fn column(&self, name: &str) -> Option<&'static ColumnDef> {
+        self.columns.iter().find(|column| column.name == name)
+    }
+
+    // Split columns live in secondary tables that share the object's id.
+    pub fn split_tables(&self) -> Vec<&'static str> {
+        let mut tables = Vec::new();
+        for table in self.columns.iter().filter_map(|column| column.split_table) {
+            if !tables.contains(&table) {
+                tables.push(table);
+            }
+        }
+        tables
+    }
+
+    pub fn table_of(&self, column: &ColumnDef) -> &'static str {
+        column.split_table.unwrap_or(self.table_name)
+    }
+
+    pub fn columns_in<'s>(
+        &'s self,
+        table: &'s str,
+    ) -> impl Iterator<Item = (usize, &'static ColumnDef)> + 's {
+        self.columns
+            .iter()
+            .enumerate()
+            .filter(move |(_, column)| self.table_of(column) == table)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ColumnDef {
+    pub name: &'static str,
+    pub data_type: DataType,
+    pub nullable: bool,
+    pub unique: bool,
+    pub primary_key: bool,
+    pub default: Option<&'static str>,
+    pub renamed_from: Option<&'static str>,
+    pub redacted: bool,
+    pub encrypted: bool,
+    pub external_blob: bool,
+    pub checksum_of: Option<&'static str>,
+    pub split_table: Option<&'static str>,
+    pub interned: bool,
+    // Fixed currency of a Money field stored without a currency column.
+    pub currency: Option<&'static str>,
+    pub format: Option<TextFormat>,
+    pub bool_encoding: Option<BoolEncoding>,
+}
+
+impl ColumnDef {
+    pub const fn new(name: &'static str, data_type: DataType) -> Self {
+        Self {
+            name,
+            data_type,
+            nullable: false,
+            unique: false,
+            primary_key: false,
+            default: None,
+            renamed_from: None,
+            redacted: false,
+            encrypted: false,
+            external_blob: false,
+            checksum_of: None,
+            split_table: None,
+            interned: false,
+            currency: None,
+            format: None,
+            bool_encoding: None,
+        }
+    }
+
+    pub fn as_pair(&self) -> (&'static str, DataType) {
+        (self.name, self.data_type)
+    }
+
+    // Interned strings are stored as references into a shared lookup table.
+    pub fn stored_type(&self) -> DataType {
+        match self.bool_encoding {
+            _ if self.interned => DataType::Int64,
+            Some(encoding) => encoding.stored_type(),
+            None => self.data_type,
+        }
+    }
+}
+
+// Revisions come from a per-table sequence, so they only ever grow even when
+// the latest row is deleted. Rows written before the column existed have 0.
+pub const REVISION_COLUMN: ColumnDef = ColumnDef {
+    default: Some("0"),
+    ..ColumnDef::new("_rev", DataType::Int64)
+};
+
+pub const REVISION_INDEX: IndexDef = IndexDef {
+    name: None,
+    columns: &[REVISION_COLUMN.name],
+    expressions: &[],
+    unique: false,
+    predicate: None,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IndexDef {
+    pub name: Option<&'static str>,
+    pub columns: &'static [&'static str],
+    pub expressions: &'static [&'static str],
+    pub unique: bool,
+    pub predicate: Option<&'static str>,
 }
 
+////////////////////////////////////////////////////////////////////////////////
+
+// A reference to an object of any type, stored as the target's table name and
+// id in two columns. Connection::register makes the target type loadable.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PolyRef {
+    pub table_name: String,
+    pub id: ObjectId,
+}
+
+impl PolyRef {
+    pub fn new<T: Object>(id: ObjectId) -> Self {
+        Self {
+            table_name: T::schema().table_name.to_string(),
+            id,
+        }
+    }
+
+    pub fn points_to<T: Object>(&self) -> bool {
+        self.table_name == T::schema().table_name
+    }
+}
+
+pub trait PolyRefField: Sized {
+    const NULLABLE: bool;
+
+    fn table_value(&self) -> Value<'_>;
+    fn id_value(&self) -> Value<'_>;
+    fn try_from_values(table: &Value<'_>, id: &Value<'_>) -> Result<Self, ConversionError>;
+
+    fn from_values(table: &Value<'_>, id: &Value<'_>) -> Self {
+        converted(Self::try_from_values(table, id))
+    }
+}
+
+impl PolyRefField for PolyRef {
+    const NULLABLE: bool = false;
+
+    fn table_value(&self) -> Value<'_> {
+        Value::String(Cow::Borrowed(&self.table_name))
+    }
+
+    fn id_value(&self) -> Value<'_> {
+        Value::Int64(self.id.into_i64())
+    }
+
+    fn try_from_values(table: &Value<'_>, id: &Value<'_>) -> Result<Self, ConversionError> {
+        Ok(Self {
+            table_name: String::try_from_value(table)?,
+            id: ObjectId::new(i64::try_from_value(id).map_err(|e| e.at_offset(1))?),
+        })
+    }
+}
+
+impl PolyRefField for Option<PolyRef> {
+    const NULLABLE: bool = true;
+
+    fn table_value(&self) -> Value<'_> {
+        self.as_ref().map_or(Value::Null, PolyRef::table_value)
+    }
+
+    fn id_value(&self) -> Value<'_> {
+        self.as_ref().map_or(Value::Null, PolyRef::id_value)
+    }
+
+    fn try_from_values(table: &Value<'_>, id: &Value<'_>) -> Result<Self, ConversionError> {
+        match table {
+            Value::Null => Ok(None),
+            table => PolyRef::try_from_values(table, id).map(Some),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
 impl Hash for Schema {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.table_name.hash(state);
     }
 }
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Schemas of generic objects depend on their type parameters and can't be
+// promoted to statics, so each instantiation is built and leaked once.
+pub fn schema_for<T: Any>(build: impl FnOnce() -> Schema) -> &'static Schema {
+    static SCHEMAS: OnceLock<Mutex<HashMap<TypeId, &'static Schema>>> = OnceLock::new();
+
+    let mut schemas = SCHEMAS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    schemas
+        .entry(TypeId::of::<T>())
+        .or_insert_with(|| Box::leak(Box::new(build())))
+}
+
+pub fn type_param_name<T: ?Sized>() -> String {
+    std::any::type_name::<T>()
+        .split(|c: char| !(c.is_alphanumeric() || c == '_' || c == ':'))
+        .filter_map(|path| path.rsplit("::").next())
+        .filter(|name| !name.is_empty())
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+// The error of a column value that doesn't convert to its field type.
+pub fn conversion_error(schema: &Schema, index: usize, error: ConversionError) -> Error {
+    let index = index + error.offset;
+    let column = &schema.columns[index];
+    Error::UnexpectedType(Box::new(UnexpectedTypeError {
+        type_name: schema.type_name,
+        attr_name: schema.attrs[index],
+        table_name: schema.table_of(column),
+        column_name: column.name,
+        expected_type: column.data_type,
+        got_type: error.got,
+    }))
+}
+
+// Hands a view field the value of its column, which no other field of the
+// view may claim. Views are checked against their object only at runtime.
+pub fn take_view_value<'r>(
+    schema: &'static Schema,
+    values: &mut [Option<Value<'r>>],
+    name: &str,
+) -> crate::Result<(usize, Value<'r>)> {
+    let Some(i) = schema.columns.iter().position(|column| column.name == name) else {
+        let message = format!("view field names no column '{}'", name);
+        return Err(Error::invalid_request(schema.type_name, message));
+    };
+    match values.get_mut(i).and_then(Option::take) {
+        Some(value) => Ok((i, value)),
+        None => {
+            let message = format!("more than one view field reads column '{}'", name);
+            Err(Error::invalid_request(schema.type_name, message))
+        }
+    }
+}
+
+pub fn leak_name(name: String) -> &'static str {
+    Box::leak(name.into_boxed_str())
+}
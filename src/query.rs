@@ -0,0 +1,431 @@
+#![forbid(unsafe_code)]
+
+use std::marker::PhantomData;
+
+use crate::{
+    data::{Value, ValueConvert},
+    money::Money,
+    object::Object,
+    Error, Result, Scan, Transaction, Tx,
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Like,
+    // Compares `lower(column)`, which an index over that expression can serve.
+    EqIgnoreCase,
+}
+
+impl Op {
+    pub fn sql(&self) -> &'static str {
+        match self {
+            Op::Eq => "=",
+            Op::Ne => "<>",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+            Op::Like => "LIKE",
+            Op::EqIgnoreCase => "=",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Filter {
+    pub column: &'static str,
+    pub op: Op,
+    pub value: Value<'static>,
+}
+
+// One hop of a recursive query: from each row found so far to the rows whose
+// `column` equals that row's `previous` column. Either may be "id".
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Step {
+    pub column: &'static str,
+    pub previous: &'static str,
+}
+
+impl Step {
+    pub fn new(column: &'static str, previous: &'static str) -> Self {
+        Self { column, previous }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WindowFunction {
+    RowNumber,
+    Rank,
+    // The column's value `offset` rows before or after, in window order.
+    Lag(&'static str, usize),
+    Lead(&'static str, usize),
+}
+
+// A window function evaluated alongside each fetched object, optionally
+// filtered on its value, e.g. `row_number() = 1` for the first row per group.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Window {
+    pub function: WindowFunction,
+    pub partition_by: Vec<&'static str>,
+    // Columns with whether they sort descending.
+    pub order_by: Vec<(&'static str, bool)>,
+    pub filter: Option<(Op, Value<'static>)>,
+}
+
+impl Window {
+    pub fn new(function: WindowFunction) -> Self {
+        Self {
+            function,
+            partition_by: Vec::new(),
+            order_by: Vec::new(),
+            filter: None,
+        }
+    }
+
+    pub fn row_number() -> Self {
+        Self::new(WindowFunction::RowNumber)
+    }
+
+    pub fn rank() -> Self {
+        Self::new(WindowFunction::Rank)
+    }
+
+    pub fn lag(column: &'static str, offset: usize) -> Self {
+        Self::new(WindowFunction::Lag(column, offset))
+    }
+
+    pub fn lead(column: &'static str, offset: usize) -> Self {
+        Self::new(WindowFunction::Lead(column, offset))
+    }
+
+    pub fn partition_by(mut self, column: &'static str) -> Self {
+        self.partition_by.push(column);
+        self
+    }
+
+    pub fn order_by(mut self, column: &'static str) -> Self {
+        self.order_by.push((column, false));
+        self
+    }
+
+    pub fn order_by_desc(mut self, column: &'static str) -> Self {
+        self.order_by.push((column, true));
+        self
+    }
+
+    pub fn filter<V: ValueConvert>(mut self, op: Op, value: V) -> Self {
+        self.filter = Some((op, value.to_value()));
+        self
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sample {
+    // Up to this many rows, picked uniformly.
+    Count(usize),
+    // Each row independently with this probability.
+    Fraction(f64),
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Overrides the planner's choice of index on the object's table.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IndexHint {
+    Use(String),
+    NoIndex,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub struct Query<T> {
+    filters: Vec<Filter>,
+    limit: Option<usize>,
+    recursive: Option<(Vec<Filter>, Step)>,
+    sample: Option<Sample>,
+    index_hint: Option<IndexHint>,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Object> Query<T> {
+    pub fn new() -> Self {
+        Self {
+            filters: Vec::new(),
+            limit: None,
+            recursive: None,
+            sample: None,
+            index_hint: None,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn filter<V: ValueConvert>(mut self, column: &'static str, op: Op, value: V) -> Self {
+        self.filters.push(Filter {
+            column,
+            op,
+            value: value.to_value(),
+        });
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    // Starts from the rows matching `seed` and follows `step` from there. The
+    // query's own filters and limit then apply to everything reached.
+    pub fn recursive(mut self, seed: Query<T>, step: Step) -> Self {
+        self.recursive = Some((seed.filters, step));
+        self
+    }
+
+    pub fn sample(mut self, count: usize) -> Self {
+        self.sample = Some(Sample::Count(count));
+        self
+    }
+
+    pub fn sample_fraction(mut self, fraction: f64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&fraction),
+            "sample fraction must be between 0 and 1, got {}",
+            fraction
+        );
+        self.sample = Some(Sample::Fraction(fraction));
+        self
+    }
+
+    pub fn use_index(mut self, name: &str) -> Self {
+        self.index_hint = Some(IndexHint::Use(name.to_string()));
+        self
+    }
+
+    pub fn no_index(mut self) -> Self {
+        self.index_hint = Some(IndexHint::NoIndex);
+        self
+    }
+
+    pub fn filters(&self) -> &[Filter] {
+        &self.filters
+    }
+
+    pub fn fetch<'t>(&self, tx: &'t Transaction<'_>) -> Result<Vec<Tx<'t, T>>> {
+        if self.index_hint.is_some() && (self.recursive.is_some() || self.sample.is_some()) {
+            return Err(Self::invalid(
+                "index hints are only supported on plain queries",
+            ));
+        }
+        match (&self.recursive, self.sample) {
+            (Some(_), Some(_)) => Err(Self::invalid("sampling recursive queries is not supported")),
+            (Some((seed, step)), None) => {
+                tx.fetch_recursive(seed, *step, &self.filters, self.limit)
+            }
+            (None, Some(sample)) => tx.fetch_sample(&self.filters, sample, self.limit),
+            (None, None) => tx.fetch(&self.filters, self.index_hint.as_ref(), self.limit),
+        }
+    }
+
+    // Like fetch, but lazily: objects are read a page at a time and evicted
+    // once passed over, so memory stays flat however many rows match.
+    pub fn scan<'t, 'a>(&self, tx: &'t Transaction<'a>) -> Result<Scan<'t, 'a, T>> {
+        if self.recursive.is_some() || self.sample.is_some() {
+            return Err(Self::invalid("scans are only supported on plain queries"));
+        }
+        if self.index_hint.is_some() {
+            return Err(Self::invalid(
+                "index hints are only supported on plain queries",
+            ));
+        }
+        Ok(tx.scan_where(self.filters.clone(), self.limit))
+    }
+
+    // Each object comes with the window function's value for its row.
+    pub fn fetch_window<'t>(
+        &self,
+        tx: &'t Transaction<'_>,
+        window: &Window,
+    ) -> Result<Vec<(Tx<'t, T>, Value<'static>)>> {
+        if self.recursive.is_some() {
+            return Err(Self::invalid(
+                "window functions over recursive queries are not supported",
+            ));
+        }
+        if self.sample.is_some() {
+            return Err(Self::invalid(
+                "window functions over sampled queries are not supported",
+            ));
+        }
+        if self.index_hint.is_some() {
+            return Err(Self::invalid(
+                "index hints are only supported on plain queries",
+            ));
+        }
+        tx.fetch_window(&self.filters, window, self.limit)
+    }
+
+    // Totals of a Money field over the matching objects, one per currency.
+    pub fn sum_money(&self, tx: &Transaction<'_>, column: &str) -> Result<Vec<Money>> {
+        if self.recursive.is_some() || self.sample.is_some() || self.limit.is_some() {
+            return Err(Self::invalid(
+                "sums are only supported on unlimited plain queries",
+            ));
+        }
+        if self.index_hint.is_some() {
+            return Err(Self::invalid(
+                "index hints are only supported on plain queries",
+            ));
+        }
+        tx.sum_money::<T>(&self.filters, column)
+    }
+
+    fn invalid(message: &str) -> Error {
+        Error::invalid_request(T::schema().type_name, message)
+    }
+}
+
+impl<T: Object> Default for Query<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// A column of T's table holding values of V, so conditions on it only take
+// values of the field's type. #[orm(query)] generates one per field, e.g.
+// `User::columns().age.gt(18)`.
+pub struct Column<T, V> {
+    name: &'static str,
+    _marker: PhantomData<fn() -> (T, V)>,
+}
+
+impl<T, V> Clone for Column<T, V> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T, V> Copy for Column<T, V> {}
+
+impl<T: Object, V: ValueConvert> Column<T, V> {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn condition(&self, op: Op, value: impl Into<V>) -> Condition<T> {
+        Condition {
+            filter: Filter {
+                column: self.name,
+                op,
+                value: value.into().to_value(),
+            },
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn eq(&self, value: impl Into<V>) -> Condition<T> {
+        self.condition(Op::Eq, value)
+    }
+
+    pub fn ne(&self, value: impl Into<V>) -> Condition<T> {
+        self.condition(Op::Ne, value)
+    }
+
+    pub fn lt(&self, value: impl Into<V>) -> Condition<T> {
+        self.condition(Op::Lt, value)
+    }
+
+    pub fn le(&self, value: impl Into<V>) -> Condition<T> {
+        self.condition(Op::Le, value)
+    }
+
+    pub fn gt(&self, value: impl Into<V>) -> Condition<T> {
+        self.condition(Op::Gt, value)
+    }
+
+    pub fn ge(&self, value: impl Into<V>) -> Condition<T> {
+        self.condition(Op::Ge, value)
+    }
+}
+
+impl<T: Object> Column<T, String> {
+    pub fn like(&self, pattern: &str) -> Condition<T> {
+        self.condition(Op::Like, pattern)
+    }
+
+    pub fn eq_ignore_case(&self, value: &str) -> Condition<T> {
+        self.condition(Op::EqIgnoreCase, value)
+    }
+}
+
+// A filter on one of T's columns, see Column.
+pub struct Condition<T> {
+    filter: Filter,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Condition<T> {
+    pub fn into_filter(self) -> Filter {
+        self.filter
+    }
+}
+
+// Objects of T matching all conditions, see Transaction::select.
+pub struct Select<'t, 'a, T> {
+    tx: &'t Transaction<'a>,
+    query: Query<T>,
+}
+
+impl<'t, 'a, T: Object> Select<'t, 'a, T> {
+    pub(crate) fn new(tx: &'t Transaction<'a>) -> Self {
+        Self {
+            tx,
+            query: Query::new(),
+        }
+    }
+
+    pub fn filter(mut self, condition: Condition<T>) -> Self {
+        self.query.filters.push(condition.filter);
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.query = self.query.limit(limit);
+        self
+    }
+
+    pub fn all(self) -> Result<Vec<Tx<'t, T>>> {
+        self.query.fetch(self.tx)
+    }
+
+    pub fn iter(self) -> Scan<'t, 'a, T> {
+        self.tx.scan_where(self.query.filters, self.query.limit)
+    }
+
+    pub fn first(self) -> Result<Option<Tx<'t, T>>> {
+        Ok(self.limit(1).all()?.pop())
+    }
+
+    pub fn into_query(self) -> Query<T> {
+        self.query
+    }
+}
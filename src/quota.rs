@@ -0,0 +1,73 @@
+#![forbid(unsafe_code)]
+
+use std::fmt;
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Quota {
+    pub max_rows: Option<u64>,
+    pub max_bytes: Option<u64>,
+}
+
+impl Quota {
+    pub fn rows(max_rows: u64) -> Self {
+        Self {
+            max_rows: Some(max_rows),
+            max_bytes: None,
+        }
+    }
+
+    pub fn bytes(max_bytes: u64) -> Self {
+        Self {
+            max_rows: None,
+            max_bytes: Some(max_bytes),
+        }
+    }
+
+    pub fn is_exceeded(&self, usage: TableUsage) -> bool {
+        self.max_rows.is_some_and(|max| usage.rows > max)
+            || self.max_bytes.is_some_and(|max| usage.bytes > max)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TableUsage {
+    pub rows: u64,
+    pub bytes: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QuotaAction {
+    Reject,
+    DeleteOldest(usize),
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QuotaError {
+    pub table_name: &'static str,
+    pub quota: Quota,
+    pub usage: TableUsage,
+}
+
+impl fmt::Display for QuotaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "quota exceeded for table {}: {} rows, ~{} bytes",
+            self.table_name, self.usage.rows, self.usage.bytes
+        )?;
+        let limits = [
+            ("rows", self.quota.max_rows),
+            ("bytes", self.quota.max_bytes),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| Some(format!("max {}: {}", key, value?)))
+        .collect::<Vec<_>>();
+        write!(f, " ({})", limits.join(", "))
+    }
+}
+
+impl std::error::Error for QuotaError {}
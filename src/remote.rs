@@ -0,0 +1,1621 @@
+#![forbid(unsafe_code)]
+
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+
+use crate::{
+    cipher,
+    connection::{self, Settings, StorageConnection},
+    data::{BoolEncoding, Coercion, DataType, TextDecoding, Value},
+    error::{
+        ConstraintError, Error, InvalidTextError, MissingColumnError, NotFoundError, Result,
+        UnexpectedTypeError, UnsupportedError,
+    },
+    format::TextFormat,
+    object::{ColumnDef, IndexDef, Schema},
+    query::{Filter, IndexHint, Op, Sample, Step, Window, WindowFunction},
+    quota::TableUsage,
+    storage::{self, ColumnInfo, Row, RowSlice, SqliteTransaction, StorageTransaction},
+    Object, ObjectId,
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+const OP_BEGIN: u8 = 0;
+const OP_TABLE_EXISTS: u8 = 1;
+const OP_CREATE_TABLE: u8 = 2;
+const OP_INSERT: u8 = 3;
+const OP_UPDATE: u8 = 4;
+const OP_SELECT: u8 = 5;
+const OP_DELETE: u8 = 6;
+const OP_COMMIT: u8 = 7;
+const OP_ROLLBACK: u8 = 8;
+const OP_TABLE_COLUMNS: u8 = 9;
+const OP_TABLE_NAMES: u8 = 10;
+const OP_SELECT_PAGE: u8 = 11;
+const OP_SELECT_ROWS: u8 = 12;
+const OP_DEFER_FOREIGN_KEYS: u8 = 13;
+const OP_SELECT_IDS: u8 = 14;
+const OP_HAS_ROW: u8 = 15;
+const OP_DELETE_EXPIRED: u8 = 16;
+const OP_TABLE_USAGE: u8 = 18;
+const OP_INCREMENT: u8 = 19;
+const OP_LOCK_ROW: u8 = 20;
+const OP_NEXT_IN_SEQUENCE: u8 = 21;
+const OP_PUT_BLOB: u8 = 22;
+const OP_GET_BLOB: u8 = 23;
+const OP_DELETE_UNREFERENCED_BLOBS: u8 = 24;
+const OP_SELECT_RECURSIVE: u8 = 25;
+const OP_SELECT_WINDOW: u8 = 26;
+const OP_SELECT_SAMPLE: u8 = 27;
+const OP_ESTIMATE_ROWS: u8 = 28;
+const OP_INTERN: u8 = 29;
+const OP_INTERNED_VALUE: u8 = 30;
+const OP_SUM_COLUMN: u8 = 31;
+const OP_SELECT_CHANGED: u8 = 32;
+const OP_SAVEPOINT: u8 = 33;
+const OP_RELEASE_SAVEPOINT: u8 = 34;
+const OP_ROLLBACK_TO_SAVEPOINT: u8 = 35;
+const OP_INSERT_ROWS: u8 = 36;
+const OP_EXECUTE_DDL: u8 = 37;
+const OP_APPLIED_MIGRATIONS: u8 = 38;
+const OP_RECORD_MIGRATION: u8 = 39;
+const OP_MERGE_STAGED: u8 = 40;
+const OP_CREATE_TEMP_TABLE: u8 = 41;
+const OP_DROP_TEMP_TABLE: u8 = 42;
+
+// Stands for the implicit `id` where columns are sent by position.
+const ID_COLUMN_INDEX: u32 = u32::MAX;
+
+const STATUS_OK: u8 = 0;
+const STATUS_NOT_FOUND: u8 = 1;
+const STATUS_UNEXPECTED_TYPE: u8 = 2;
+const STATUS_MISSING_COLUMN: u8 = 3;
+const STATUS_LOCK_CONFLICT: u8 = 4;
+const STATUS_STORAGE: u8 = 5;
+const STATUS_UNIQUE_VIOLATION: u8 = 6;
+const STATUS_FOREIGN_KEY_VIOLATION: u8 = 7;
+const STATUS_CHECK_VIOLATION: u8 = 8;
+const STATUS_NOT_NULL_VIOLATION: u8 = 9;
+const STATUS_INVALID_TEXT: u8 = 10;
+const STATUS_UNSUPPORTED: u8 = 11;
+
+const NO_COLUMN: u32 = u32::MAX;
+
+const FILTER_OPS: [Op; 8] = [
+    Op::Eq,
+    Op::Ne,
+    Op::Lt,
+    Op::Le,
+    Op::Gt,
+    Op::Ge,
+    Op::Like,
+    Op::EqIgnoreCase,
+];
+
+const MAX_FRAME_LEN: usize = 64 << 20;
+
+fn io_error(err: std::io::Error) -> Error {
+    Error::Storage(Box::new(err))
+}
+
+fn protocol_error(msg: &str) -> Error {
+    Error::Storage(format!("remote protocol error: {}", msg).into())
+}
+
+fn write_frame(mut stream: &TcpStream, payload: &[u8]) -> Result<()> {
+    let len = u32::try_from(payload.len()).map_err(|_| protocol_error("frame is too large"))?;
+    stream.write_all(&len.to_le_bytes()).map_err(io_error)?;
+    stream.write_all(payload).map_err(io_error)?;
+    stream.flush().map_err(io_error)
+}
+
+fn read_frame(mut stream: &TcpStream) -> Result<Vec<u8>> {
+    let mut len = [0; 4];
+    stream.read_exact(&mut len).map_err(io_error)?;
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(protocol_error("frame is too large"));
+    }
+    let mut payload = vec![0; len];
+    stream.read_exact(&mut payload).map_err(io_error)?;
+    Ok(payload)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Default)]
+struct Encoder(Vec<u8>);
+
+impl Encoder {
+    fn u8(&mut self, v: u8) -> &mut Self {
+        self.0.push(v);
+        self
+    }
+
+    fn u32(&mut self, v: u32) -> &mut Self {
+        self.0.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    fn i64(&mut self, v: i64) -> &mut Self {
+        self.0.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    fn bytes(&mut self, v: &[u8]) -> &mut Self {
+        self.u32(v.len() as u32);
+        self.0.extend_from_slice(v);
+        self
+    }
+
+    fn str(&mut self, v: &str) -> &mut Self {
+        self.bytes(v.as_bytes())
+    }
+
+    fn id(&mut self, id: ObjectId) -> &mut Self {
+        self.i64(id.into_i64())
+    }
+
+    fn value(&mut self, v: &Value) -> &mut Self {
+        match v {
+            Value::String(s) => self.u8(0).str(s),
+            Value::Bytes(b) => self.u8(1).bytes(b),
+            Value::Int64(i) => self.u8(2).i64(*i),
+            Value::Float64(f) => self.u8(3).i64(f.to_bits() as i64),
+            Value::Bool(b) => self.u8(4).u8(*b as u8),
+            Value::Null => self.u8(5),
+        }
+    }
+
+    fn row(&mut self, row: &RowSlice) -> &mut Self {
+        self.u32(row.len() as u32);
+        for value in row {
+            self.value(value);
+        }
+        self
+    }
+
+    fn column_ref(&mut self, schema: &Schema, name: &str) -> Result<&mut Self> {
+        Ok(match name {
+            "id" => self.u32(ID_COLUMN_INDEX),
+            name => self.u32(column_index(schema, name)?),
+        })
+    }
+
+    fn filters(&mut self, schema: &Schema, filters: &[Filter]) -> Result<&mut Self> {
+        self.u32(filters.len() as u32);
+        for filter in filters {
+            cipher::check_filter(schema, filter)?;
+            let op = FILTER_OPS.iter().position(|op| *op == filter.op).unwrap();
+            self.column_ref(schema, filter.column)?
+                .u8(op as u8)
+                .value(&filter.value);
+        }
+        Ok(self)
+    }
+
+    fn window(&mut self, schema: &Schema, window: &Window) -> Result<&mut Self> {
+        match window.function {
+            WindowFunction::RowNumber => self.u8(0),
+            WindowFunction::Rank => self.u8(1),
+            WindowFunction::Lag(column, offset) => {
+                self.u8(2).column_ref(schema, column)?.u32(offset as u32)
+            }
+            WindowFunction::Lead(column, offset) => {
+                self.u8(3).column_ref(schema, column)?.u32(offset as u32)
+            }
+        };
+        self.u32(window.partition_by.len() as u32);
+        for column in &window.partition_by {
+            self.column_ref(schema, column)?;
+        }
+        self.u32(window.order_by.len() as u32);
+        for (column, desc) in &window.order_by {
+            self.column_ref(schema, column)?.u8(*desc as u8);
+        }
+        Ok(match &window.filter {
+            Some((op, value)) => {
+                let op = FILTER_OPS.iter().position(|o| o == op).unwrap();
+                self.u8(1).u8(op as u8).value(value)
+            }
+            None => self.u8(0),
+        })
+    }
+
+    fn index_hint(&mut self, hint: Option<&IndexHint>) -> &mut Self {
+        match hint {
+            None => self.u8(0),
+            Some(IndexHint::Use(name)) => self.u8(1).str(name),
+            Some(IndexHint::NoIndex) => self.u8(2),
+        }
+    }
+
+    fn sample(&mut self, sample: Sample) -> &mut Self {
+        match sample {
+            Sample::Count(n) => self.u8(0).i64(n as i64),
+            Sample::Fraction(fraction) => self.u8(1).i64(fraction.to_bits() as i64),
+        }
+    }
+
+    fn column_infos(&mut self, columns: &[ColumnInfo]) -> &mut Self {
+        self.u32(columns.len() as u32);
+        for column in columns {
+            self.str(&column.name)
+                .str(&column.sql_type)
+                .u8(column.not_null as u8 | (column.primary_key as u8) << 1);
+            match &column.default {
+                Some(default) => self.u8(1).str(default),
+                None => self.u8(0),
+            };
+        }
+        self
+    }
+
+    fn schema(&mut self, schema: &Schema) -> &mut Self {
+        self.str(schema.table_name)
+            .str(schema.type_name)
+            .u8(schema.strict as u8
+                | (schema.without_rowid as u8) << 1
+                | (schema.revisions as u8) << 2);
+        self.u32(schema.columns.len() as u32);
+        for (column, attr) in schema.columns.iter().zip(schema.attrs) {
+            self.column(column).str(attr);
+        }
+        self.u32(schema.indexes.len() as u32);
+        for index in schema.indexes {
+            self.index(index);
+        }
+        self
+    }
+
+    fn index(&mut self, index: &IndexDef) -> &mut Self {
+        self.u8(index.unique as u8);
+        for value in [index.name, index.predicate] {
+            match value {
+                Some(value) => self.u8(1).str(value),
+                None => self.u8(0),
+            };
+        }
+        for keys in [index.columns, index.expressions] {
+            self.u32(keys.len() as u32);
+            for key in keys {
+                self.str(key);
+            }
+        }
+        self
+    }
+
+    fn column(&mut self, column: &ColumnDef) -> &mut Self {
+        let flags = column.nullable as u8
+            | (column.unique as u8) << 1
+            | (column.primary_key as u8) << 2
+            | (column.redacted as u8) << 3
+            | (column.encrypted as u8) << 4
+            | (column.external_blob as u8) << 5
+            | (column.interned as u8) << 6;
+        self.str(column.name)
+            .u8(data_type_tag(column.data_type))
+            .u8(flags)
+            .u8(format_tag(column.format))
+            .u8(bool_encoding_tag(column.bool_encoding));
+        for value in [
+            column.default,
+            column.renamed_from,
+            column.checksum_of,
+            column.split_table,
+            column.currency,
+        ] {
+            match value {
+                Some(value) => self.u8(1).str(value),
+                None => self.u8(0),
+            };
+        }
+        self
+    }
+}
+
+struct Decoder<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.buf.len() < len {
+            return Err(protocol_error("truncated frame"));
+        }
+        let (head, tail) = self.buf.split_at(len);
+        self.buf = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn i64(&mut self) -> Result<i64> {
+        Ok(i64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    fn string(&mut self) -> Result<String> {
+        String::from_utf8(self.bytes()?.to_vec()).map_err(|_| protocol_error("invalid utf-8"))
+    }
+
+    fn id(&mut self) -> Result<ObjectId> {
+        self.i64().map(ObjectId::new)
+    }
+
+    fn value(&mut self) -> Result<Value<'static>> {
+        Ok(match self.u8()? {
+            0 => Value::String(Cow::Owned(self.string()?)),
+            1 => Value::Bytes(Cow::Owned(self.bytes()?.to_vec())),
+            2 => Value::Int64(self.i64()?),
+            3 => Value::Float64(f64::from_bits(self.i64()? as u64)),
+            4 => Value::Bool(self.u8()? != 0),
+            5 => Value::Null,
+            _ => return Err(protocol_error("unknown value tag")),
+        })
+    }
+
+    fn row(&mut self) -> Result<Row<'static>> {
+        let len = self.u32()? as usize;
+        (0..len).map(|_| self.value()).collect()
+    }
+
+    fn column_ref(&mut self, schema: &'static Schema) -> Result<&'static str> {
+        match self.u32()? {
+            ID_COLUMN_INDEX => Ok("id"),
+            i => schema
+                .columns
+                .get(i as usize)
+                .map(|column| column.name)
+                .ok_or_else(|| protocol_error("column index is out of range")),
+        }
+    }
+
+    fn filters(&mut self, schema: &'static Schema) -> Result<Vec<Filter>> {
+        (0..self.u32()?)
+            .map(|_| {
+                let column = self.column_ref(schema)?;
+                let op = FILTER_OPS
+                    .get(self.u8()? as usize)
+                    .ok_or_else(|| protocol_error("unknown filter operator"))?;
+                Ok(Filter {
+                    column,
+                    op: *op,
+                    value: self.value()?,
+                })
+            })
+            .collect()
+    }
+
+    fn window(&mut self, schema: &'static Schema) -> Result<Window> {
+        let function = match self.u8()? {
+            0 => WindowFunction::RowNumber,
+            1 => WindowFunction::Rank,
+            2 => WindowFunction::Lag(self.column_ref(schema)?, self.u32()? as usize),
+            3 => WindowFunction::Lead(self.column_ref(schema)?, self.u32()? as usize),
+            _ => return Err(protocol_error("unknown window function")),
+        };
+        let mut window = Window::new(function);
+        for _ in 0..self.u32()? {
+            window.partition_by.push(self.column_ref(schema)?);
+        }
+        for _ in 0..self.u32()? {
+            window
+                .order_by
+                .push((self.column_ref(schema)?, self.u8()? != 0));
+        }
+        if self.u8()? != 0 {
+            let op = FILTER_OPS
+                .get(self.u8()? as usize)
+                .ok_or_else(|| protocol_error("unknown filter operator"))?;
+            window.filter = Some((*op, self.value()?));
+        }
+        Ok(window)
+    }
+
+    fn index_hint(&mut self) -> Result<Option<IndexHint>> {
+        match self.u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(IndexHint::Use(self.string()?))),
+            2 => Ok(Some(IndexHint::NoIndex)),
+            _ => Err(protocol_error("unknown index hint")),
+        }
+    }
+
+    fn sample(&mut self) -> Result<Sample> {
+        match self.u8()? {
+            0 => Ok(Sample::Count(self.i64()? as usize)),
+            1 => Ok(Sample::Fraction(f64::from_bits(self.i64()? as u64))),
+            _ => Err(protocol_error("unknown sample kind")),
+        }
+    }
+
+    fn column_infos(&mut self) -> Result<Vec<ColumnInfo>> {
+        let len = self.u32()? as usize;
+        let mut columns = Vec::with_capacity(len);
+        for _ in 0..len {
+            let name = self.string()?;
+            let sql_type = self.string()?;
+            let flags = self.u8()?;
+            let default = match self.u8()? {
+                0 => None,
+                _ => Some(self.string()?),
+            };
+            columns.push(ColumnInfo {
+                name,
+                sql_type,
+                not_null: flags & 1 != 0,
+                default,
+                primary_key: flags & 2 != 0,
+            });
+        }
+        Ok(columns)
+    }
+
+    // Only schemas registered with the server are accepted, see Server::register.
+    fn schema(&mut self, registry: &Registry) -> Result<&'static Schema> {
+        let start = self.buf;
+        self.string()?;
+        self.string()?;
+        self.u8()?;
+        for _ in 0..self.u32()? {
+            self.string()?;
+            data_type_from_tag(self.u8()?)?;
+            self.u8()?;
+            format_from_tag(self.u8()?)?;
+            bool_encoding_from_tag(self.u8()?)?;
+            for _ in 0..5 {
+                if self.u8()? != 0 {
+                    self.string()?;
+                }
+            }
+            self.string()?;
+        }
+        for _ in 0..self.u32()? {
+            self.u8()?;
+            for _ in 0..2 {
+                if self.u8()? != 0 {
+                    self.string()?;
+                }
+            }
+            for _ in 0..2 {
+                for _ in 0..self.u32()? {
+                    self.string()?;
+                }
+            }
+        }
+        let encoded = &start[..start.len() - self.buf.len()];
+        registry
+            .schemas
+            .get(encoded)
+            .copied()
+            .ok_or_else(|| protocol_error("unknown schema, see Server::register"))
+    }
+}
+
+fn data_type_tag(ty: DataType) -> u8 {
+    match ty {
+        DataType::String => 0,
+        DataType::Bytes => 1,
+        DataType::Int64 => 2,
+        DataType::Float64 => 3,
+        DataType::Bool => 4,
+    }
+}
+
+fn data_type_from_tag(tag: u8) -> Result<DataType> {
+    Ok(match tag {
+        0 => DataType::String,
+        1 => DataType::Bytes,
+        2 => DataType::Int64,
+        3 => DataType::Float64,
+        4 => DataType::Bool,
+        _ => return Err(protocol_error("unknown data type tag")),
+    })
+}
+
+fn format_tag(format: Option<TextFormat>) -> u8 {
+    match format {
+        None => 0,
+        Some(TextFormat::Email) => 1,
+        Some(TextFormat::PhoneNumber) => 2,
+    }
+}
+
+fn format_from_tag(tag: u8) -> Result<Option<TextFormat>> {
+    Ok(match tag {
+        0 => None,
+        1 => Some(TextFormat::Email),
+        2 => Some(TextFormat::PhoneNumber),
+        _ => return Err(protocol_error("unknown text format tag")),
+    })
+}
+
+fn bool_encoding_tag(encoding: Option<BoolEncoding>) -> u8 {
+    match encoding {
+        None => 0,
+        Some(BoolEncoding::Integer) => 1,
+        Some(BoolEncoding::TextYn) => 2,
+        Some(BoolEncoding::TextTrueFalse) => 3,
+    }
+}
+
+fn bool_encoding_from_tag(tag: u8) -> Result<Option<BoolEncoding>> {
+    Ok(match tag {
+        0 => None,
+        1 => Some(BoolEncoding::Integer),
+        2 => Some(BoolEncoding::TextYn),
+        3 => Some(BoolEncoding::TextTrueFalse),
+        _ => return Err(protocol_error("unknown bool encoding tag")),
+    })
+}
+
+fn column_position(schema: &Schema, column_name: &str) -> Option<u32> {
+    schema
+        .columns
+        .iter()
+        .position(|column| column.name == column_name)
+        .map(|i| i as u32)
+}
+
+fn column_index(schema: &Schema, column_name: &str) -> Result<u32> {
+    column_position(schema, column_name).ok_or_else(|| {
+        let message = format!("no column '{}'", column_name);
+        Error::invalid_request(schema.type_name, message)
+    })
+}
+
+fn encode_error(err: &Error, schema: Option<&Schema>) -> Vec<u8> {
+    let mut enc = Encoder::default();
+    // Errors about a column the client's schema doesn't have are sent as
+    // plain storage errors.
+    let column = |name: &str| schema.and_then(|schema| column_position(schema, name));
+    match (err, schema) {
+        (Error::NotFound(e), _) => {
+            enc.u8(STATUS_NOT_FOUND).id(e.object_id);
+        }
+        (Error::UnexpectedType(e), _) if column(e.column_name).is_some() => {
+            enc.u8(STATUS_UNEXPECTED_TYPE)
+                .u32(column(e.column_name).unwrap())
+                .str(&e.got_type);
+        }
+        (Error::MissingColumn(e), _) if column(e.column_name).is_some() => {
+            enc.u8(STATUS_MISSING_COLUMN)
+                .u32(column(e.column_name).unwrap());
+        }
+        (Error::InvalidText(e), _) if column(e.column_name).is_some() => {
+            enc.u8(STATUS_INVALID_TEXT)
+                .u32(column(e.column_name).unwrap())
+                .str(&e.message);
+        }
+        (Error::Unsupported(e), _) => {
+            enc.u8(STATUS_UNSUPPORTED)
+                .str(&e.feature)
+                .str(&e.required)
+                .str(&e.found);
+        }
+        (Error::LockConflict, _) => {
+            enc.u8(STATUS_LOCK_CONFLICT);
+        }
+        (
+            Error::UniqueViolation(e)
+            | Error::ForeignKeyViolation(e)
+            | Error::CheckViolation(e)
+            | Error::NotNullViolation(e),
+            _,
+        ) => {
+            let status = match err {
+                Error::UniqueViolation(_) => STATUS_UNIQUE_VIOLATION,
+                Error::ForeignKeyViolation(_) => STATUS_FOREIGN_KEY_VIOLATION,
+                Error::CheckViolation(_) => STATUS_CHECK_VIOLATION,
+                _ => STATUS_NOT_NULL_VIOLATION,
+            };
+            let column = e.column_name.and_then(column).unwrap_or(NO_COLUMN);
+            enc.u8(status).u32(column).str(&e.message);
+        }
+        (err, _) => {
+            enc.u8(STATUS_STORAGE).str(&err.to_string());
+        }
+    }
+    enc.0
+}
+
+fn decode_error(status: u8, dec: &mut Decoder, schema: Option<&Schema>) -> Result<Error> {
+    let column = |i: u32| -> Result<(&Schema, usize)> {
+        let schema = schema.ok_or_else(|| protocol_error("unexpected column error"))?;
+        let i = i as usize;
+        if i >= schema.columns.len() {
+            return Err(protocol_error("column index is out of range"));
+        }
+        Ok((schema, i))
+    };
+
+    Ok(match status {
+        STATUS_NOT_FOUND => Error::NotFound(Box::new(NotFoundError {
+            object_id: dec.id()?,
+            type_name: schema.map(|s| s.type_name).unwrap_or_default(),
+        })),
+        STATUS_UNEXPECTED_TYPE => {
+            let (schema, i) = column(dec.u32()?)?;
+            Error::UnexpectedType(Box::new(UnexpectedTypeError {
+                type_name: schema.type_name,
+                attr_name: schema.attrs[i],
+                table_name: schema.table_name,
+                column_name: schema.columns[i].name,
+                expected_type: schema.columns[i].data_type,
+                got_type: dec.string()?,
+            }))
+        }
+        STATUS_MISSING_COLUMN => {
+            let (schema, i) = column(dec.u32()?)?;
+            Error::MissingColumn(Box::new(MissingColumnError {
+                type_name: schema.type_name,
+                attr_name: schema.attrs[i],
+                table_name: schema.table_name,
+                column_name: schema.columns[i].name,
+            }))
+        }
+        STATUS_INVALID_TEXT => {
+            let (schema, i) = column(dec.u32()?)?;
+            Error::InvalidText(Box::new(InvalidTextError {
+                type_name: schema.type_name,
+                attr_name: schema.attrs[i],
+                table_name: schema.table_name,
+                column_name: schema.columns[i].name,
+                message: dec.string()?,
+            }))
+        }
+        STATUS_UNSUPPORTED => Error::Unsupported(Box::new(UnsupportedError {
+            feature: dec.string()?,
+            required: dec.string()?,
+            found: dec.string()?,
+        })),
+        STATUS_LOCK_CONFLICT => Error::LockConflict,
+        STATUS_UNIQUE_VIOLATION
+        | STATUS_FOREIGN_KEY_VIOLATION
+        | STATUS_CHECK_VIOLATION
+        | STATUS_NOT_NULL_VIOLATION => {
+            let mut err = Box::new(ConstraintError {
+                type_name: schema.map(|s| s.type_name),
+                attr_name: None,
+                table_name: schema.map(|s| s.table_name),
+                column_name: None,
+                message: String::new(),
+            });
+            let i = dec.u32()?;
+            if i != NO_COLUMN {
+                let (schema, i) = column(i)?;
+                err.attr_name = Some(schema.attrs[i]);
+                err.column_name = Some(schema.columns[i].name);
+            }
+            err.message = dec.string()?;
+            match status {
+                STATUS_UNIQUE_VIOLATION => Error::UniqueViolation(err),
+                STATUS_FOREIGN_KEY_VIOLATION => Error::ForeignKeyViolation(err),
+                STATUS_CHECK_VIOLATION => Error::CheckViolation(err),
+                _ => Error::NotNullViolation(err),
+            }
+        }
+        STATUS_STORAGE => Error::Storage(dec.string()?.into()),
+        _ => return Err(protocol_error("unknown status")),
+    })
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub(crate) struct RemoteConnection {
+    stream: TcpStream,
+}
+
+impl RemoteConnection {
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<Self> {
+        let stream = TcpStream::connect(addr).map_err(io_error)?;
+        stream.set_nodelay(true).map_err(io_error)?;
+        Ok(Self { stream })
+    }
+}
+
+impl StorageConnection for RemoteConnection {
+    fn new_transaction<'a>(
+        &'a mut self,
+        settings: &'a Settings,
+    ) -> Result<Box<dyn StorageTransaction + 'a>> {
+        let tx = RemoteTransaction {
+            stream: &self.stream,
+            finished: Cell::new(false),
+        };
+        let coercion = match settings.coercion {
+            Coercion::Strict => 0,
+            Coercion::Lenient => 1,
+        };
+        let decoding = match settings.text_decoding {
+            TextDecoding::Strict => 0,
+            TextDecoding::Lossy => 1,
+            TextDecoding::Latin1Fallback => 2,
+        };
+        let mut request = Encoder::default();
+        request
+            .u8(OP_BEGIN)
+            .u8(coercion)
+            .u8(decoding)
+            .u8(settings.hydrate_missing_columns as u8)
+            .u8(settings.strict_tables as u8);
+        if let Err(e) = tx.call(&request, None) {
+            tx.finished.set(true);
+            return Err(e);
+        }
+        Ok(Box::new(tx))
+    }
+}
+
+struct RemoteTransaction<'a> {
+    stream: &'a TcpStream,
+    finished: Cell<bool>,
+}
+
+impl<'a> RemoteTransaction<'a> {
+    fn call(&self, request: &Encoder, schema: Option<&Schema>) -> Result<Vec<u8>> {
+        write_frame(self.stream, &request.0)?;
+        let response = read_frame(self.stream)?;
+        let mut dec = Decoder::new(&response);
+        match dec.u8()? {
+            STATUS_OK => Ok(dec.buf.to_vec()),
+            status => Err(decode_error(status, &mut dec, schema)?),
+        }
+    }
+}
+
+impl<'a> StorageTransaction for RemoteTransaction<'a> {
+    fn table_exists(&self, table: &str) -> Result<bool> {
+        let response = self.call(Encoder::default().u8(OP_TABLE_EXISTS).str(table), None)?;
+        Ok(Decoder::new(&response).u8()? != 0)
+    }
+
+    fn table_names(&self) -> Result<Vec<String>> {
+        let response = self.call(Encoder::default().u8(OP_TABLE_NAMES), None)?;
+        let mut dec = Decoder::new(&response);
+        (0..dec.u32()?).map(|_| dec.string()).collect()
+    }
+
+    fn table_columns(&self, table: &str) -> Result<Vec<ColumnInfo>> {
+        let response = self.call(Encoder::default().u8(OP_TABLE_COLUMNS).str(table), None)?;
+        Decoder::new(&response).column_infos()
+    }
+
+    fn execute_ddl(&self, statements: &[String]) -> Result<()> {
+        let mut request = Encoder::default();
+        request.u8(OP_EXECUTE_DDL).u32(statements.len() as u32);
+        for sql in statements {
+            request.str(sql);
+        }
+        self.call(&request, None)?;
+        Ok(())
+    }
+
+    fn create_temp_table(&self, schema: &Schema) -> Result<()> {
+        let mut request = Encoder::default();
+        request.u8(OP_CREATE_TEMP_TABLE).schema(schema);
+        self.call(&request, Some(schema))?;
+        Ok(())
+    }
+
+    fn drop_temp_table(&self, schema: &Schema) -> Result<()> {
+        let mut request = Encoder::default();
+        request.u8(OP_DROP_TEMP_TABLE).schema(schema);
+        self.call(&request, Some(schema))?;
+        Ok(())
+    }
+
+    fn create_table(&self, schema: &Schema) -> Result<()> {
+        let mut request = Encoder::default();
+        request.u8(OP_CREATE_TABLE).schema(schema);
+        self.call(&request, Some(schema))?;
+        Ok(())
+    }
+
+    fn insert_row(&self, schema: &Schema, row: &RowSlice) -> Result<ObjectId> {
+        let mut request = Encoder::default();
+        request.u8(OP_INSERT).schema(schema).row(row);
+        let response = self.call(&request, Some(schema))?;
+        Decoder::new(&response).id()
+    }
+
+    fn insert_rows(&self, schema: &Schema, rows: &[Row]) -> Result<Vec<ObjectId>> {
+        let mut request = Encoder::default();
+        request
+            .u8(OP_INSERT_ROWS)
+            .schema(schema)
+            .u32(rows.len() as u32);
+        for row in rows {
+            request.row(row);
+        }
+        let response = self.call(&request, Some(schema))?;
+        let mut dec = Decoder::new(&response);
+        (0..dec.u32()?).map(|_| dec.id()).collect()
+    }
+
+    fn update_row(&self, id: ObjectId, schema: &Schema, row: &RowSlice) -> Result<()> {
+        let mut request = Encoder::default();
+        request.u8(OP_UPDATE).id(id).schema(schema).row(row);
+        self.call(&request, Some(schema))?;
+        Ok(())
+    }
+
+    fn increment(&self, id: ObjectId, schema: &Schema, column: &str, delta: i64) -> Result<i64> {
+        let mut request = Encoder::default();
+        request
+            .u8(OP_INCREMENT)
+            .id(id)
+            .schema(schema)
+            .str(column)
+            .i64(delta);
+        let response = self.call(&request, Some(schema))?;
+        Decoder::new(&response).i64()
+    }
+
+    fn select_row(&self, id: ObjectId, schema: &Schema) -> Result<Row<'static>> {
+        let mut request = Encoder::default();
+        request.u8(OP_SELECT).id(id).schema(schema);
+        let response = self.call(&request, Some(schema))?;
+        Decoder::new(&response).row()
+    }
+
+    fn has_row(&self, id: ObjectId, schema: &Schema) -> Result<bool> {
+        let mut request = Encoder::default();
+        request.u8(OP_HAS_ROW).id(id).schema(schema);
+        let response = self.call(&request, Some(schema))?;
+        Ok(Decoder::new(&response).u8()? != 0)
+    }
+
+    fn lock_row(&self, id: ObjectId, schema: &Schema) -> Result<()> {
+        let mut request = Encoder::default();
+        request.u8(OP_LOCK_ROW).id(id).schema(schema);
+        self.call(&request, Some(schema))?;
+        Ok(())
+    }
+
+    fn select_page(
+        &self,
+        schema: &Schema,
+        after: Option<ObjectId>,
+        limit: usize,
+    ) -> Result<Vec<(ObjectId, Row<'static>)>> {
+        let mut request = Encoder::default();
+        request
+            .u8(OP_SELECT_PAGE)
+            .schema(schema)
+            .id(after.unwrap_or(ObjectId::new(0)))
+            .i64(limit as i64);
+        let response = self.call(&request, Some(schema))?;
+        let mut dec = Decoder::new(&response);
+        (0..dec.u32()?)
+            .map(|_| Ok((dec.id()?, dec.row()?)))
+            .collect()
+    }
+
+    fn select_rows(
+        &self,
+        schema: &Schema,
+        filters: &[Filter],
+        hint: Option<&IndexHint>,
+        limit: Option<usize>,
+    ) -> Result<Vec<(ObjectId, Row<'static>)>> {
+        let mut request = Encoder::default();
+        request
+            .u8(OP_SELECT_ROWS)
+            .schema(schema)
+            .filters(schema, filters)?
+            .index_hint(hint)
+            .i64(limit.map_or(-1, |limit| limit as i64));
+        let response = self.call(&request, Some(schema))?;
+        let mut dec = Decoder::new(&response);
+        (0..dec.u32()?)
+            .map(|_| Ok((dec.id()?, dec.row()?)))
+            .collect()
+    }
+
+    fn select_ids(
+        &self,
+        schema: &Schema,
+        ids: &[ObjectId],
+    ) -> Result<Vec<(ObjectId, Row<'static>)>> {
+        let mut request = Encoder::default();
+        request
+            .u8(OP_SELECT_IDS)
+            .schema(schema)
+            .u32(ids.len() as u32);
+        for id in ids {
+            request.id(*id);
+        }
+        let response = self.call(&request, Some(schema))?;
+        let mut dec = Decoder::new(&response);
+        (0..dec.u32()?)
+            .map(|_| Ok((dec.id()?, dec.row()?)))
+            .collect()
+    }
+
+    fn select_recursive(
+        &self,
+        schema: &Schema,
+        seed: &[Filter],
+        step: Step,
+        filters: &[Filter],
+        limit: Option<usize>,
+    ) -> Result<Vec<(ObjectId, Row<'static>)>> {
+        let mut request = Encoder::default();
+        request
+            .u8(OP_SELECT_RECURSIVE)
+            .schema(schema)
+            .filters(schema, seed)?
+            .column_ref(schema, step.column)?
+            .column_ref(schema, step.previous)?
+            .filters(schema, filters)?
+            .i64(limit.map_or(-1, |limit| limit as i64));
+        let response = self.call(&request, Some(schema))?;
+        let mut dec = Decoder::new(&response);
+        (0..dec.u32()?)
+            .map(|_| Ok((dec.id()?, dec.row()?)))
+            .collect()
+    }
+
+    fn select_window(
+        &self,
+        schema: &Schema,
+        filters: &[Filter],
+        window: &Window,
+        limit: Option<usize>,
+    ) -> Result<Vec<(ObjectId, Row<'static>, Value<'static>)>> {
+        let mut request = Encoder::default();
+        request
+            .u8(OP_SELECT_WINDOW)
+            .schema(schema)
+            .filters(schema, filters)?
+            .window(schema, window)?
+            .i64(limit.map_or(-1, |limit| limit as i64));
+        let response = self.call(&request, Some(schema))?;
+        let mut dec = Decoder::new(&response);
+        (0..dec.u32()?)
+            .map(|_| Ok((dec.id()?, dec.row()?, dec.value()?)))
+            .collect()
+    }
+
+    fn select_sample(
+        &self,
+        schema: &Schema,
+        filters: &[Filter],
+        sample: Sample,
+        limit: Option<usize>,
+    ) -> Result<Vec<(ObjectId, Row<'static>)>> {
+        let mut request = Encoder::default();
+        request
+            .u8(OP_SELECT_SAMPLE)
+            .schema(schema)
+            .filters(schema, filters)?
+            .sample(sample)
+            .i64(limit.map_or(-1, |limit| limit as i64));
+        let response = self.call(&request, Some(schema))?;
+        let mut dec = Decoder::new(&response);
+        (0..dec.u32()?)
+            .map(|_| Ok((dec.id()?, dec.row()?)))
+            .collect()
+    }
+
+    fn select_changed(
+        &self,
+        schema: &Schema,
+        filters: &[Filter],
+        since: i64,
+    ) -> Result<Vec<(ObjectId, Row<'static>, i64)>> {
+        let mut request = Encoder::default();
+        request
+            .u8(OP_SELECT_CHANGED)
+            .schema(schema)
+            .filters(schema, filters)?
+            .i64(since);
+        let response = self.call(&request, Some(schema))?;
+        let mut dec = Decoder::new(&response);
+        (0..dec.u32()?)
+            .map(|_| Ok((dec.id()?, dec.row()?, dec.i64()?)))
+            .collect()
+    }
+
+    fn delete_row(&self, id: ObjectId, schema: &Schema) -> Result<()> {
+        let mut request = Encoder::default();
+        request.u8(OP_DELETE).id(id).schema(schema);
+        self.call(&request, Some(schema))?;
+        Ok(())
+    }
+
+    fn delete_expired(
+        &self,
+        schema: &Schema,
+        column: &str,
+        now: i64,
+        limit: usize,
+    ) -> Result<usize> {
+        let mut request = Encoder::default();
+        request
+            .u8(OP_DELETE_EXPIRED)
+            .schema(schema)
+            .str(column)
+            .i64(now)
+            .i64(limit as i64);
+        let response = self.call(&request, Some(schema))?;
+        Ok(Decoder::new(&response).i64()? as usize)
+    }
+
+    fn merge_staged(&self, schema: &Schema, match_columns: &[&str]) -> Result<usize> {
+        let mut request = Encoder::default();
+        request
+            .u8(OP_MERGE_STAGED)
+            .schema(schema)
+            .u32(match_columns.len() as u32);
+        for name in match_columns {
+            request.str(name);
+        }
+        let response = self.call(&request, Some(schema))?;
+        Ok(Decoder::new(&response).i64()? as usize)
+    }
+
+    fn table_usage(&self, schema: &Schema) -> Result<TableUsage> {
+        let mut request = Encoder::default();
+        request.u8(OP_TABLE_USAGE).schema(schema);
+        let response = self.call(&request, Some(schema))?;
+        let mut dec = Decoder::new(&response);
+        Ok(TableUsage {
+            rows: dec.i64()? as u64,
+            bytes: dec.i64()? as u64,
+        })
+    }
+
+    fn estimate_rows(&self, schema: &Schema) -> Result<u64> {
+        let mut request = Encoder::default();
+        request.u8(OP_ESTIMATE_ROWS).schema(schema);
+        let response = self.call(&request, Some(schema))?;
+        Ok(Decoder::new(&response).i64()? as u64)
+    }
+
+    fn sum_column(
+        &self,
+        schema: &Schema,
+        filters: &[Filter],
+        column: &str,
+        group_by: Option<&str>,
+    ) -> Result<Vec<(Value<'static>, i64)>> {
+        let mut request = Encoder::default();
+        request
+            .u8(OP_SUM_COLUMN)
+            .schema(schema)
+            .filters(schema, filters)?
+            .column_ref(schema, column)?;
+        match group_by {
+            Some(group) => request.u8(1).column_ref(schema, group)?,
+            None => request.u8(0),
+        };
+        let response = self.call(&request, Some(schema))?;
+        let mut dec = Decoder::new(&response);
+        (0..dec.u32()?)
+            .map(|_| Ok((dec.value()?, dec.i64()?)))
+            .collect()
+    }
+
+    fn defer_foreign_keys(&self, enabled: bool) -> Result<()> {
+        let mut request = Encoder::default();
+        request.u8(OP_DEFER_FOREIGN_KEYS).u8(enabled as u8);
+        self.call(&request, None)?;
+        Ok(())
+    }
+
+    fn savepoint(&self, name: &str) -> Result<()> {
+        self.call(Encoder::default().u8(OP_SAVEPOINT).str(name), None)?;
+        Ok(())
+    }
+
+    fn release_savepoint(&self, name: &str) -> Result<()> {
+        self.call(Encoder::default().u8(OP_RELEASE_SAVEPOINT).str(name), None)?;
+        Ok(())
+    }
+
+    fn rollback_to_savepoint(&self, name: &str) -> Result<()> {
+        self.call(
+            Encoder::default().u8(OP_ROLLBACK_TO_SAVEPOINT).str(name),
+            None,
+        )?;
+        Ok(())
+    }
+
+    fn next_in_sequence(&self, name: &str) -> Result<i64> {
+        let response = self.call(Encoder::default().u8(OP_NEXT_IN_SEQUENCE).str(name), None)?;
+        Decoder::new(&response).i64()
+    }
+
+    fn put_blob(&self, hash: &[u8], data: &[u8]) -> Result<()> {
+        self.call(
+            Encoder::default().u8(OP_PUT_BLOB).bytes(hash).bytes(data),
+            None,
+        )?;
+        Ok(())
+    }
+
+    fn get_blob(&self, hash: &[u8]) -> Result<Option<Vec<u8>>> {
+        let response = self.call(Encoder::default().u8(OP_GET_BLOB).bytes(hash), None)?;
+        let mut dec = Decoder::new(&response);
+        Ok(match dec.u8()? {
+            0 => None,
+            _ => Some(dec.bytes()?.to_vec()),
+        })
+    }
+
+    fn intern(&self, value: &str) -> Result<i64> {
+        let response = self.call(Encoder::default().u8(OP_INTERN).str(value), None)?;
+        Decoder::new(&response).i64()
+    }
+
+    fn interned_value(&self, id: i64) -> Result<Option<String>> {
+        let response = self.call(Encoder::default().u8(OP_INTERNED_VALUE).i64(id), None)?;
+        let mut dec = Decoder::new(&response);
+        Ok(match dec.u8()? {
+            0 => None,
+            _ => Some(dec.string()?),
+        })
+    }
+
+    fn applied_migrations(&self) -> Result<Vec<i64>> {
+        let response = self.call(Encoder::default().u8(OP_APPLIED_MIGRATIONS), None)?;
+        let mut dec = Decoder::new(&response);
+        (0..dec.u32()?).map(|_| dec.i64()).collect()
+    }
+
+    fn record_migration(&self, version: i64, name: &str) -> Result<()> {
+        self.call(
+            Encoder::default()
+                .u8(OP_RECORD_MIGRATION)
+                .i64(version)
+                .str(name),
+            None,
+        )?;
+        Ok(())
+    }
+
+    // The server works out the references from its registered schemas.
+    fn delete_unreferenced_blobs(&self, _references: &[(&str, &str)]) -> Result<usize> {
+        let response = self.call(Encoder::default().u8(OP_DELETE_UNREFERENCED_BLOBS), None)?;
+        Ok(Decoder::new(&response).i64()? as usize)
+    }
+
+    fn commit(&self) -> Result<()> {
+        self.finished.set(true);
+        self.call(Encoder::default().u8(OP_COMMIT), None)?;
+        Ok(())
+    }
+
+    fn rollback(&self) -> Result<()> {
+        self.finished.set(true);
+        self.call(Encoder::default().u8(OP_ROLLBACK), None)?;
+        Ok(())
+    }
+}
+
+impl<'a> Drop for RemoteTransaction<'a> {
+    fn drop(&mut self) {
+        if !self.finished.get() {
+            let _ = self.rollback();
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Serves one database to remote connections. Clients are trusted to the
+// extent of the schemas registered here: requests naming any other schema are
+// rejected, blob purges only keep blobs referenced from registered schemas, and
+// raw DDL only runs if allowed with allow_ddl.
+pub struct Server {
+    path: PathBuf,
+    registry: Registry,
+}
+
+#[derive(Clone, Default)]
+struct Registry {
+    schemas: HashMap<Vec<u8>, &'static Schema>,
+    allow_ddl: bool,
+}
+
+impl Server {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_owned(),
+            registry: Registry::default(),
+        }
+    }
+
+    pub fn register<T: Object>(&mut self) -> &mut Self {
+        let mut encoded = Encoder::default();
+        encoded.schema(T::schema());
+        self.registry.schemas.insert(encoded.0, T::schema());
+        self
+    }
+
+    // Lets clients run statements of their own, as auto-migration, migrations
+    // and table adoption do.
+    pub fn allow_ddl(&mut self, enabled: bool) -> &mut Self {
+        self.registry.allow_ddl = enabled;
+        self
+    }
+
+    pub fn serve(&self, listener: TcpListener) -> Result<()> {
+        let registry = Arc::new(self.registry.clone());
+        for stream in listener.incoming() {
+            let stream = stream.map_err(io_error)?;
+            let conn = storage::open_sqlite(rusqlite::Connection::open(&self.path)?);
+            let registry = Arc::clone(&registry);
+            thread::spawn(move || {
+                let mut conn = conn;
+                let _ = serve_connection(&mut conn, stream, &registry);
+            });
+        }
+        Ok(())
+    }
+
+    pub fn serve_connection(
+        &self,
+        conn: &mut rusqlite::Connection,
+        stream: TcpStream,
+    ) -> Result<()> {
+        serve_connection(conn, stream, &self.registry)
+    }
+}
+
+fn serve_connection(
+    conn: &mut rusqlite::Connection,
+    stream: TcpStream,
+    registry: &Registry,
+) -> Result<()> {
+    let mut settings = Settings::default();
+    loop {
+        let request = match read_frame(&stream) {
+            Ok(request) => request,
+            Err(Error::Storage(e))
+                if e.downcast_ref::<std::io::Error>()
+                    .is_some_and(|e| e.kind() == std::io::ErrorKind::UnexpectedEof) =>
+            {
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+
+        if request.first() != Some(&OP_BEGIN) {
+            let err = protocol_error("no active transaction");
+            write_frame(&stream, &encode_error(&err, None))?;
+            continue;
+        }
+        settings.coercion = match request.get(1) {
+            Some(1) => Coercion::Lenient,
+            _ => Coercion::Strict,
+        };
+        settings.text_decoding = match request.get(2) {
+            Some(1) => TextDecoding::Lossy,
+            Some(2) => TextDecoding::Latin1Fallback,
+            _ => TextDecoding::Strict,
+        };
+        settings.hydrate_missing_columns = request.get(3) == Some(&1);
+        settings.strict_tables = request.get(4) == Some(&1);
+
+        match conn.transaction() {
+            Ok(tx) => {
+                write_frame(&stream, &[STATUS_OK])?;
+                serve_transaction(&SqliteTransaction::new(tx, &settings), &stream, registry)?;
+            }
+            Err(e) => write_frame(&stream, &encode_error(&Error::from(e), None))?,
+        }
+    }
+}
+
+fn serve_transaction(
+    tx: &dyn StorageTransaction,
+    stream: &TcpStream,
+    registry: &Registry,
+) -> Result<()> {
+    loop {
+        let request = read_frame(stream)?;
+        let mut dec = Decoder::new(&request);
+        let mut schema = None;
+        let mut done = false;
+        let result = (|| {
+            let mut response = Encoder::default();
+            response.u8(STATUS_OK);
+            match dec.u8()? {
+                OP_TABLE_EXISTS => {
+                    let table = dec.string()?;
+                    response.u8(tx.table_exists(&table)? as u8);
+                }
+                OP_TABLE_NAMES => {
+                    let names = tx.table_names()?;
+                    response.u32(names.len() as u32);
+                    for name in &names {
+                        response.str(name);
+                    }
+                }
+                OP_TABLE_COLUMNS => {
+                    let table = dec.string()?;
+                    response.column_infos(&tx.table_columns(&table)?);
+                }
+                OP_EXECUTE_DDL => {
+                    if !registry.allow_ddl {
+                        return Err(Error::Storage("DDL is not allowed by this server".into()));
+                    }
+                    let statements = (0..dec.u32()?)
+                        .map(|_| dec.string())
+                        .collect::<Result<Vec<_>>>()?;
+                    tx.execute_ddl(&statements)?;
+                }
+                OP_CREATE_TEMP_TABLE => {
+                    let s = *schema.insert(dec.schema(registry)?);
+                    tx.create_temp_table(s)?;
+                }
+                OP_DROP_TEMP_TABLE => {
+                    let s = *schema.insert(dec.schema(registry)?);
+                    tx.drop_temp_table(s)?;
+                }
+                OP_CREATE_TABLE => {
+                    let s = *schema.insert(dec.schema(registry)?);
+                    tx.create_table(s)?;
+                }
+                OP_INSERT => {
+                    let s = *schema.insert(dec.schema(registry)?);
+                    response.id(tx.insert_row(s, &dec.row()?)?);
+                }
+                OP_INSERT_ROWS => {
+                    let s = *schema.insert(dec.schema(registry)?);
+                    let rows = (0..dec.u32()?)
+                        .map(|_| dec.row())
+                        .collect::<Result<Vec<_>>>()?;
+                    let ids = tx.insert_rows(s, &rows)?;
+                    response.u32(ids.len() as u32);
+                    for id in ids {
+                        response.id(id);
+                    }
+                }
+                OP_UPDATE => {
+                    let id = dec.id()?;
+                    let s = *schema.insert(dec.schema(registry)?);
+                    tx.update_row(id, s, &dec.row()?)?;
+                }
+                OP_INCREMENT => {
+                    let id = dec.id()?;
+                    let s = *schema.insert(dec.schema(registry)?);
+                    let column = dec.string()?;
+                    response.i64(tx.increment(id, s, &column, dec.i64()?)?);
+                }
+                OP_SELECT => {
+                    let id = dec.id()?;
+                    let s = *schema.insert(dec.schema(registry)?);
+                    response.row(&tx.select_row(id, s)?);
+                }
+                OP_HAS_ROW => {
+                    let id = dec.id()?;
+                    let s = *schema.insert(dec.schema(registry)?);
+                    response.u8(tx.has_row(id, s)? as u8);
+                }
+                OP_LOCK_ROW => {
+                    let id = dec.id()?;
+                    let s = *schema.insert(dec.schema(registry)?);
+                    tx.lock_row(id, s)?;
+                }
+                OP_SELECT_PAGE => {
+                    let s = *schema.insert(dec.schema(registry)?);
+                    let after = dec.id()?;
+                    let limit = dec.i64()? as usize;
+                    let rows = tx.select_page(s, Some(after), limit)?;
+                    response.u32(rows.len() as u32);
+                    for (id, row) in &rows {
+                        response.id(*id).row(row);
+                    }
+                }
+                OP_SELECT_ROWS => {
+                    let s = *schema.insert(dec.schema(registry)?);
+                    let filters = dec.filters(s)?;
+                    let hint = dec.index_hint()?;
+                    let limit = usize::try_from(dec.i64()?).ok();
+                    let rows = tx.select_rows(s, &filters, hint.as_ref(), limit)?;
+                    response.u32(rows.len() as u32);
+                    for (id, row) in &rows {
+                        response.id(*id).row(row);
+                    }
+                }
+                OP_DELETE => {
+                    let id = dec.id()?;
+                    let s = *schema.insert(dec.schema(registry)?);
+                    tx.delete_row(id, s)?;
+                }
+                OP_SELECT_IDS => {
+                    let s = *schema.insert(dec.schema(registry)?);
+                    let ids = (0..dec.u32()?)
+                        .map(|_| dec.id())
+                        .collect::<Result<Vec<_>>>()?;
+                    let rows = tx.select_ids(s, &ids)?;
+                    response.u32(rows.len() as u32);
+                    for (id, row) in &rows {
+                        response.id(*id).row(row);
+                    }
+                }
+                OP_SELECT_RECURSIVE => {
+                    let s = *schema.insert(dec.schema(registry)?);
+                    let seed = dec.filters(s)?;
+                    let step = Step::new(dec.column_ref(s)?, dec.column_ref(s)?);
+                    let filters = dec.filters(s)?;
+                    let limit = usize::try_from(dec.i64()?).ok();
+                    let rows = tx.select_recursive(s, &seed, step, &filters, limit)?;
+                    response.u32(rows.len() as u32);
+                    for (id, row) in &rows {
+                        response.id(*id).row(row);
+                    }
+                }
+                OP_SELECT_WINDOW => {
+                    let s = *schema.insert(dec.schema(registry)?);
+                    let filters = dec.filters(s)?;
+                    let window = dec.window(s)?;
+                    let limit = usize::try_from(dec.i64()?).ok();
+                    let rows = tx.select_window(s, &filters, &window, limit)?;
+                    response.u32(rows.len() as u32);
+                    for (id, row, value) in &rows {
+                        response.id(*id).row(row).value(value);
+                    }
+                }
+                OP_SELECT_SAMPLE => {
+                    let s = *schema.insert(dec.schema(registry)?);
+                    let filters = dec.filters(s)?;
+                    let sample = dec.sample()?;
+                    let limit = usize::try_from(dec.i64()?).ok();
+                    let rows = tx.select_sample(s, &filters, sample, limit)?;
+                    response.u32(rows.len() as u32);
+                    for (id, row) in &rows {
+                        response.id(*id).row(row);
+                    }
+                }
+                OP_SELECT_CHANGED => {
+                    let s = *schema.insert(dec.schema(registry)?);
+                    let filters = dec.filters(s)?;
+                    let rows = tx.select_changed(s, &filters, dec.i64()?)?;
+                    response.u32(rows.len() as u32);
+                    for (id, row, revision) in &rows {
+                        response.id(*id).row(row).i64(*revision);
+                    }
+                }
+                OP_DELETE_EXPIRED => {
+                    let s = *schema.insert(dec.schema(registry)?);
+                    let column = dec.string()?;
+                    let now = dec.i64()?;
+                    let limit = dec.i64()? as usize;
+                    response.i64(tx.delete_expired(s, &column, now, limit)? as i64);
+                }
+                OP_MERGE_STAGED => {
+                    let s = *schema.insert(dec.schema(registry)?);
+                    let match_columns = (0..dec.u32()?)
+                        .map(|_| dec.string())
+                        .collect::<Result<Vec<_>>>()?;
+                    let match_columns =
+                        match_columns.iter().map(String::as_str).collect::<Vec<_>>();
+                    response.i64(tx.merge_staged(s, &match_columns)? as i64);
+                }
+                OP_TABLE_USAGE => {
+                    let s = *schema.insert(dec.schema(registry)?);
+                    let usage = tx.table_usage(s)?;
+                    response.i64(usage.rows as i64).i64(usage.bytes as i64);
+                }
+                OP_ESTIMATE_ROWS => {
+                    let s = *schema.insert(dec.schema(registry)?);
+                    response.i64(tx.estimate_rows(s)? as i64);
+                }
+                OP_SUM_COLUMN => {
+                    let s = *schema.insert(dec.schema(registry)?);
+                    let filters = dec.filters(s)?;
+                    let column = dec.column_ref(s)?;
+                    let group_by = match dec.u8()? {
+                        0 => None,
+                        _ => Some(dec.column_ref(s)?),
+                    };
+                    let sums = tx.sum_column(s, &filters, column, group_by)?;
+                    response.u32(sums.len() as u32);
+                    for (group, sum) in &sums {
+                        response.value(group).i64(*sum);
+                    }
+                }
+                OP_NEXT_IN_SEQUENCE => {
+                    let name = dec.string()?;
+                    response.i64(tx.next_in_sequence(&name)?);
+                }
+                OP_PUT_BLOB => {
+                    let hash = dec.bytes()?;
+                    tx.put_blob(hash, dec.bytes()?)?;
+                }
+                OP_GET_BLOB => match tx.get_blob(dec.bytes()?)? {
+                    Some(data) => {
+                        response.u8(1).bytes(&data);
+                    }
+                    None => {
+                        response.u8(0);
+                    }
+                },
+                OP_INTERN => {
+                    response.i64(tx.intern(&dec.string()?)?);
+                }
+                OP_INTERNED_VALUE => match tx.interned_value(dec.i64()?)? {
+                    Some(value) => {
+                        response.u8(1).str(&value);
+                    }
+                    None => {
+                        response.u8(0);
+                    }
+                },
+                OP_DELETE_UNREFERENCED_BLOBS => {
+                    // Blob references come from the registered schemas only, so a
+                    // client can't delete blobs that registered tables still use.
+                    let schemas = registry.schemas.values().copied();
+                    let references = connection::blob_references(tx, schemas)?;
+                    response.i64(tx.delete_unreferenced_blobs(&references)? as i64);
+                }
+                OP_APPLIED_MIGRATIONS => {
+                    let versions = tx.applied_migrations()?;
+                    response.u32(versions.len() as u32);
+                    for version in versions {
+                        response.i64(version);
+                    }
+                }
+                OP_RECORD_MIGRATION => {
+                    let version = dec.i64()?;
+                    tx.record_migration(version, &dec.string()?)?;
+                }
+                OP_DEFER_FOREIGN_KEYS => {
+                    tx.defer_foreign_keys(dec.u8()? != 0)?;
+                }
+                OP_SAVEPOINT => {
+                    tx.savepoint(&dec.string()?)?;
+                }
+                OP_RELEASE_SAVEPOINT => {
+                    tx.release_savepoint(&dec.string()?)?;
+                }
+                OP_ROLLBACK_TO_SAVEPOINT => {
+                    tx.rollback_to_savepoint(&dec.string()?)?;
+                }
+                OP_COMMIT => {
+                    done = true;
+                    tx.commit()?;
+                }
+                OP_ROLLBACK => {
+                    done = true;
+                    tx.rollback()?;
+                }
+                _ => return Err(protocol_error("unknown operation")),
+            }
+            Ok(response.0)
+        })();
+
+        let response = result.unwrap_or_else(|e| encode_error(&e, schema));
+        write_frame(stream, &response)?;
+        if done {
+            return Ok(());
+        }
+    }
+}
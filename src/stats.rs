@@ -0,0 +1,19 @@
+#![forbid(unsafe_code)]
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TableStats {
+    pub selects: u64,
+    pub inserts: u64,
+    pub updates: u64,
+    pub deletes: u64,
+    pub rows_read: u64,
+}
+
+impl TableStats {
+    pub(crate) fn read(&mut self, rows: usize) {
+        self.selects += 1;
+        self.rows_read += rows as u64;
+    }
+}
@@ -1,13 +1,25 @@
 #![forbid(unsafe_code)]
 
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::fmt::Write;
+use std::ops::Deref;
 
+use rusqlite::types::{ToSqlOutput, Type, ValueRef};
 use rusqlite::ToSql;
 
 use crate::{
-    data::{DataType, Value},
-    error::{Error, ErrorCtx, ErrorWithCtx, Result, UnexpectedTypeError},
-    object::Schema,
+    cipher,
+    connection::Settings,
+    data::{Coercion, DataType, TextDecoding, Value, ValueConvert},
+    ddl::{self, Dialect},
+    error::{
+        Error, ErrorCtx, ErrorWithCtx, InvalidTextError, Result, StatementError,
+        UnexpectedTypeError, UnsupportedError,
+    },
+    object::{ColumnDef, Schema, REVISION_COLUMN},
+    query::{Filter, IndexHint, Op, Sample, Step, Window, WindowFunction},
+    quota::TableUsage,
     ObjectId,
 };
 
@@ -16,49 +28,305 @@ use crate::{
 pub type Row<'a> = Vec<Value<'a>>;
 pub type RowSlice<'a> = [Value<'a>];
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ColumnInfo {
+    pub name: String,
+    pub sql_type: String,
+    pub not_null: bool,
+    pub default: Option<String>,
+    pub primary_key: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LockState {
+    Unlocked,
+    // Another connection is writing; readers can still proceed.
+    Reserved,
+    // Another connection is committing; readers are blocked as well.
+    Exclusive,
+}
+
+// How hard a WAL checkpoint tries, see Connection::checkpoint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheckpointMode {
+    // Copies what it can without waiting for anyone.
+    Passive,
+    // Waits for writers, then copies everything readers no longer need.
+    Full,
+    // Like Full, then waits for readers so the next writer restarts the WAL.
+    Restart,
+    // Like Restart, then truncates the WAL file to zero bytes.
+    Truncate,
+}
+
+impl CheckpointMode {
+    pub fn sql(&self) -> &'static str {
+        match self {
+            CheckpointMode::Passive => "PASSIVE",
+            CheckpointMode::Full => "FULL",
+            CheckpointMode::Restart => "RESTART",
+            CheckpointMode::Truncate => "TRUNCATE",
+        }
+    }
+}
+
+// Frame counts are zero when the database isn't in WAL mode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Checkpoint {
+    // A reader or writer kept the checkpoint from finishing.
+    pub busy: bool,
+    pub wal_frames: u64,
+    pub checkpointed_frames: u64,
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 pub(crate) trait StorageTransaction {
     fn table_exists(&self, table: &str) -> Result<bool>;
     fn create_table(&self, schema: &Schema) -> Result<()>;
+    fn table_names(&self) -> Result<Vec<String>>;
+    fn table_columns(&self, table: &str) -> Result<Vec<ColumnInfo>>;
+    // Runs the statements of a SchemaChange.
+    fn execute_ddl(&self, statements: &[String]) -> Result<()>;
+    fn create_temp_table(&self, schema: &Schema) -> Result<()>;
+    fn drop_temp_table(&self, schema: &Schema) -> Result<()>;
 
     fn insert_row(&self, schema: &Schema, row: &RowSlice) -> Result<ObjectId>;
+    // Inserting through the statement cache already makes each row cheap
+    // locally; backends paying per call send them all at once.
+    fn insert_rows(&self, schema: &Schema, rows: &[Row]) -> Result<Vec<ObjectId>> {
+        rows.iter()
+            .map(|row| self.insert_row(schema, row))
+            .collect()
+    }
     fn update_row(&self, id: ObjectId, schema: &Schema, row: &RowSlice) -> Result<()>;
+    fn increment(&self, id: ObjectId, schema: &Schema, column: &str, delta: i64) -> Result<i64>;
     fn select_row(&self, id: ObjectId, schema: &Schema) -> Result<Row<'static>>;
+    fn has_row(&self, id: ObjectId, schema: &Schema) -> Result<bool>;
+    fn lock_row(&self, id: ObjectId, schema: &Schema) -> Result<()>;
+    fn with_row(&self, id: ObjectId, schema: &Schema, f: &mut dyn FnMut(Row<'_>)) -> Result<()> {
+        f(self.select_row(id, schema)?);
+        Ok(())
+    }
+    fn select_page(
+        &self,
+        schema: &Schema,
+        after: Option<ObjectId>,
+        limit: usize,
+    ) -> Result<Vec<(ObjectId, Row<'static>)>>;
+    fn select_rows(
+        &self,
+        schema: &Schema,
+        filters: &[Filter],
+        hint: Option<&IndexHint>,
+        limit: Option<usize>,
+    ) -> Result<Vec<(ObjectId, Row<'static>)>>;
+    fn select_ids(
+        &self,
+        schema: &Schema,
+        ids: &[ObjectId],
+    ) -> Result<Vec<(ObjectId, Row<'static>)>>;
+    fn select_recursive(
+        &self,
+        schema: &Schema,
+        seed: &[Filter],
+        step: Step,
+        filters: &[Filter],
+        limit: Option<usize>,
+    ) -> Result<Vec<(ObjectId, Row<'static>)>>;
+    fn select_window(
+        &self,
+        schema: &Schema,
+        filters: &[Filter],
+        window: &Window,
+        limit: Option<usize>,
+    ) -> Result<Vec<(ObjectId, Row<'static>, Value<'static>)>>;
+    fn select_sample(
+        &self,
+        schema: &Schema,
+        filters: &[Filter],
+        sample: Sample,
+        limit: Option<usize>,
+    ) -> Result<Vec<(ObjectId, Row<'static>)>>;
+    fn select_changed(
+        &self,
+        schema: &Schema,
+        filters: &[Filter],
+        since: i64,
+    ) -> Result<Vec<(ObjectId, Row<'static>, i64)>>;
     fn delete_row(&self, id: ObjectId, schema: &Schema) -> Result<()>;
+    fn delete_expired(
+        &self,
+        schema: &Schema,
+        column: &str,
+        now: i64,
+        limit: usize,
+    ) -> Result<usize>;
+    // Upserts the rows of the schema's TEMP table into the stored one.
+    fn merge_staged(&self, schema: &Schema, match_columns: &[&str]) -> Result<usize>;
+    fn table_usage(&self, schema: &Schema) -> Result<TableUsage>;
+    fn estimate_rows(&self, schema: &Schema) -> Result<u64>;
+    fn sum_column(
+        &self,
+        schema: &Schema,
+        filters: &[Filter],
+        column: &str,
+        group_by: Option<&str>,
+    ) -> Result<Vec<(Value<'static>, i64)>>;
+    fn defer_foreign_keys(&self, enabled: bool) -> Result<()>;
+    fn savepoint(&self, name: &str) -> Result<()>;
+    fn release_savepoint(&self, name: &str) -> Result<()>;
+    // Undoes everything since the savepoint and releases it.
+    fn rollback_to_savepoint(&self, name: &str) -> Result<()>;
+    fn next_in_sequence(&self, name: &str) -> Result<i64>;
+    fn put_blob(&self, hash: &[u8], data: &[u8]) -> Result<()>;
+    fn get_blob(&self, hash: &[u8]) -> Result<Option<Vec<u8>>>;
+    fn delete_unreferenced_blobs(&self, references: &[(&str, &str)]) -> Result<usize>;
+    fn intern(&self, value: &str) -> Result<i64>;
+    fn interned_value(&self, id: i64) -> Result<Option<String>>;
+    fn applied_migrations(&self) -> Result<Vec<i64>>;
+    fn record_migration(&self, version: i64, name: &str) -> Result<()>;
 
     fn commit(&self) -> Result<()>;
     fn rollback(&self) -> Result<()>;
 }
 
-macro_rules! write_columns {
-    ($sql:ident, $schema:ident) => {
-        let tmp = $schema
-            .columns
-            .iter()
-            .map(|(name, _)| name.to_string())
-            .collect::<Vec<_>>();
-        write!($sql, "{}", tmp.join(", ")).unwrap();
+const MAX_BATCH_PARAMS: usize = 500;
+
+// Statements are cached by their SQL, which is specific to a table and an
+// operation. rusqlite keeps 16 by default, fewer than a loop touching a few
+// tables with splits, revisions or interned columns goes through, and an LRU
+// cache that's too small recompiles every statement on every call.
+pub(crate) const STATEMENT_CACHE_CAPACITY: usize = 256;
+
+pub(crate) fn open_sqlite(conn: rusqlite::Connection) -> rusqlite::Connection {
+    conn.set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+    conn
+}
+const SEQUENCES_TABLE: &str = "_orm_sequences";
+pub(crate) const BLOBS_TABLE: &str = "_orm_blobs";
+pub(crate) const INTERNED_TABLE: &str = "_orm_interned";
+const MIGRATIONS_TABLE: &str = "_orm_migrations";
+
+const STRICT_TABLES_VERSION: i32 = 3_037_000;
+const WINDOW_FUNCTIONS_VERSION: i32 = 3_025_000;
+
+// Split tables are written alongside the main one, so the id is bound first.
+static ID_COLUMN: ColumnDef = ColumnDef::new("id", DataType::Int64);
+
+pub(crate) fn quote_ident(name: &str) -> String {
+    Dialect::Sqlite.quote_ident(name)
+}
+
+fn join_splits(schema: &Schema, tables: &[&str]) -> String {
+    let main = quote_ident(schema.table_name);
+    let mut from = main.clone();
+    for table in tables {
+        let table = quote_ident(table);
+        write!(
+            &mut from,
+            " LEFT JOIN {} ON {}.id = {}.id",
+            table, table, main
+        )
+        .unwrap();
+    }
+    from
+}
+
+fn qualified_column(schema: &Schema, name: &str) -> String {
+    let table = match schema.column(name) {
+        Some(column) => schema.table_of(column),
+        None => schema.table_name,
     };
+    format!("{}.{}", quote_ident(table), quote_ident(name))
+}
+
+fn filter_conditions<'f>(
+    schema: &Schema,
+    filters: &'f [Filter],
+    params: &mut Vec<&'f dyn ToSql>,
+) -> Result<Vec<String>> {
+    filters
+        .iter()
+        .map(|filter| {
+            let column = qualified_column(schema, filter.column);
+            cipher::check_filter(schema, filter)?;
+            let interned = schema
+                .column(filter.column)
+                .is_some_and(|column| column.interned);
+            let bool_encoding = schema
+                .column(filter.column)
+                .and_then(|column| column.bool_encoding);
+            // Legacy bool columns read NULL as false, so filters do too.
+            if let (Some(encoding), Value::Bool(value)) = (bool_encoding, &filter.value) {
+                return Ok(format!(
+                    "IFNULL({}, {}) {} {}",
+                    column,
+                    encoding.sql_literal(false),
+                    filter.op.sql(),
+                    encoding.sql_literal(*value)
+                ));
+            }
+            Ok(match (filter.op, &filter.value) {
+                (Op::Eq, Value::Null) => format!("{} IS NULL", column),
+                (Op::Ne, Value::Null) => format!("{} IS NOT NULL", column),
+                // Compared by the strings referenced, not the references.
+                (op, value) if interned => {
+                    params.push(value);
+                    let condition = match op {
+                        Op::EqIgnoreCase => "lower(value) = lower(?)".to_string(),
+                        op => format!("value {} ?", op.sql()),
+                    };
+                    format!(
+                        "{} IN (SELECT id FROM {} WHERE {})",
+                        column,
+                        quote_ident(INTERNED_TABLE),
+                        condition
+                    )
+                }
+                (Op::EqIgnoreCase, value) => {
+                    params.push(value);
+                    format!("lower({}) = lower(?)", column)
+                }
+                (op, value) => {
+                    params.push(value);
+                    format!("{} {} ?", column, op.sql())
+                }
+            })
+        })
+        .collect()
 }
 
 fn error_by_scheme(schema: &Schema, e: rusqlite::Error, id: ObjectId) -> Error {
+    if let rusqlite::Error::FromSqlConversionFailure(i, Type::Text, source) = &e {
+        if let Some(column) = schema.columns.get(*i) {
+            return Error::InvalidText(Box::new(InvalidTextError {
+                type_name: schema.type_name,
+                attr_name: schema.attrs[*i],
+                table_name: schema.table_name,
+                column_name: column.name,
+                message: source.to_string(),
+            }));
+        }
+    }
     Error::from(match &e {
         rusqlite::Error::QueryReturnedNoRows => {
             ErrorWithCtx::new(e, ErrorCtx::not_found(id, schema.type_name))
         }
-        rusqlite::Error::InvalidColumnType(i, t, ty) => ErrorWithCtx::new(
-            rusqlite::Error::InvalidColumnType(*i, t.clone(), ty.clone()),
-            ErrorCtx {
-                table_name: schema.table_name.into(),
-                type_name: schema.type_name.into(),
-                attr_name: schema.attrs[*i].into(),
-                column_name: schema.columns[*i].0.into(),
-                expected_type: schema.columns[*i].1.into(),
-                got_type: ty.to_string().into(),
-                ..Default::default()
-            },
-        ),
+        rusqlite::Error::InvalidColumnType(i, t, ty) if *i < schema.columns.len() => {
+            ErrorWithCtx::new(
+                rusqlite::Error::InvalidColumnType(*i, t.clone(), ty.clone()),
+                ErrorCtx {
+                    table_name: schema.table_name.into(),
+                    type_name: schema.type_name.into(),
+                    attr_name: schema.attrs[*i].into(),
+                    column_name: schema.columns[*i].name.into(),
+                    expected_type: schema.columns[*i].stored_type().into(),
+                    got_type: ty.to_string().into(),
+                    ..Default::default()
+                },
+            )
+        }
         rusqlite::Error::SqliteFailure(_, text)
             if text.as_ref().is_some_and(|text| {
                 text.contains("no such column:") || text.contains("has no column named")
@@ -76,15 +344,47 @@ fn error_by_scheme(schema: &Schema, e: rusqlite::Error, id: ObjectId) -> Error {
             } else {
                 text.split("has no column named").last().unwrap().trim()
             };
+            // Selects qualify columns with their table, as in `user_profiles.bio`.
+            let column_name = column_name.rsplit('.').next().unwrap();
 
-            let pos = schema
+            if let Some(pos) = schema
                 .columns
                 .iter()
-                .position(|(name, _)| *name == column_name)
-                .unwrap();
+                .position(|column| column.name == column_name)
+            {
+                ctx.table_name = schema.table_of(&schema.columns[pos]).into();
+                ctx.column_name = schema.columns[pos].name.into();
+                ctx.attr_name = schema.attrs[pos].into();
+            }
 
-            ctx.column_name = schema.columns[pos].0.into();
-            ctx.attr_name = schema.attrs[pos].into();
+            ErrorWithCtx::new(e, ctx)
+        }
+        rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error {
+                code: rusqlite::ErrorCode::ConstraintViolation,
+                ..
+            },
+            text,
+        ) => {
+            let mut ctx = ErrorCtx {
+                table_name: schema.table_name.into(),
+                type_name: schema.type_name.into(),
+                ..Default::default()
+            };
+
+            let column_name = text
+                .as_deref()
+                .and_then(|text| text.split("constraint failed:").nth(1))
+                .and_then(|columns| columns.split(',').next())
+                .and_then(|column| column.trim().rsplit('.').next());
+            if let Some(pos) = schema
+                .columns
+                .iter()
+                .position(|column| Some(column.name) == column_name)
+            {
+                ctx.column_name = schema.columns[pos].name.into();
+                ctx.attr_name = schema.attrs[pos].into();
+            }
 
             ErrorWithCtx::new(e, ctx)
         }
@@ -92,13 +392,343 @@ fn error_by_scheme(schema: &Schema, e: rusqlite::Error, id: ObjectId) -> Error {
     })
 }
 
-fn row_exists(tx: &rusqlite::Transaction, id: ObjectId, schema: &Schema) -> Result<()> {
-    let sql = format!("SELECT 1 FROM {} WHERE id = ?", schema.table_name);
-    tx.query_row(&sql, [&id], |_| Ok(()))
-        .map_err(|e| error_by_scheme(schema, e, id))
+// Decoding errors carry the index of the schema column, not of the result one.
+fn decode_value<'a>(
+    value: ValueRef<'a>,
+    decoding: TextDecoding,
+    column: usize,
+) -> rusqlite::Result<Value<'a>> {
+    Value::borrow_sql(value, decoding)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(column, Type::Text, Box::new(e)))
+}
+
+fn read_values(
+    row: &rusqlite::Row,
+    start: usize,
+    decoding: TextDecoding,
+) -> rusqlite::Result<Row<'static>> {
+    let mut result = Vec::new();
+    for i in start.. {
+        match row.get_ref(i) {
+            Ok(val) => result.push(decode_value(val, decoding, i - start)?.into_owned()),
+            Err(rusqlite::Error::InvalidColumnIndex(_)) => break,
+            Err(e) => return Err(e),
+        };
+    }
+    Ok(result)
+}
+
+fn describe_params(params: &[&dyn ToSql]) -> Vec<String> {
+    params
+        .iter()
+        .map(|param| {
+            let output = match param.to_sql() {
+                Ok(output) => output,
+                Err(_) => return "?".to_string(),
+            };
+            let value = match &output {
+                ToSqlOutput::Borrowed(value) => *value,
+                ToSqlOutput::Owned(value) => value.into(),
+                _ => return "?".to_string(),
+            };
+            match value {
+                ValueRef::Null => "Null".to_string(),
+                ValueRef::Integer(_) => "Integer".to_string(),
+                ValueRef::Real(_) => "Real".to_string(),
+                ValueRef::Text(text) => format!("Text({} bytes)", text.len()),
+                ValueRef::Blob(blob) => format!("Blob({} bytes)", blob.len()),
+            }
+        })
+        .collect()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub(crate) struct SqliteTransaction<'a> {
+    tx: rusqlite::Transaction<'a>,
+    settings: &'a Settings,
+    // Column names by table, read once for hydrate_missing_columns. Any DDL
+    // run through this transaction clears them.
+    stored_columns: RefCell<HashMap<String, Vec<String>>>,
+}
+
+impl<'a> SqliteTransaction<'a> {
+    pub fn new(tx: rusqlite::Transaction<'a>, settings: &'a Settings) -> Self {
+        Self {
+            tx,
+            settings,
+            stored_columns: RefCell::default(),
+        }
+    }
+
+    fn with_statement(&self, err: Error, sql: &str, params: &[&dyn ToSql]) -> Error {
+        match err {
+            Error::Storage(source) if self.settings.debug_sql => {
+                Error::Storage(Box::new(StatementError {
+                    source,
+                    sql: sql.to_string(),
+                    params: describe_params(params),
+                }))
+            }
+            err => err,
+        }
+    }
+
+    fn with_row_statement(
+        &self,
+        err: Error,
+        sql: &str,
+        params: &[&dyn ToSql],
+        columns: impl IntoIterator<Item = &'static ColumnDef>,
+    ) -> Error {
+        let mut err = self.with_statement(err, sql, params);
+        if let Error::Storage(source) = &mut err {
+            if let Some(statement) = source.downcast_mut::<StatementError>() {
+                for (param, column) in statement.params.iter_mut().zip(columns) {
+                    if column.redacted {
+                        *param = "<redacted>".to_string();
+                    }
+                }
+            }
+        }
+        err
+    }
+
+    fn execute_cached(&self, sql: &str, params: &[&dyn ToSql]) -> rusqlite::Result<usize> {
+        self.prepare_cached(sql)?.execute(params)
+    }
+
+    fn query_row_cached<T, F>(&self, sql: &str, params: &[&dyn ToSql], f: F) -> rusqlite::Result<T>
+    where
+        F: FnOnce(&rusqlite::Row<'_>) -> rusqlite::Result<T>,
+    {
+        self.prepare_cached(sql)?.query_row(params, f)
+    }
+
+    fn query_rows(
+        &self,
+        schema: &Schema,
+        sql: &str,
+        params: &[&dyn ToSql],
+    ) -> Result<Vec<(ObjectId, Row<'static>)>> {
+        let rows = self.query_rows_with_extra(schema, sql, params, 0)?;
+        Ok(rows.into_iter().map(|(id, row, _)| (id, row)).collect())
+    }
+
+    // Like query_rows, but the last `extra` selected values aren't object
+    // columns and are returned as they were read.
+    fn query_rows_with_extra(
+        &self,
+        schema: &Schema,
+        sql: &str,
+        params: &[&dyn ToSql],
+        extra: usize,
+    ) -> Result<Vec<(ObjectId, Row<'static>, Row<'static>)>> {
+        let to_error =
+            |e| self.with_statement(error_by_scheme(schema, e, ObjectId::new(0)), sql, params);
+        let mut stmt = self.prepare_cached(sql).map_err(to_error)?;
+        let rows = stmt
+            .query_map(params, |row| {
+                Ok((
+                    ObjectId::new(row.get(0)?),
+                    read_values(row, 1, self.settings.text_decoding)?,
+                ))
+            })
+            .map_err(to_error)?
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .map_err(to_error)?;
+
+        rows.into_iter()
+            .map(|(id, mut row): (ObjectId, Row<'static>)| {
+                let trailing = row.split_off(row.len() - extra);
+                let row = convert_by_schema(row, schema, self.settings.coercion)?;
+                Ok((id, row, trailing))
+            })
+            .collect()
+    }
+
+    // Returns the select list and the FROM clause joining in split tables.
+    // Columns the table doesn't have yet are read as the value a migration
+    // adding them would fill in, so readers can be upgraded before the database.
+    fn select_source(&self, schema: &Schema) -> Result<(String, String)> {
+        let mut existing = HashMap::new();
+        if self.settings.hydrate_missing_columns {
+            for table in std::iter::once(schema.table_name).chain(schema.split_tables()) {
+                existing.insert(table, self.stored_columns(table)?);
+            }
+        }
+        let mut joined = Vec::new();
+        let columns = schema
+            .columns
+            .iter()
+            .map(|column| {
+                let table = schema.table_of(column);
+                let name = quote_ident(column.name);
+                let missing = existing.get(table).is_some_and(|existing| {
+                    !existing
+                        .iter()
+                        .any(|name| name.eq_ignore_ascii_case(column.name))
+                });
+                if missing {
+                    return format!("{} AS {}", ddl::missing_column_sql(column), name);
+                }
+                if table != schema.table_name && !joined.contains(&table) {
+                    joined.push(table);
+                }
+                format!("{}.{}", quote_ident(table), name)
+            })
+            .collect::<Vec<_>>();
+        Ok((columns.join(", "), join_splits(schema, &joined)))
+    }
+
+    fn stored_columns(&self, table: &str) -> Result<Vec<String>> {
+        if let Some(names) = self.stored_columns.borrow().get(table) {
+            return Ok(names.clone());
+        }
+        let names = self
+            .table_columns(table)?
+            .into_iter()
+            .map(|info| info.name)
+            .collect::<Vec<_>>();
+        self.stored_columns
+            .borrow_mut()
+            .insert(table.to_string(), names.clone());
+        Ok(names)
+    }
+
+    fn select_with_id(&self, schema: &Schema) -> Result<String> {
+        self.select_hinted(schema, None)
+    }
+
+    fn select_hinted(&self, schema: &Schema, hint: Option<&IndexHint>) -> Result<String> {
+        let (columns, mut from) = self.select_source(schema)?;
+        // The main table comes first in the FROM clause, and the hint has to
+        // follow it directly.
+        let main = quote_ident(schema.table_name).len();
+        match hint {
+            Some(IndexHint::Use(name)) => {
+                from.insert_str(main, &format!(" INDEXED BY {}", quote_ident(name)))
+            }
+            Some(IndexHint::NoIndex) => from.insert_str(main, " NOT INDEXED"),
+            None => {}
+        }
+        let mut sql = format!("SELECT {}.id", quote_ident(schema.table_name));
+        if !columns.is_empty() {
+            write!(&mut sql, ", {}", columns).unwrap();
+        }
+        write!(&mut sql, " FROM {}", from).unwrap();
+        Ok(sql)
+    }
+
+    // Every write takes the next revision of the table, see Schema::revisions.
+    fn bump_revision(&self, id: ObjectId, schema: &Schema) -> Result<()> {
+        if !schema.revisions {
+            return Ok(());
+        }
+        let revision =
+            self.next_in_sequence(&format!("{}.{}", schema.table_name, REVISION_COLUMN.name))?;
+        let sql = format!(
+            "UPDATE {} SET {} = ? WHERE id = ?",
+            quote_ident(schema.table_name),
+            quote_ident(REVISION_COLUMN.name)
+        );
+        let params: [&dyn ToSql; 2] = [&revision, &id];
+        self.execute_cached(&sql, params.as_slice())
+            .map_err(|e| self.with_statement(error_by_scheme(schema, e, id), &sql, &params))?;
+        Ok(())
+    }
+
+    fn write_splits(&self, id: ObjectId, schema: &Schema, row: &RowSlice) -> Result<()> {
+        for table in schema.split_tables() {
+            let columns = schema.columns_in(table).collect::<Vec<_>>();
+            let names = columns
+                .iter()
+                .map(|(_, column)| quote_ident(column.name))
+                .collect::<Vec<_>>();
+            let updates = names
+                .iter()
+                .map(|name| format!("{} = excluded.{}", name, name))
+                .collect::<Vec<_>>();
+            let sql = format!(
+                "INSERT INTO {} (id, {}) VALUES (?{}) ON CONFLICT (id) DO UPDATE SET {}",
+                quote_ident(table),
+                names.join(", "),
+                ", ?".repeat(columns.len()),
+                updates.join(", ")
+            );
+            let mut params: Vec<&dyn ToSql> = vec![&id];
+            params.extend(columns.iter().map(|(i, _)| &row[*i] as &dyn ToSql));
+            self.execute_cached(&sql, params.as_slice()).map_err(|e| {
+                // The leading id is never redacted.
+                let columns = std::iter::once(&ID_COLUMN).chain(columns.iter().map(|(_, c)| *c));
+                self.with_row_statement(error_by_scheme(schema, e, id), &sql, &params, columns)
+            })?;
+        }
+        Ok(())
+    }
+
+    fn delete_orphaned_splits(&self, schema: &Schema) -> Result<()> {
+        for table in schema.split_tables() {
+            let sql = format!(
+                "DELETE FROM {} WHERE id NOT IN (SELECT id FROM {})",
+                quote_ident(table),
+                quote_ident(schema.table_name)
+            );
+            self.execute_cached(&sql, &[])
+                .map_err(|e| self.with_statement(Error::from(e), &sql, &[]))?;
+        }
+        Ok(())
+    }
+
+    // There's no rowid to hand out ids, so the next one is picked explicitly.
+    // Unlike AUTOINCREMENT, the ids of rows deleted from the end may be reused.
+    fn insert_without_rowid(&self, schema: &Schema, row: &RowSlice) -> Result<ObjectId> {
+        let table = quote_ident(schema.table_name);
+        let columns = schema.columns_in(schema.table_name).collect::<Vec<_>>();
+        let mut sql = format!("INSERT INTO {} (id", table);
+        for (_, column) in &columns {
+            write!(&mut sql, ", {}", quote_ident(column.name)).unwrap();
+        }
+        write!(
+            &mut sql,
+            ") VALUES ((SELECT IFNULL(MAX(id), 0) + 1 FROM {}){}) RETURNING id",
+            table,
+            ", ?".repeat(columns.len())
+        )
+        .unwrap();
+        let params: Vec<&dyn ToSql> = columns
+            .iter()
+            .map(|(i, _)| &row[*i] as &dyn ToSql)
+            .collect();
+        self.query_row_cached(&sql, params.as_slice(), |row| row.get(0))
+            .map(ObjectId::new)
+            .map_err(|e| {
+                let id = ObjectId::new(0);
+                let columns = columns.iter().map(|(_, column)| *column);
+                self.with_row_statement(error_by_scheme(schema, e, id), &sql, &params, columns)
+            })
+    }
+
+    fn row_exists(&self, id: ObjectId, schema: &Schema) -> Result<()> {
+        let sql = format!(
+            "SELECT 1 FROM {} WHERE id = ?",
+            quote_ident(schema.table_name)
+        );
+        let params: [&dyn ToSql; 1] = [&id];
+        self.query_row_cached(&sql, params.as_slice(), |_| Ok(()))
+            .map_err(|e| self.with_statement(error_by_scheme(schema, e, id), &sql, &params))
+    }
+}
+
+impl<'a> Deref for SqliteTransaction<'a> {
+    type Target = rusqlite::Transaction<'a>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.tx
+    }
 }
 
-impl<'a> StorageTransaction for rusqlite::Transaction<'a> {
+impl<'a> StorageTransaction for SqliteTransaction<'a> {
     fn table_exists(&self, table: &str) -> Result<bool> {
         let exists = self
             .query_row(
@@ -110,83 +740,914 @@ impl<'a> StorageTransaction for rusqlite::Transaction<'a> {
         Ok(exists)
     }
 
+    fn table_names(&self) -> Result<Vec<String>> {
+        let mut stmt = self.prepare(
+            "SELECT name FROM sqlite_master \
+            WHERE type = 'table' AND name NOT LIKE 'sqlite\\_%' ESCAPE '\\' \
+            AND name NOT LIKE '\\_orm\\_%' ESCAPE '\\' ORDER BY name",
+        )?;
+        let names = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(names)
+    }
+
+    fn table_columns(&self, table: &str) -> Result<Vec<ColumnInfo>> {
+        let mut stmt = self.prepare(
+            "SELECT name, type, \"notnull\", dflt_value, pk FROM pragma_table_info(?) ORDER BY cid",
+        )?;
+        let columns = stmt
+            .query_map([&table], |row| {
+                Ok(ColumnInfo {
+                    name: row.get(0)?,
+                    sql_type: row.get(1)?,
+                    not_null: row.get(2)?,
+                    default: row.get(3)?,
+                    primary_key: row.get::<_, i64>(4)? != 0,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(columns)
+    }
+
+    fn execute_ddl(&self, statements: &[String]) -> Result<()> {
+        self.stored_columns.borrow_mut().clear();
+        for sql in statements {
+            self.execute(sql, [])
+                .map_err(|e| self.with_statement(Error::from(e), sql, &[]))?;
+        }
+        Ok(())
+    }
+
+    fn create_temp_table(&self, schema: &Schema) -> Result<()> {
+        self.execute_ddl(&schema.create_temp_statements(Dialect::Sqlite))
+    }
+
+    fn drop_temp_table(&self, schema: &Schema) -> Result<()> {
+        self.execute_ddl(&schema.drop_temp_statements(Dialect::Sqlite))
+    }
+
     fn create_table(&self, schema: &Schema) -> Result<()> {
-        let mut sql = format!("CREATE TABLE {} (", schema.table_name);
-        let mut columns = vec!["id INTEGER PRIMARY KEY AUTOINCREMENT".to_string()];
-        for (name, ty) in schema.columns {
-            columns.push(format!("{} {}", name, ty));
+        let schema = Schema {
+            strict: schema.strict || self.settings.strict_tables,
+            ..*schema
+        };
+        if schema.strict && rusqlite::version_number() < STRICT_TABLES_VERSION {
+            return Err(Error::Unsupported(Box::new(UnsupportedError {
+                feature: "STRICT tables".to_string(),
+                required: "3.37.0".to_string(),
+                found: rusqlite::version().to_string(),
+            })));
+        }
+        self.stored_columns.borrow_mut().clear();
+        for sql in schema.create_statements(Dialect::Sqlite) {
+            self.execute(&sql, [])
+                .map_err(|e| self.with_statement(Error::from(e), &sql, &[]))?;
         }
-        write!(&mut sql, "{}", columns.join(", ")).unwrap();
-        write!(&mut sql, ")").unwrap();
-        self.execute(&sql, []).map_err(Error::from)?;
         Ok(())
     }
 
     fn insert_row(&self, schema: &Schema, row: &RowSlice) -> Result<ObjectId> {
-        let mut sql = format!("INSERT INTO {}", schema.table_name);
-        if !row.is_empty() {
-            write!(&mut sql, " (").unwrap();
-            write_columns!(sql, schema);
-            write!(&mut sql, ") VALUES (").unwrap();
-            write!(&mut sql, "{}", vec!["?"; row.len()].join(", ")).unwrap();
-            write!(&mut sql, ")").unwrap();
+        if schema.without_rowid {
+            let id = self.insert_without_rowid(schema, row)?;
+            self.write_splits(id, schema, row)?;
+            self.bump_revision(id, schema)?;
+            return Ok(id);
+        }
+        let columns = schema.columns_in(schema.table_name).collect::<Vec<_>>();
+        let mut sql = format!("INSERT INTO {}", quote_ident(schema.table_name));
+        if !columns.is_empty() {
+            let names = columns
+                .iter()
+                .map(|(_, column)| quote_ident(column.name))
+                .collect::<Vec<_>>();
+            write!(
+                &mut sql,
+                " ({}) VALUES ({})",
+                names.join(", "),
+                vec!["?"; columns.len()].join(", ")
+            )
+            .unwrap();
         } else {
             write!(&mut sql, " DEFAULT VALUES").unwrap();
         }
-        let params: Vec<&dyn ToSql> = row.iter().map(|x| x as &dyn ToSql).collect();
-        self.execute(&sql, params.as_slice())
-            .map_err(|e| error_by_scheme(schema, e, ObjectId::new(self.last_insert_rowid())))?;
-        Ok(ObjectId::new(self.last_insert_rowid()))
+        let params: Vec<&dyn ToSql> = columns
+            .iter()
+            .map(|(i, _)| &row[*i] as &dyn ToSql)
+            .collect();
+        self.execute_cached(&sql, params.as_slice()).map_err(|e| {
+            let id = ObjectId::new(self.last_insert_rowid());
+            let columns = columns.iter().map(|(_, column)| *column);
+            self.with_row_statement(error_by_scheme(schema, e, id), &sql, &params, columns)
+        })?;
+        let id = ObjectId::new(self.last_insert_rowid());
+        self.write_splits(id, schema, row)?;
+        self.bump_revision(id, schema)?;
+        Ok(id)
     }
 
     fn update_row(&self, id: ObjectId, schema: &Schema, row: &RowSlice) -> Result<()> {
-        if schema.columns.is_empty() {
-            return row_exists(self, id, schema);
+        let columns = schema.columns_in(schema.table_name).collect::<Vec<_>>();
+        if columns.is_empty() {
+            self.row_exists(id, schema)?;
+            self.write_splits(id, schema, row)?;
+            return self.bump_revision(id, schema);
         }
-        let mut sql = format!("UPDATE {} SET ", schema.table_name);
-        let mut columns = Vec::new();
-        for (name, _) in schema.columns.iter() {
-            columns.push(format!("{} = ?", name));
-        }
-        write!(&mut sql, "{}", columns.join(", ")).unwrap();
+        let mut sql = format!("UPDATE {} SET ", quote_ident(schema.table_name));
+        let assignments = columns
+            .iter()
+            .map(|(_, column)| format!("{} = ?", quote_ident(column.name)))
+            .collect::<Vec<_>>();
+        write!(&mut sql, "{}", assignments.join(", ")).unwrap();
         write!(&mut sql, " WHERE id = ?").unwrap();
 
-        let mut params: Vec<&dyn ToSql> = row.iter().map(|x| x as &dyn ToSql).collect();
+        let mut params: Vec<&dyn ToSql> = columns
+            .iter()
+            .map(|(i, _)| &row[*i] as &dyn ToSql)
+            .collect();
         params.push(&id);
-        self.execute(&sql, params.as_slice())
-            .map_err(|e| error_by_scheme(schema, e, id))?;
+        let updated = self.execute_cached(&sql, params.as_slice()).map_err(|e| {
+            let columns = columns.iter().map(|(_, column)| *column);
+            self.with_row_statement(error_by_scheme(schema, e, id), &sql, &params, columns)
+        })?;
+        if updated != 0 {
+            self.write_splits(id, schema, row)?;
+            self.bump_revision(id, schema)?;
+        }
         Ok(())
     }
 
+    fn increment(&self, id: ObjectId, schema: &Schema, column: &str, delta: i64) -> Result<i64> {
+        let table = schema
+            .column(column)
+            .map_or(schema.table_name, |c| schema.table_of(c));
+        if table != schema.table_name {
+            // Like write_splits, add the split row if the object has none yet.
+            self.row_exists(id, schema)?;
+            let sql = format!(
+                "INSERT INTO {} (id) VALUES (?) ON CONFLICT (id) DO NOTHING",
+                quote_ident(table)
+            );
+            let params: [&dyn ToSql; 1] = [&id];
+            self.execute_cached(&sql, params.as_slice())
+                .map_err(|e| self.with_statement(error_by_scheme(schema, e, id), &sql, &params))?;
+        }
+        let table = quote_ident(table);
+        let column = quote_ident(column);
+        let sql = format!(
+            "UPDATE {} SET {} = IFNULL({}, 0) + ? WHERE id = ?",
+            table, column, column
+        );
+        let params: [&dyn ToSql; 2] = [&delta, &id];
+        let updated = self
+            .execute_cached(&sql, params.as_slice())
+            .map_err(|e| self.with_statement(error_by_scheme(schema, e, id), &sql, &params))?;
+        if updated == 0 {
+            self.row_exists(id, schema)?;
+        }
+        self.bump_revision(id, schema)?;
+
+        let sql = format!("SELECT {} FROM {} WHERE id = ?", column, table);
+        let params: [&dyn ToSql; 1] = [&id];
+        self.query_row_cached(&sql, params.as_slice(), |row| row.get(0))
+            .map_err(|e| self.with_statement(error_by_scheme(schema, e, id), &sql, &params))
+    }
+
     fn select_row(&self, id: ObjectId, schema: &Schema) -> Result<Row<'static>> {
         if schema.columns.is_empty() {
-            return row_exists(self, id, schema).map(|_| Vec::new());
-        }
-        let mut sql = "SELECT ".to_string();
-        write_columns!(sql, schema);
-        write!(&mut sql, " FROM {} WHERE id = ?", schema.table_name).unwrap();
-
-        let val = self.query_row(&sql, [&id], |row| {
-            let mut result = Vec::new();
-            for i in 0.. {
-                match row.get(i) {
-                    Ok(val) => result.push(val),
-                    Err(rusqlite::Error::InvalidColumnIndex(_)) => break,
-                    Err(e) => return Err(e),
-                };
-            }
-            Ok(result)
+            return self.row_exists(id, schema).map(|_| Vec::new());
+        }
+        let (columns, from) = self.select_source(schema)?;
+        let sql = format!(
+            "SELECT {} FROM {} WHERE {}.id = ?",
+            columns,
+            from,
+            quote_ident(schema.table_name)
+        );
+
+        let params: [&dyn ToSql; 1] = [&id];
+        let val = self.query_row_cached(&sql, params.as_slice(), |row| {
+            read_values(row, 0, self.settings.text_decoding)
         });
-        let val = val.map_err(|e| error_by_scheme(schema, e, id))?;
-        convert_by_schema(val, schema)
+        let val =
+            val.map_err(|e| self.with_statement(error_by_scheme(schema, e, id), &sql, &params))?;
+        convert_by_schema(val, schema, self.settings.coercion)
+    }
+
+    fn has_row(&self, id: ObjectId, schema: &Schema) -> Result<bool> {
+        match self.row_exists(id, schema) {
+            Ok(()) => Ok(true),
+            Err(Error::NotFound(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    // A no-op write takes SQLite's reserved lock, so no other connection can
+    // start writing until this transaction ends.
+    fn lock_row(&self, id: ObjectId, schema: &Schema) -> Result<()> {
+        let sql = format!(
+            "UPDATE {} SET id = id WHERE id = ?",
+            quote_ident(schema.table_name)
+        );
+        let params: [&dyn ToSql; 1] = [&id];
+        let updated = self
+            .execute_cached(&sql, params.as_slice())
+            .map_err(|e| self.with_statement(error_by_scheme(schema, e, id), &sql, &params))?;
+        if updated == 0 {
+            self.row_exists(id, schema)?;
+        }
+        Ok(())
+    }
+
+    fn with_row(&self, id: ObjectId, schema: &Schema, f: &mut dyn FnMut(Row<'_>)) -> Result<()> {
+        if schema.columns.is_empty() {
+            return self.row_exists(id, schema).map(|_| f(Vec::new()));
+        }
+        let (columns, from) = self.select_source(schema)?;
+        let sql = format!(
+            "SELECT {} FROM {} WHERE {}.id = ?",
+            columns,
+            from,
+            quote_ident(schema.table_name)
+        );
+
+        let params: [&dyn ToSql; 1] = [&id];
+        let val = self.query_row_cached(&sql, params.as_slice(), |row| {
+            let values = (0..schema.columns.len())
+                .map(|i| decode_value(row.get_ref(i)?, self.settings.text_decoding, i))
+                .collect::<rusqlite::Result<Row<'_>>>()?;
+            Ok(convert_by_schema(values, schema, self.settings.coercion).map(&mut *f))
+        });
+        val.map_err(|e| self.with_statement(error_by_scheme(schema, e, id), &sql, &params))?
+    }
+
+    fn select_page(
+        &self,
+        schema: &Schema,
+        after: Option<ObjectId>,
+        limit: usize,
+    ) -> Result<Vec<(ObjectId, Row<'static>)>> {
+        let mut sql = self.select_with_id(schema)?;
+        let table = quote_ident(schema.table_name);
+        write!(
+            &mut sql,
+            " WHERE {}.id > ? ORDER BY {}.id LIMIT ?",
+            table, table
+        )
+        .unwrap();
+
+        let after = after.unwrap_or(ObjectId::new(0));
+        let limit = limit as i64;
+        self.query_rows(schema, &sql, &[&after, &limit])
+    }
+
+    fn select_rows(
+        &self,
+        schema: &Schema,
+        filters: &[Filter],
+        hint: Option<&IndexHint>,
+        limit: Option<usize>,
+    ) -> Result<Vec<(ObjectId, Row<'static>)>> {
+        let mut sql = self.select_hinted(schema, hint)?;
+        let mut params: Vec<&dyn ToSql> = Vec::new();
+        let conditions = filter_conditions(schema, filters, &mut params)?;
+        if !conditions.is_empty() {
+            write!(&mut sql, " WHERE {}", conditions.join(" AND ")).unwrap();
+        }
+        write!(&mut sql, " ORDER BY {}.id", quote_ident(schema.table_name)).unwrap();
+
+        let limit = limit.map(|limit| limit as i64);
+        if let Some(limit) = &limit {
+            sql.push_str(" LIMIT ?");
+            params.push(limit);
+        }
+        self.query_rows(schema, &sql, &params)
+    }
+
+    // Rows come nearest first, each at the depth it was first reached. Depth is
+    // capped at the row count, so a cycle ends the recursion.
+    fn select_recursive(
+        &self,
+        schema: &Schema,
+        seed: &[Filter],
+        step: Step,
+        filters: &[Filter],
+        limit: Option<usize>,
+    ) -> Result<Vec<(ObjectId, Row<'static>)>> {
+        let table = quote_ident(schema.table_name);
+        let source = join_splits(schema, &schema.split_tables());
+        let link = qualified_column(schema, step.previous);
+        let mut params: Vec<&dyn ToSql> = Vec::new();
+
+        let mut sql = format!(
+            "WITH RECURSIVE _orm_tree(id, link, depth) AS (SELECT {}.id, {}, 1 FROM {}",
+            table, link, source
+        );
+        let conditions = filter_conditions(schema, seed, &mut params)?;
+        if !conditions.is_empty() {
+            write!(&mut sql, " WHERE {}", conditions.join(" AND ")).unwrap();
+        }
+        write!(
+            &mut sql,
+            " UNION ALL SELECT {}.id, {}, _orm_tree.depth + 1 FROM {} \
+             JOIN _orm_tree ON {} = _orm_tree.link \
+             WHERE _orm_tree.depth < (SELECT COUNT(*) FROM {})) ",
+            table,
+            link,
+            source,
+            qualified_column(schema, step.column),
+            table
+        )
+        .unwrap();
+
+        sql.push_str(&self.select_with_id(schema)?);
+        write!(
+            &mut sql,
+            " JOIN (SELECT id, MIN(depth) AS depth FROM _orm_tree GROUP BY id) AS _orm_nodes \
+             ON _orm_nodes.id = {}.id",
+            table
+        )
+        .unwrap();
+        let conditions = filter_conditions(schema, filters, &mut params)?;
+        if !conditions.is_empty() {
+            write!(&mut sql, " WHERE {}", conditions.join(" AND ")).unwrap();
+        }
+        write!(&mut sql, " ORDER BY _orm_nodes.depth, {}.id", table).unwrap();
+
+        let limit = limit.map(|limit| limit as i64);
+        if let Some(limit) = &limit {
+            sql.push_str(" LIMIT ?");
+            params.push(limit);
+        }
+        self.query_rows(schema, &sql, &params)
+    }
+
+    // The window is evaluated over the filtered rows, and its own filter is
+    // applied to the result, so "first row per group" sees only matching rows.
+    fn select_window(
+        &self,
+        schema: &Schema,
+        filters: &[Filter],
+        window: &Window,
+        limit: Option<usize>,
+    ) -> Result<Vec<(ObjectId, Row<'static>, Value<'static>)>> {
+        if rusqlite::version_number() < WINDOW_FUNCTIONS_VERSION {
+            return Err(Error::Unsupported(Box::new(UnsupportedError {
+                feature: "window functions".to_string(),
+                required: "3.25.0".to_string(),
+                found: rusqlite::version().to_string(),
+            })));
+        }
+
+        let function = match window.function {
+            WindowFunction::RowNumber => "row_number()".to_string(),
+            WindowFunction::Rank => "rank()".to_string(),
+            WindowFunction::Lag(column, offset) => {
+                format!("lag({}, {})", qualified_column(schema, column), offset)
+            }
+            WindowFunction::Lead(column, offset) => {
+                format!("lead({}, {})", qualified_column(schema, column), offset)
+            }
+        };
+        let mut over = Vec::new();
+        if !window.partition_by.is_empty() {
+            let columns = window
+                .partition_by
+                .iter()
+                .map(|column| qualified_column(schema, column))
+                .collect::<Vec<_>>();
+            over.push(format!("PARTITION BY {}", columns.join(", ")));
+        }
+        if !window.order_by.is_empty() {
+            let columns = window
+                .order_by
+                .iter()
+                .map(|(column, desc)| {
+                    let order = if *desc { "DESC" } else { "ASC" };
+                    format!("{} {}", qualified_column(schema, column), order)
+                })
+                .collect::<Vec<_>>();
+            over.push(format!("ORDER BY {}", columns.join(", ")));
+        }
+
+        let (columns, from) = self.select_source(schema)?;
+        let mut sql = format!(
+            "SELECT * FROM (SELECT {}.id",
+            quote_ident(schema.table_name)
+        );
+        if !columns.is_empty() {
+            write!(&mut sql, ", {}", columns).unwrap();
+        }
+        write!(
+            &mut sql,
+            ", {} OVER ({}) AS _orm_window FROM {}",
+            function,
+            over.join(" "),
+            from
+        )
+        .unwrap();
+        let mut params: Vec<&dyn ToSql> = Vec::new();
+        let conditions = filter_conditions(schema, filters, &mut params)?;
+        if !conditions.is_empty() {
+            write!(&mut sql, " WHERE {}", conditions.join(" AND ")).unwrap();
+        }
+        sql.push(')');
+        if let Some((op, value)) = &window.filter {
+            match (op, value) {
+                (Op::Eq, Value::Null) => sql.push_str(" WHERE _orm_window IS NULL"),
+                (Op::Ne, Value::Null) => sql.push_str(" WHERE _orm_window IS NOT NULL"),
+                (op, value) => {
+                    write!(&mut sql, " WHERE _orm_window {} ?", op.sql()).unwrap();
+                    params.push(value);
+                }
+            }
+        }
+        sql.push_str(" ORDER BY id");
+
+        let limit = limit.map(|limit| limit as i64);
+        if let Some(limit) = &limit {
+            sql.push_str(" LIMIT ?");
+            params.push(limit);
+        }
+        let rows = self.query_rows_with_extra(schema, &sql, &params, 1)?;
+        Ok(rows
+            .into_iter()
+            .map(|(id, row, mut extra)| (id, row, extra.pop().unwrap()))
+            .collect())
+    }
+
+    // A count shuffles only the ids of matching rows rather than whole rows,
+    // and a fraction keeps each row by its own random draw without sorting.
+    // Either way the sample comes back ordered by id.
+    fn select_sample(
+        &self,
+        schema: &Schema,
+        filters: &[Filter],
+        sample: Sample,
+        limit: Option<usize>,
+    ) -> Result<Vec<(ObjectId, Row<'static>)>> {
+        let table = quote_ident(schema.table_name);
+        let mut sql = self.select_with_id(schema)?;
+        let mut params: Vec<&dyn ToSql> = Vec::new();
+        let mut conditions = filter_conditions(schema, filters, &mut params)?;
+        let count;
+        let threshold;
+        match sample {
+            Sample::Count(n) => {
+                count = n as i64;
+                let mut ids = format!(
+                    "{}.id IN (SELECT {}.id FROM {}",
+                    table,
+                    table,
+                    join_splits(schema, &schema.split_tables())
+                );
+                if !conditions.is_empty() {
+                    write!(&mut ids, " WHERE {}", conditions.join(" AND ")).unwrap();
+                }
+                ids.push_str(" ORDER BY random() LIMIT ?)");
+                params.push(&count);
+                conditions = vec![ids];
+            }
+            Sample::Fraction(fraction) => {
+                threshold = (fraction * (1u64 << 32) as f64) as i64;
+                conditions.push("(random() & 4294967295) < ?".to_string());
+                params.push(&threshold);
+            }
+        }
+        write!(&mut sql, " WHERE {}", conditions.join(" AND ")).unwrap();
+        write!(&mut sql, " ORDER BY {}.id", table).unwrap();
+
+        let limit = limit.map(|limit| limit as i64);
+        if let Some(limit) = &limit {
+            sql.push_str(" LIMIT ?");
+            params.push(limit);
+        }
+        self.query_rows(schema, &sql, &params)
+    }
+
+    fn select_changed(
+        &self,
+        schema: &Schema,
+        filters: &[Filter],
+        since: i64,
+    ) -> Result<Vec<(ObjectId, Row<'static>, i64)>> {
+        let table = quote_ident(schema.table_name);
+        let revision = format!("{}.{}", table, quote_ident(REVISION_COLUMN.name));
+        let (columns, from) = self.select_source(schema)?;
+        let mut sql = format!("SELECT {}.id", table);
+        if !columns.is_empty() {
+            write!(&mut sql, ", {}", columns).unwrap();
+        }
+        write!(
+            &mut sql,
+            ", {} FROM {} WHERE {} > ?",
+            revision, from, revision
+        )
+        .unwrap();
+        let mut params: Vec<&dyn ToSql> = vec![&since];
+        for condition in filter_conditions(schema, filters, &mut params)? {
+            write!(&mut sql, " AND {}", condition).unwrap();
+        }
+        write!(&mut sql, " ORDER BY {}", revision).unwrap();
+        let rows = self.query_rows_with_extra(schema, &sql, &params, 1)?;
+        rows.into_iter()
+            .map(|(id, row, extra)| {
+                let revision = i64::try_from_value(&extra[0]).map_err(|e| {
+                    Error::UnexpectedType(Box::new(UnexpectedTypeError {
+                        type_name: schema.type_name,
+                        attr_name: REVISION_COLUMN.name,
+                        table_name: schema.table_name,
+                        column_name: REVISION_COLUMN.name,
+                        expected_type: REVISION_COLUMN.data_type,
+                        got_type: e.got,
+                    }))
+                })?;
+                Ok((id, row, revision))
+            })
+            .collect()
+    }
+
+    fn select_ids(
+        &self,
+        schema: &Schema,
+        ids: &[ObjectId],
+    ) -> Result<Vec<(ObjectId, Row<'static>)>> {
+        let select = self.select_with_id(schema)?;
+        let mut rows = Vec::new();
+        for chunk in ids.chunks(MAX_BATCH_PARAMS) {
+            let mut sql = select.clone();
+            let placeholders = vec!["?"; chunk.len()].join(", ");
+            write!(
+                &mut sql,
+                " WHERE {}.id IN ({})",
+                quote_ident(schema.table_name),
+                placeholders
+            )
+            .unwrap();
+            let params = chunk.iter().map(|id| id as &dyn ToSql).collect::<Vec<_>>();
+            rows.extend(self.query_rows(schema, &sql, &params)?);
+        }
+        Ok(rows)
     }
 
     fn delete_row(&self, id: ObjectId, schema: &Schema) -> Result<()> {
-        let sql = format!("DELETE FROM {} WHERE id = ?", schema.table_name);
-        self.execute(&sql, [&id]).map_err(Error::from)?;
+        let sql = format!(
+            "DELETE FROM {} WHERE id = ?",
+            quote_ident(schema.table_name)
+        );
+        let params: [&dyn ToSql; 1] = [&id];
+        self.execute_cached(&sql, params.as_slice())
+            .map_err(|e| self.with_statement(Error::from(e), &sql, &params))?;
+        for table in schema.split_tables() {
+            let sql = format!("DELETE FROM {} WHERE id = ?", quote_ident(table));
+            self.execute_cached(&sql, params.as_slice())
+                .map_err(|e| self.with_statement(Error::from(e), &sql, &params))?;
+        }
         Ok(())
     }
 
+    fn delete_expired(
+        &self,
+        schema: &Schema,
+        column: &str,
+        now: i64,
+        limit: usize,
+    ) -> Result<usize> {
+        let table = quote_ident(schema.table_name);
+        let sql = format!(
+            "DELETE FROM {} WHERE id IN (SELECT id FROM {} WHERE {} <= ? LIMIT ?)",
+            table,
+            table,
+            quote_ident(column)
+        );
+        let limit = limit as i64;
+        let params: [&dyn ToSql; 2] = [&now, &limit];
+        let deleted = self
+            .execute_cached(&sql, params.as_slice())
+            .map_err(|e| self.with_statement(Error::from(e), &sql, &params))?;
+        self.delete_orphaned_splits(schema)?;
+        Ok(deleted)
+    }
+
+    // The stored table assigns ids of its own. All merged rows share the next
+    // revision, as if written by a single update.
+    fn merge_staged(&self, schema: &Schema, match_columns: &[&str]) -> Result<usize> {
+        let table = quote_ident(schema.table_name);
+        let mut names = schema
+            .columns_in(schema.table_name)
+            .map(|(_, column)| quote_ident(column.name))
+            .collect::<Vec<_>>();
+        let mut values = names.clone();
+        let mut revision = None;
+        if schema.revisions {
+            names.push(quote_ident(REVISION_COLUMN.name));
+            values.push("?".to_string());
+            revision = Some(
+                self.next_in_sequence(&format!("{}.{}", schema.table_name, REVISION_COLUMN.name))?,
+            );
+        }
+        let keys = match_columns
+            .iter()
+            .map(|name| quote_ident(name))
+            .collect::<Vec<_>>();
+        let updates = names
+            .iter()
+            .filter(|name| !keys.contains(name))
+            .map(|name| format!("{} = excluded.{}", name, name))
+            .collect::<Vec<_>>();
+        // `WHERE true` keeps ON CONFLICT from parsing as a join constraint.
+        let mut sql = format!(
+            "INSERT INTO main.{} ({}) SELECT {} FROM temp.{} WHERE true ON CONFLICT ({}) DO ",
+            table,
+            names.join(", "),
+            values.join(", "),
+            table,
+            keys.join(", ")
+        );
+        if updates.is_empty() {
+            sql.push_str("NOTHING");
+        } else {
+            write!(&mut sql, "UPDATE SET {}", updates.join(", ")).unwrap();
+        }
+        let params = revision.iter().map(|r| r as &dyn ToSql).collect::<Vec<_>>();
+        self.execute_cached(&sql, params.as_slice())
+            .map_err(|e| self.with_statement(Error::from(e), &sql, &params))
+    }
+
+    fn table_usage(&self, schema: &Schema) -> Result<TableUsage> {
+        // Approximate: the id plus the stored length of every column.
+        let mut bytes = String::from("8");
+        for column in schema.columns {
+            write!(
+                &mut bytes,
+                " + IFNULL(LENGTH({}.{}), 0)",
+                quote_ident(schema.table_of(column)),
+                quote_ident(column.name)
+            )
+            .unwrap();
+        }
+        let sql = format!(
+            "SELECT COUNT(*), IFNULL(SUM({}), 0) FROM {}",
+            bytes,
+            join_splits(schema, &schema.split_tables())
+        );
+        self.query_row_cached(&sql, &[], |row| {
+            Ok(TableUsage {
+                rows: row.get::<_, i64>(0)? as u64,
+                bytes: row.get::<_, i64>(1)? as u64,
+            })
+        })
+        .map_err(|e| self.with_statement(Error::from(e), &sql, &[]))
+    }
+
+    // Row counts recorded by the last ANALYZE, or else the span of ids. Both
+    // are cheap lookups, but go stale as rows are added and deleted.
+    fn estimate_rows(&self, schema: &Schema) -> Result<u64> {
+        let analyzed = self
+            .query_row_cached(
+                "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'sqlite_stat1'",
+                &[],
+                |row| row.get::<_, i64>(0),
+            )
+            .map_err(Error::from)?
+            != 0;
+        if analyzed {
+            let sql = "SELECT stat FROM sqlite_stat1 WHERE tbl = ?";
+            let params: [&dyn ToSql; 1] = [&schema.table_name];
+            let stats = self
+                .prepare_cached(sql)
+                .and_then(|mut stmt| {
+                    stmt.query_map(params.as_slice(), |row| row.get::<_, String>(0))?
+                        .collect::<rusqlite::Result<Vec<_>>>()
+                })
+                .map_err(|e| self.with_statement(Error::from(e), sql, &params))?;
+            // Every entry starts with the number of rows its index covers, so
+            // the largest one counts the whole table.
+            let rows = stats
+                .iter()
+                .filter_map(|stat| stat.split(' ').next()?.parse::<u64>().ok())
+                .max();
+            if let Some(rows) = rows {
+                return Ok(rows);
+            }
+        }
+
+        let sql = format!(
+            "SELECT IFNULL(MAX(id) - MIN(id) + 1, 0) FROM {}",
+            quote_ident(schema.table_name)
+        );
+        self.query_row_cached(&sql, &[], |row| row.get::<_, i64>(0))
+            .map(|rows| rows as u64)
+            .map_err(|e| self.with_statement(Error::from(e), &sql, &[]))
+    }
+
+    // One total per distinct value of the grouping column, in its order. NULLs
+    // are skipped, and SQLite fails the query rather than let a sum overflow.
+    fn sum_column(
+        &self,
+        schema: &Schema,
+        filters: &[Filter],
+        column: &str,
+        group_by: Option<&str>,
+    ) -> Result<Vec<(Value<'static>, i64)>> {
+        let column = qualified_column(schema, column);
+        let group = group_by.map_or_else(
+            || "NULL".to_string(),
+            |group| qualified_column(schema, group),
+        );
+        let mut sql = format!(
+            "SELECT {}, SUM({}) FROM {} WHERE {} IS NOT NULL",
+            group,
+            column,
+            join_splits(schema, &schema.split_tables()),
+            column
+        );
+        let mut params: Vec<&dyn ToSql> = Vec::new();
+        for condition in filter_conditions(schema, filters, &mut params)? {
+            write!(&mut sql, " AND {}", condition).unwrap();
+        }
+        sql.push_str(" GROUP BY 1 ORDER BY 1");
+
+        self.prepare_cached(&sql)
+            .and_then(|mut stmt| {
+                stmt.query_map(params.as_slice(), |row| {
+                    Ok((row.get::<_, Value<'static>>(0)?, row.get::<_, i64>(1)?))
+                })?
+                .collect::<rusqlite::Result<Vec<_>>>()
+            })
+            .map_err(|e| self.with_statement(Error::from(e), &sql, &params))
+    }
+
+    fn defer_foreign_keys(&self, enabled: bool) -> Result<()> {
+        self.pragma_update(None, "defer_foreign_keys", enabled)?;
+        Ok(())
+    }
+
+    fn savepoint(&self, name: &str) -> Result<()> {
+        self.execute_batch(&format!("SAVEPOINT {}", quote_ident(name)))?;
+        Ok(())
+    }
+
+    fn release_savepoint(&self, name: &str) -> Result<()> {
+        self.execute_batch(&format!("RELEASE {}", quote_ident(name)))?;
+        Ok(())
+    }
+
+    fn rollback_to_savepoint(&self, name: &str) -> Result<()> {
+        let name = quote_ident(name);
+        self.stored_columns.borrow_mut().clear();
+        self.execute_batch(&format!("ROLLBACK TO {}; RELEASE {}", name, name))?;
+        Ok(())
+    }
+
+    fn next_in_sequence(&self, name: &str) -> Result<i64> {
+        self.execute_batch(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (name TEXT PRIMARY KEY, value INTEGER NOT NULL)",
+            quote_ident(SEQUENCES_TABLE)
+        ))?;
+        let sql = format!(
+            "INSERT INTO {} (name, value) VALUES (?, 1) \
+            ON CONFLICT (name) DO UPDATE SET value = value + 1",
+            quote_ident(SEQUENCES_TABLE)
+        );
+        let params: [&dyn ToSql; 1] = [&name];
+        self.execute_cached(&sql, params.as_slice())
+            .map_err(|e| self.with_statement(Error::from(e), &sql, &params))?;
+
+        let sql = format!(
+            "SELECT value FROM {} WHERE name = ?",
+            quote_ident(SEQUENCES_TABLE)
+        );
+        self.query_row_cached(&sql, params.as_slice(), |row| row.get(0))
+            .map_err(|e| self.with_statement(Error::from(e), &sql, &params))
+    }
+
+    fn put_blob(&self, hash: &[u8], data: &[u8]) -> Result<()> {
+        self.execute_batch(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (hash BLOB PRIMARY KEY, data BLOB NOT NULL)",
+            quote_ident(BLOBS_TABLE)
+        ))?;
+        let sql = format!(
+            "INSERT INTO {} (hash, data) VALUES (?, ?) ON CONFLICT (hash) DO NOTHING",
+            quote_ident(BLOBS_TABLE)
+        );
+        let params: [&dyn ToSql; 2] = [&hash, &data];
+        self.execute_cached(&sql, params.as_slice())
+            .map_err(|e| self.with_statement(Error::from(e), &sql, &params))?;
+        Ok(())
+    }
+
+    fn intern(&self, value: &str) -> Result<i64> {
+        for sql in ddl::create_interned_table_sql(Dialect::Sqlite) {
+            self.execute_cached(&sql, &[])
+                .map_err(|e| self.with_statement(Error::from(e), &sql, &[]))?;
+        }
+        let sql = format!(
+            "INSERT INTO {} (value) VALUES (?) ON CONFLICT (value) DO NOTHING",
+            quote_ident(INTERNED_TABLE)
+        );
+        let params: [&dyn ToSql; 1] = [&value];
+        self.execute_cached(&sql, params.as_slice())
+            .map_err(|e| self.with_statement(Error::from(e), &sql, &params))?;
+
+        let sql = format!(
+            "SELECT id FROM {} WHERE value = ?",
+            quote_ident(INTERNED_TABLE)
+        );
+        self.query_row_cached(&sql, params.as_slice(), |row| row.get(0))
+            .map_err(|e| self.with_statement(Error::from(e), &sql, &params))
+    }
+
+    fn interned_value(&self, id: i64) -> Result<Option<String>> {
+        if !self.table_exists(INTERNED_TABLE)? {
+            return Ok(None);
+        }
+        let sql = format!(
+            "SELECT value FROM {} WHERE id = ?",
+            quote_ident(INTERNED_TABLE)
+        );
+        let params: [&dyn ToSql; 1] = [&id];
+        match self.query_row_cached(&sql, params.as_slice(), |row| row.get(0)) {
+            Ok(value) => Ok(Some(value)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(self.with_statement(Error::from(e), &sql, &params)),
+        }
+    }
+
+    fn applied_migrations(&self) -> Result<Vec<i64>> {
+        if !self.table_exists(MIGRATIONS_TABLE)? {
+            return Ok(Vec::new());
+        }
+        let sql = format!(
+            "SELECT version FROM {} ORDER BY version",
+            quote_ident(MIGRATIONS_TABLE)
+        );
+        let mut stmt = self
+            .prepare_cached(&sql)
+            .map_err(|e| self.with_statement(Error::from(e), &sql, &[]))?;
+        let versions = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(versions)
+    }
+
+    fn record_migration(&self, version: i64, name: &str) -> Result<()> {
+        self.execute_batch(&format!(
+            "CREATE TABLE IF NOT EXISTS {} \
+            (version INTEGER PRIMARY KEY, name TEXT NOT NULL, applied_at INTEGER NOT NULL)",
+            quote_ident(MIGRATIONS_TABLE)
+        ))?;
+        let sql = format!(
+            "INSERT INTO {} (version, name, applied_at) \
+            VALUES (?, ?, CAST(strftime('%s', 'now') AS INTEGER))",
+            quote_ident(MIGRATIONS_TABLE)
+        );
+        let params: [&dyn ToSql; 2] = [&version, &name];
+        self.execute_cached(&sql, params.as_slice())
+            .map_err(|e| self.with_statement(Error::from(e), &sql, &params))?;
+        Ok(())
+    }
+
+    fn get_blob(&self, hash: &[u8]) -> Result<Option<Vec<u8>>> {
+        if !self.table_exists(BLOBS_TABLE)? {
+            return Ok(None);
+        }
+        let sql = format!(
+            "SELECT data FROM {} WHERE hash = ?",
+            quote_ident(BLOBS_TABLE)
+        );
+        let params: [&dyn ToSql; 1] = [&hash];
+        match self.query_row_cached(&sql, params.as_slice(), |row| row.get(0)) {
+            Ok(data) => Ok(Some(data)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(self.with_statement(Error::from(e), &sql, &params)),
+        }
+    }
+
+    fn delete_unreferenced_blobs(&self, references: &[(&str, &str)]) -> Result<usize> {
+        let mut sql = format!("DELETE FROM {}", quote_ident(BLOBS_TABLE));
+        let referenced = references
+            .iter()
+            .map(|(table, column)| {
+                format!(
+                    "SELECT {column} FROM {} WHERE {column} IS NOT NULL",
+                    quote_ident(table),
+                    column = quote_ident(column)
+                )
+            })
+            .collect::<Vec<_>>();
+        if !referenced.is_empty() {
+            write!(
+                &mut sql,
+                " WHERE hash NOT IN ({})",
+                referenced.join(" UNION ")
+            )
+            .unwrap();
+        }
+        self.execute(&sql, [])
+            .map_err(|e| self.with_statement(Error::from(e), &sql, &[]))
+    }
+
     fn commit(&self) -> Result<()> {
         let sql = "COMMIT".to_string();
         self.execute(&sql, []).map_err(Error::from)?;
@@ -200,25 +1661,33 @@ impl<'a> StorageTransaction for rusqlite::Transaction<'a> {
     }
 }
 
-fn convert_by_schema<'a>(mut val: Row<'a>, schema: &Schema) -> Result<Row<'a>> {
+fn convert_by_schema<'a>(mut val: Row<'a>, schema: &Schema, coercion: Coercion) -> Result<Row<'a>> {
     debug_assert_eq!(val.len(), schema.columns.len());
 
     let mut result = Vec::with_capacity(val.len());
-    for ((i, (column_name, ty)), v) in schema.columns.iter().enumerate().zip(val.iter_mut()) {
-        match (ty, v) {
-            (ty, v) if v.data_type() == *ty => result.push(v.clone()),
+    for ((i, column), v) in schema.columns.iter().enumerate().zip(val.iter_mut()) {
+        match (&column.stored_type(), v) {
+            (_, Value::Null) if column.nullable => result.push(Value::Null),
+            (ty, v) if v.data_type() == Some(*ty) => result.push(v.clone()),
             (DataType::Bool, Value::Int64(i)) if matches!(*i, 0..=1) => {
                 result.push(Value::Bool(*i != 0));
             }
-            (_, v) => {
-                return Err(Error::UnexpectedType(Box::new(UnexpectedTypeError {
-                    type_name: schema.type_name,
-                    attr_name: schema.attrs[i],
-                    table_name: schema.table_name,
-                    column_name,
-                    expected_type: *ty,
-                    got_type: v.sql_type().to_string(),
-                })));
+            (ty, v) => {
+                let coerced = match coercion {
+                    Coercion::Strict => None,
+                    Coercion::Lenient => v.coerce(*ty),
+                };
+                let Some(coerced) = coerced else {
+                    return Err(Error::UnexpectedType(Box::new(UnexpectedTypeError {
+                        type_name: schema.type_name,
+                        attr_name: schema.attrs[i],
+                        table_name: schema.table_name,
+                        column_name: column.name,
+                        expected_type: column.stored_type(),
+                        got_type: v.sql_type().to_string(),
+                    })));
+                };
+                result.push(coerced);
             }
         }
     }
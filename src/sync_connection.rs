@@ -0,0 +1,327 @@
+#![forbid(unsafe_code)]
+
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{mpsc, Arc, Condvar, Mutex, MutexGuard, OnceLock, Weak};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{object::Expiring, Connection, Error, Result, Transaction};
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub struct SyncConnection {
+    inner: Mutex<Connection>,
+    drain: Mutex<Drain>,
+    drained: Condvar,
+    // The worker running async_transaction, started by the first call.
+    async_jobs: OnceLock<mpsc::Sender<Job>>,
+}
+
+// Transactions run through `transaction`, tracked for shutdown.
+#[derive(Default)]
+struct Drain {
+    closing: bool,
+    aborting: bool,
+    in_flight: usize,
+    aborted: usize,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ShutdownReport {
+    // Transactions in flight when the shutdown began.
+    pub waited: usize,
+    // Those still running at the deadline, rolled back instead of committed.
+    pub aborted: usize,
+}
+
+struct InFlight<'a>(&'a SyncConnection);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.drain().in_flight -= 1;
+        self.0.drained.notify_all();
+    }
+}
+
+impl SyncConnection {
+    pub fn new(conn: Connection) -> Self {
+        Self {
+            inner: Mutex::new(conn),
+            drain: Mutex::new(Drain::default()),
+            drained: Condvar::new(),
+            async_jobs: OnceLock::new(),
+        }
+    }
+
+    pub fn open_sqlite_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Connection::open_sqlite_file(path).map(Self::new)
+    }
+
+    pub fn open_in_memory() -> Result<Self> {
+        Connection::open_in_memory().map(Self::new)
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, Connection> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn drain(&self) -> MutexGuard<'_, Drain> {
+        self.drain.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn transaction<R, F>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&Transaction<'_>) -> Result<R>,
+    {
+        let _in_flight = {
+            let mut drain = self.drain();
+            if drain.closing {
+                return Err(Error::Storage("connection is shut down".into()));
+            }
+            drain.in_flight += 1;
+            InFlight(self)
+        };
+        let mut conn = self.lock();
+        if self.abort_if_shutting_down() {
+            return Err(aborted());
+        }
+        let tx = conn.new_transaction()?;
+        match f(&tx) {
+            Ok(_) if self.abort_if_shutting_down() => {
+                tx.rollback()?;
+                Err(aborted())
+            }
+            Ok(result) => {
+                tx.commit()?;
+                Ok(result)
+            }
+            Err(e) => {
+                tx.rollback()?;
+                Err(e)
+            }
+        }
+    }
+
+    fn abort_if_shutting_down(&self) -> bool {
+        let mut drain = self.drain();
+        if drain.aborting {
+            drain.aborted += 1;
+        }
+        drain.aborting
+    }
+
+    // Refuses new transactions, waits until the deadline for those in flight
+    // to finish, and aborts the rest: they're rolled back once their closure
+    // returns, instead of committed. Then the WAL of a local database is
+    // checkpointed, so it can be closed or copied as is. Only transactions run
+    // through `transaction` are waited for; `lock` still works afterwards.
+    pub fn shutdown(&self, deadline: Instant) -> Result<ShutdownReport> {
+        let mut drain = self.drain();
+        drain.closing = true;
+        let waited = drain.in_flight;
+        while drain.in_flight > 0 && !drain.aborting {
+            let now = Instant::now();
+            if now >= deadline {
+                drain.aborting = true;
+                break;
+            }
+            drain = self
+                .drained
+                .wait_timeout(drain, deadline - now)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+        while drain.in_flight > 0 {
+            drain = self.drained.wait(drain).unwrap_or_else(|e| e.into_inner());
+        }
+        let report = ShutdownReport {
+            waited,
+            aborted: drain.aborted,
+        };
+        drop(drain);
+        self.lock().prepare_shutdown()?;
+        Ok(report)
+    }
+
+    fn is_shut_down(&self) -> bool {
+        self.drain().closing
+    }
+
+    // Runs on a single worker thread shared by all calls, as transactions on
+    // the connection take turns anyway. Each job holds the connection until
+    // it has run, and the worker exits once the connection is dropped.
+    pub fn async_transaction<R, F>(self: &Arc<Self>, f: F) -> BlockingTask<R>
+    where
+        F: FnOnce(&Transaction<'_>) -> Result<R> + Send + 'static,
+        R: Send + 'static,
+    {
+        let state = Arc::new(Mutex::new(TaskState {
+            result: None,
+            waker: None,
+        }));
+
+        let conn = Arc::clone(self);
+        let task_state = Arc::clone(&state);
+        let job: Job = Box::new(move |_| {
+            let result = panic::catch_unwind(AssertUnwindSafe(|| conn.transaction(f)));
+            task_state
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .finish(result);
+        });
+        let sender = self
+            .async_jobs
+            .get_or_init(|| spawn_worker(Arc::downgrade(self)).0);
+        if sender.send(job).is_err() {
+            let error = Error::Storage("async worker has stopped".into());
+            state
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .finish(Ok(Err(error)));
+        }
+
+        BlockingTask { state }
+    }
+
+    pub fn write_behind(self: &Arc<Self>) -> WriteBehind {
+        let (sender, worker) = spawn_worker(Arc::downgrade(self));
+        WriteBehind {
+            sender: Some(sender),
+            worker: Some(worker),
+            _conn: Arc::clone(self),
+        }
+    }
+
+    pub fn purge_expired_every<T: Expiring>(
+        self: &Arc<Self>,
+        interval: Duration,
+    ) -> thread::JoinHandle<Result<()>> {
+        let conn = Arc::downgrade(self);
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            let Some(conn) = conn.upgrade().filter(|conn| !conn.is_shut_down()) else {
+                return Ok(());
+            };
+            conn.lock().purge_expired::<T>()?;
+        })
+    }
+
+    pub fn into_inner(self) -> Connection {
+        self.inner.into_inner().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn aborted() -> Error {
+    Error::Storage("transaction aborted by shutdown".into())
+}
+
+// Runs jobs one at a time until every sender is gone. A panicking job mustn't
+// take the worker and every later job down with it; the jobs report their own
+// panics, e.g. through the ack of WriteBehind::enqueue.
+fn spawn_worker(conn: Weak<SyncConnection>) -> (mpsc::Sender<Job>, thread::JoinHandle<()>) {
+    let (sender, receiver) = mpsc::channel::<Job>();
+    let worker = thread::spawn(move || {
+        for job in receiver {
+            let Some(conn) = conn.upgrade() else {
+                return;
+            };
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| job(&conn)));
+        }
+    });
+    (sender, worker)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+struct TaskState<R> {
+    result: Option<thread::Result<Result<R>>>,
+    waker: Option<Waker>,
+}
+
+impl<R> TaskState<R> {
+    fn finish(&mut self, result: thread::Result<Result<R>>) {
+        self.result = Some(result);
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+pub struct BlockingTask<R> {
+    state: Arc<Mutex<TaskState<R>>>,
+}
+
+impl<R> Future for BlockingTask<R> {
+    type Output = Result<R>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        match state.result.take() {
+            Some(Ok(result)) => Poll::Ready(result),
+            Some(Err(payload)) => panic::resume_unwind(payload),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+type Job = Box<dyn FnOnce(&SyncConnection) + Send>;
+
+pub struct WriteBehind {
+    sender: Option<mpsc::Sender<Job>>,
+    worker: Option<thread::JoinHandle<()>>,
+    // Keeps the connection alive for the worker until it's joined on drop.
+    _conn: Arc<SyncConnection>,
+}
+
+impl WriteBehind {
+    fn send(&self, job: Job) -> Result<()> {
+        self.sender
+            .as_ref()
+            .and_then(|sender| sender.send(job).ok())
+            .ok_or_else(stopped)
+    }
+
+    // The ack gets an error if `f` panics. It's never called if the worker has
+    // stopped, which is reported here instead.
+    pub fn enqueue<F, A>(&self, f: F, ack: A) -> Result<()>
+    where
+        F: FnOnce(&Transaction<'_>) -> Result<()> + Send + 'static,
+        A: FnOnce(Result<()>) + Send + 'static,
+    {
+        self.send(Box::new(move |conn| {
+            let result = panic::catch_unwind(AssertUnwindSafe(|| conn.transaction(f)))
+                .unwrap_or_else(|_| Err(Error::Storage("write-behind job panicked".into())));
+            ack(result)
+        }))
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        let (sender, receiver) = mpsc::channel();
+        self.send(Box::new(move |_| {
+            let _ = sender.send(());
+        }))?;
+        receiver.recv().map_err(|_| stopped())
+    }
+}
+
+fn stopped() -> Error {
+    Error::Storage("write-behind worker has stopped".into())
+}
+
+impl Drop for WriteBehind {
+    fn drop(&mut self) {
+        self.sender.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
@@ -3,73 +3,467 @@
 use std::ops::Deref;
 use std::{
     any::Any,
-    cell::{Ref, RefCell, RefMut},
-    collections::{hash_map::Entry, HashMap},
+    borrow::Cow,
+    cell::{Cell, Ref, RefCell, RefMut},
+    collections::{hash_map::Entry, HashMap, HashSet, VecDeque},
+    fmt,
     marker::PhantomData,
     rc::Rc,
+    str::FromStr,
+    time::Instant,
 };
 
 use crate::object::Store;
 use crate::{
-    data::ObjectId,
-    error::{Error, NotFoundError, Result},
-    object::{Object, Schema},
-    storage::StorageTransaction,
+    access::{Access, AccessDeniedError, Operation},
+    advisor::QueryShape,
+    batch::{self, BatchOp, BatchResult},
+    blob, checksum, cipher,
+    connection::{self, Settings},
+    data::{DataType, ObjectId, Value, ValueConvert},
+    ddl::ChangeKind,
+    error::{Error, LockConflictInfo, NotFoundError, Result, UnregisteredTypeError},
+    format,
+    intern::{self, InternCache},
+    merge::{self, FieldTimes},
+    money::{self, Money},
+    object::{self, Object, ObjectView, Patch, PolyRef, Schema, Tree},
+    query::{Filter, IndexHint, Op, Sample, Select, Step, Window},
+    quota::{QuotaAction, QuotaError},
+    storage::{Row, StorageTransaction},
+    watchdog::{Monitor, Watch},
 };
 
 ////////////////////////////////////////////////////////////////////////////////
 
+const SAVEPOINT: &str = "orm_savepoint";
+
 // TODO: your code goes here.
 pub struct Transaction<'a> {
     inner: Box<dyn StorageTransaction + 'a>,
-    objects: RefCell<HashMap<(&'static Schema, ObjectId), TxState>>,
+    settings: &'a Settings,
+    objects: RefCell<HashMap<ObjectKey, TxState>>,
+    evicted_created: RefCell<Vec<ObjectKey>>,
+    references: RefCell<HashSet<ObjectKey>>,
+    observers: Rc<Observers>,
+    last_used: RefCell<HashMap<ObjectKey, u64>>,
+    clock: Cell<u64>,
+    interned: RefCell<InternCache>,
+    migrated: RefCell<HashSet<&'static Schema>>,
+    temp_tables: RefCell<Vec<&'static Schema>>,
+    watch: Option<Watch>,
 }
 
 impl<'a> Transaction<'a> {
-    pub(crate) fn new(inner: Box<dyn StorageTransaction + 'a>) -> Self {
+    pub(crate) fn new(inner: Box<dyn StorageTransaction + 'a>, settings: &'a Settings) -> Self {
         Self {
             inner,
+            settings,
             objects: RefCell::new(HashMap::new()),
+            evicted_created: RefCell::new(Vec::new()),
+            references: RefCell::new(HashSet::new()),
+            observers: Rc::new(RefCell::new(Vec::new())),
+            last_used: RefCell::new(HashMap::new()),
+            clock: Cell::new(0),
+            interned: RefCell::new(InternCache::default()),
+            migrated: RefCell::new(HashSet::new()),
+            temp_tables: RefCell::new(Vec::new()),
+            watch: settings.watchdog.as_ref().map(Monitor::start),
+        }
+    }
+
+    // Called before anything that may take the write lock, see Watchdog.
+    fn writing(&self) {
+        if let Some(watch) = &self.watch {
+            watch.writing();
+        }
+    }
+
+    fn track<T: Object>(&self, id: ObjectId, obj: T, state: ObjectState) -> TxState {
+        TxState {
+            id,
+            schema: T::schema(),
+            obj: Rc::new(RefCell::new(obj)),
+            state: Rc::new(RefCell::new(state)),
+            created: state == ObjectState::Created,
+            access: self.access_of(T::schema()),
+            observers: Rc::clone(&self.observers),
         }
     }
 
+    fn access_of(&self, schema: &Schema) -> Access {
+        self.settings.access_of(schema)
+    }
+
+    fn check_access(&self, schema: &Schema, operation: Operation) -> Result<()> {
+        self.settings.check_access(schema, operation)
+    }
+
+    // The caller's filters plus the type's row filter, see
+    // Connection::set_row_filter.
+    fn with_row_filter<'f>(&self, schema: &Schema, filters: &'f [Filter]) -> Cow<'f, [Filter]> {
+        match self.settings.row_filters.get(schema.table_name) {
+            Some(row_filter) => {
+                let mut filters = filters.to_vec();
+                filters.extend(row_filter());
+                Cow::Owned(filters)
+            }
+            None => Cow::Borrowed(filters),
+        }
+    }
+
+    fn row_visible(&self, id: ObjectId, schema: &Schema) -> Result<bool> {
+        if !self.settings.row_filters.contains_key(schema.table_name) {
+            return Ok(true);
+        }
+        let by_id = [id_filter(Op::Eq, id)];
+        let filters = self.with_row_filter(schema, &by_id);
+        let rows = self.inner.select_rows(schema, &filters, None, Some(1))?;
+        Ok(!rows.is_empty())
+    }
+
+    // Rows after `after` in id order that match the filters. Pages of a type
+    // with a row filter are selected with it like any other query.
+    fn select_page<T: Object>(
+        &self,
+        after: Option<ObjectId>,
+        size: usize,
+        filters: &[Filter],
+    ) -> Result<Vec<(ObjectId, Row<'static>)>> {
+        let schema = T::schema();
+        if filters.is_empty() && !self.settings.row_filters.contains_key(schema.table_name) {
+            return self.inner.select_page(schema, after, size);
+        }
+        let mut page = vec![id_filter(Op::Gt, after.unwrap_or(ObjectId::new(0)))];
+        page.extend_from_slice(filters);
+        let filters = self.with_row_filter(schema, &page);
+        self.inner.select_rows(schema, &filters, None, Some(size))
+    }
+
+    fn not_found(id: ObjectId, schema: &Schema) -> Error {
+        Error::NotFound(Box::new(NotFoundError {
+            object_id: id,
+            type_name: schema.type_name,
+        }))
+    }
+
+    pub fn on_change<F>(&self, f: F)
+    where
+        F: Fn(&'static str, ObjectId, ObjectState) + 'static,
+    {
+        self.observers
+            .borrow_mut()
+            .push(Rc::new(move |schema, id, state| {
+                f(schema.type_name, id, state)
+            }));
+    }
+
+    pub fn on_change_of<T: Object, F>(&self, f: F)
+    where
+        F: Fn(ObjectId, ObjectState) + 'static,
+    {
+        self.observers
+            .borrow_mut()
+            .push(Rc::new(move |schema, id, state| {
+                if schema == T::schema() {
+                    f(id, state)
+                }
+            }));
+    }
+
+    pub fn defer_foreign_keys(&self, enabled: bool) -> Result<()> {
+        self.inner
+            .defer_foreign_keys(enabled)
+            .map_err(|e| self.with_context(e, || "defer foreign keys".to_string()))
+    }
+
+    // Statements the ORM doesn't generate itself, e.g. in a Migration. Objects
+    // already loaded by the transaction aren't refreshed.
+    pub fn execute_ddl<S: AsRef<str>>(&self, statements: &[S]) -> Result<()> {
+        let statements = statements
+            .iter()
+            .map(|sql| sql.as_ref().to_string())
+            .collect::<Vec<_>>();
+        self.writing();
+        self.inner
+            .execute_ddl(&statements)
+            .map_err(|e| self.with_context(e, || "execute_ddl".to_string()))
+    }
+
+    pub(crate) fn applied_migrations(&self) -> Result<Vec<i64>> {
+        self.inner.applied_migrations()
+    }
+
+    pub(crate) fn record_migration(&self, version: i64, name: &str) -> Result<()> {
+        self.writing();
+        self.inner.record_migration(version, name)
+    }
+
+    fn with_context(&self, err: Error, operation: impl FnOnce() -> String) -> Error {
+        if self.settings.error_context {
+            err.with_operation(operation())
+        } else {
+            err
+        }
+    }
+
+    fn decode_row<'r>(&self, id: ObjectId, schema: &Schema, row: Row<'r>) -> Result<Row<'r>> {
+        let mut interned = self.interned.borrow_mut();
+        let row = intern::load_interned(self.inner.as_ref(), &mut interned, id, schema, row)?;
+        let row = blob::load_blobs(self.inner.as_ref(), id, schema, row)?;
+        let row = cipher::decrypt_row(&self.settings.ciphers, schema, row)?;
+        let row = format::decode_bools(schema, row)?;
+        checksum::verify_row(id, schema, &row)?;
+        format::verify_row(schema, &row)?;
+        Ok(row)
+    }
+
+    fn encode_row<'r>(&self, schema: &Schema, row: Row<'r>) -> Result<Row<'r>> {
+        let row = format::encode_bools(schema, row);
+        let row = cipher::encrypt_row(&self.settings.ciphers, schema, row)?;
+        let row = blob::store_blobs(self.inner.as_ref(), schema, row)?;
+        let mut interned = self.interned.borrow_mut();
+        intern::store_interned(self.inner.as_ref(), &mut interned, schema, row)
+    }
+
     fn ensure_table<T: Object>(&self) -> Result<()> {
         let exists = self.inner.table_exists(T::schema().table_name)?;
         if !exists {
+            self.writing();
             self.inner.create_table(T::schema())
+        } else if self.settings.auto_migrate {
+            self.add_missing_columns(T::schema())
         } else {
             Ok(())
         }
     }
 
+    // Only changes that lose nothing are made: columns are added or renamed
+    // and missing split tables created. Checked once per transaction, since a
+    // rollback undoes the changes too.
+    fn add_missing_columns(&self, schema: &'static Schema) -> Result<()> {
+        if !self.migrated.borrow_mut().insert(schema) {
+            return Ok(());
+        }
+        let changes = connection::schema_changes(self.inner.as_ref(), schema)?
+            .into_iter()
+            .filter(|change| {
+                !change.destructive
+                    && matches!(
+                        change.kind,
+                        ChangeKind::CreateTable | ChangeKind::RenameColumn | ChangeKind::AddColumn
+                    )
+            })
+            .collect::<Vec<_>>();
+        for change in changes {
+            self.writing();
+            self.inner.execute_ddl(&change.statements)?;
+        }
+        Ok(())
+    }
+
+    // Until the transaction ends, objects of type T are kept in a TEMP table,
+    // e.g. to stage an import. The stored table isn't touched, and the TEMP
+    // one is dropped on commit; after_commit hooks don't see its objects.
+    // Types with split columns or WITHOUT ROWID tables can't be staged.
+    pub fn create_temp_table<T: Object>(&self) -> Result<()> {
+        self.try_create_temp_table::<T>().map_err(|e| {
+            self.with_context(e, || {
+                format!("create_temp_table::<{}>", T::schema().type_name)
+            })
+        })
+    }
+
+    fn try_create_temp_table<T: Object>(&self) -> Result<()> {
+        let schema = T::schema();
+        let invalid = |message: &str| Err(Error::invalid_request(schema.type_name, message));
+        if self.temp_tables.borrow().contains(&schema) {
+            return invalid("the TEMP table already exists");
+        }
+        if !schema.split_tables().is_empty() || schema.without_rowid {
+            return invalid("split columns and WITHOUT ROWID tables can't be staged");
+        }
+        if self.objects.borrow().keys().any(|(s, _)| *s == schema) {
+            return invalid("objects were loaded before the TEMP table was created");
+        }
+        // Created first, so that nothing creates it later under the TEMP one.
+        self.ensure_table::<T>()?;
+        self.inner.create_temp_table(schema)?;
+        self.temp_tables.borrow_mut().push(schema);
+        Ok(())
+    }
+
+    // Upserts every object staged in T's TEMP table, see create_temp_table,
+    // into the stored table in one statement. Rows are matched on
+    // `match_columns`, which need a unique index; matched rows take the staged
+    // values. Returns the number of rows inserted or updated.
+    pub fn merge_from_staging<T: Object>(&self, match_columns: &[&str]) -> Result<usize> {
+        self.try_merge_from_staging::<T>(match_columns)
+            .map_err(|e| {
+                self.with_context(e, || {
+                    format!("merge_from_staging::<{}>", T::schema().type_name)
+                })
+            })
+    }
+
+    fn try_merge_from_staging<T: Object>(&self, match_columns: &[&str]) -> Result<usize> {
+        let schema = T::schema();
+        let invalid = |message: String| Err(Error::invalid_request(schema.type_name, message));
+        if !self.temp_tables.borrow().contains(&schema) {
+            return invalid("no TEMP table to merge from, see create_temp_table".to_string());
+        }
+        if !schema.split_tables().is_empty() || schema.without_rowid {
+            return invalid("split columns and WITHOUT ROWID tables can't be merged".to_string());
+        }
+        if match_columns.is_empty() {
+            return invalid("no columns to match rows on".to_string());
+        }
+        for name in match_columns {
+            if !schema
+                .columns_in(schema.table_name)
+                .any(|(_, column)| column.name == *name)
+            {
+                return invalid(format!("no column '{}' to match rows on", name));
+            }
+        }
+        self.check_access(schema, Operation::Create)?;
+        self.check_access(schema, Operation::Update)?;
+        self.flush_pending(schema)?;
+        self.writing();
+        self.inner.merge_staged(schema, match_columns)
+    }
+
+    // Writes the type's modified and removed objects now rather than on
+    // commit: modified ones become clean, removed ones are forgotten.
+    fn flush_pending(&self, schema: &'static Schema) -> Result<()> {
+        self.apply_pending(Some(schema))?;
+        let mut last_used = self.last_used.borrow_mut();
+        self.objects.borrow_mut().retain(|key, obj| {
+            if key.0 != schema {
+                return true;
+            }
+            let mut state = obj.state.borrow_mut();
+            match *state {
+                ObjectState::Modified => *state = ObjectState::Clean,
+                ObjectState::Removed => {
+                    last_used.remove(key);
+                    return false;
+                }
+                _ => {}
+            }
+            true
+        });
+        Ok(())
+    }
+
+    fn drop_temp_tables(&self) -> Result<()> {
+        for schema in self.temp_tables.borrow().iter() {
+            self.inner.drop_temp_table(schema)?;
+        }
+        Ok(())
+    }
+
     pub fn create<T: Object>(&self, src_obj: T) -> Result<Tx<'_, T>> {
+        self.try_create(src_obj)
+            .map_err(|e| self.with_context(e, || format!("create::<{}>", T::schema().type_name)))
+    }
+
+    fn try_create<T: Object>(&self, mut src_obj: T) -> Result<Tx<'_, T>> {
+        self.check_access(T::schema(), Operation::Create)?;
+        src_obj
+            .validate()
+            .map_err(|e| Error::Validation(Box::new(e)))?;
         self.ensure_table::<T>()?;
         let schema = T::schema();
-        let id = self.inner.insert_row(schema, &src_obj.to_row())?;
-        let state = TxState {
-            id,
-            obj: Rc::new(RefCell::new(src_obj)),
-            state: Rc::new(RefCell::new(ObjectState::Clean)),
-        };
+        let row = self.encode_row(schema, src_obj.to_row())?;
+        self.writing();
+        let id = self.inner.insert_row(schema, &row)?;
+        self.settings.record(schema, |stats| stats.inserts += 1);
+        src_obj.set_id(id);
+        let state = self.track(id, src_obj, ObjectState::Created);
         self.objects
             .borrow_mut()
             .insert((T::schema(), id), state.clone());
+        self.touch(T::schema(), id);
         Ok(Tx::new(state))
     }
 
+    // Like create for each object, with the rows inserted in one go.
+    pub fn create_many<T: Object>(&self, src_objs: Vec<T>) -> Result<Vec<Tx<'_, T>>> {
+        self.try_create_many(src_objs).map_err(|e| {
+            self.with_context(e, || format!("create_many::<{}>", T::schema().type_name))
+        })
+    }
+
+    fn try_create_many<T: Object>(&self, src_objs: Vec<T>) -> Result<Vec<Tx<'_, T>>> {
+        self.check_access(T::schema(), Operation::Create)?;
+        for obj in &src_objs {
+            obj.validate().map_err(|e| Error::Validation(Box::new(e)))?;
+        }
+        self.ensure_table::<T>()?;
+        let schema = T::schema();
+        // A failing row mustn't leave the ones before it behind.
+        let ids = self.in_savepoint(|| {
+            let rows = src_objs
+                .iter()
+                .map(|obj| self.encode_row(schema, obj.to_row()))
+                .collect::<Result<Vec<_>>>()?;
+            self.writing();
+            self.inner.insert_rows(schema, &rows)
+        })?;
+        self.settings
+            .record(schema, |stats| stats.inserts += ids.len() as u64);
+        let mut txs = Vec::with_capacity(ids.len());
+        for (mut obj, id) in src_objs.into_iter().zip(ids) {
+            obj.set_id(id);
+            let state = self.track(id, obj, ObjectState::Created);
+            self.objects
+                .borrow_mut()
+                .insert((schema, id), state.clone());
+            self.touch(schema, id);
+            txs.push(Tx::new(state));
+        }
+        Ok(txs)
+    }
+
+    // Runs f, undoing whatever it wrote if it fails.
+    fn in_savepoint<R>(&self, f: impl FnOnce() -> Result<R>) -> Result<R> {
+        self.inner.savepoint(SAVEPOINT)?;
+        match f() {
+            Ok(result) => {
+                self.inner.release_savepoint(SAVEPOINT)?;
+                Ok(result)
+            }
+            Err(e) => {
+                self.inner.rollback_to_savepoint(SAVEPOINT)?;
+                // Values interned since the savepoint are gone with it.
+                *self.interned.borrow_mut() = InternCache::default();
+                Err(e)
+            }
+        }
+    }
+
     pub fn get<T: Object>(&self, id: ObjectId) -> Result<Tx<'_, T>> {
+        self.try_get(id)
+            .map_err(|e| self.with_context(e, || format!("get::<{}>", T::schema().type_name)))
+    }
+
+    fn try_get<T: Object>(&self, id: ObjectId) -> Result<Tx<'_, T>> {
         self.ensure_table::<T>()?;
-        match self.objects.borrow_mut().entry((T::schema(), id)) {
+        let tx = match self.objects.borrow_mut().entry((T::schema(), id)) {
             Entry::Vacant(place) => {
+                if !self.row_visible(id, T::schema())? {
+                    return Err(Self::not_found(id, T::schema()));
+                }
                 let row = self.inner.select_row(id, T::schema())?;
-                let state = TxState {
-                    id,
-                    obj: Rc::new(RefCell::new(T::from_row(row))),
-                    state: Rc::new(RefCell::new(ObjectState::Clean)),
-                };
+                self.settings.record(T::schema(), |stats| stats.read(1));
+                let row = self.decode_row(id, T::schema(), row)?;
+                let mut obj = T::try_from_row(row)?;
+                obj.set_id(id);
+                let state = self.track(id, obj, ObjectState::Clean);
                 let tx = Tx::new(state.clone());
                 place.insert(state);
-                Ok(tx)
+                tx
             }
             Entry::Occupied(e) => {
                 let rc = e.get().clone();
@@ -82,21 +476,770 @@ impl<'a> Transaction<'a> {
                 if !rc.obj.borrow().as_any().is::<T>() {
                     panic!("type mismatch")
                 }
-                Ok(Tx::new(rc))
+                Tx::new(rc)
+            }
+        };
+        self.touch(T::schema(), id);
+        Ok(tx)
+    }
+
+    // Loads the target through the loader its type registered on the connection.
+    pub fn get_poly(&self, target: &PolyRef) -> Result<AnyTx<'_>> {
+        let Some(load) = self.settings.loaders.get(target.table_name.as_str()) else {
+            let err = Error::UnregisteredType(Box::new(UnregisteredTypeError {
+                object_id: target.id,
+                table_name: target.table_name.clone(),
+            }));
+            return Err(self.with_context(err, || format!("get_poly({})", target.table_name)));
+        };
+        load(self, target.id)
+    }
+
+    pub fn get_locked<T: Object>(&self, id: ObjectId) -> Result<Tx<'_, T>> {
+        self.ensure_table::<T>()
+            .and_then(|_| {
+                self.writing();
+                self.inner.lock_row(id, T::schema())
+            })
+            .and_then(|_| self.try_get(id))
+            .map_err(|e| {
+                self.with_context(e, || format!("get_locked::<{}>", T::schema().type_name))
+            })
+    }
+
+    pub fn get_in_order<T: Object>(&self, ids: &[ObjectId]) -> Result<Vec<Option<Tx<'_, T>>>> {
+        self.try_get_in_order(ids).map_err(|e| {
+            self.with_context(e, || format!("get_in_order::<{}>", T::schema().type_name))
+        })
+    }
+
+    fn try_get_in_order<T: Object>(&self, ids: &[ObjectId]) -> Result<Vec<Option<Tx<'_, T>>>> {
+        self.ensure_table::<T>()?;
+        let mut found = HashMap::new();
+        let mut missing = Vec::new();
+        for &id in ids {
+            if found.contains_key(&id) {
+                continue;
+            }
+            let cached = self.objects.borrow().get(&(T::schema(), id)).cloned();
+            match cached {
+                Some(state) => {
+                    let removed = *state.state.borrow() == ObjectState::Removed;
+                    found.insert(id, (!removed).then_some(state));
+                    self.touch(T::schema(), id);
+                }
+                None => {
+                    found.insert(id, None);
+                    missing.push(id);
+                }
+            }
+        }
+        let mut visible = Vec::with_capacity(missing.len());
+        for id in missing {
+            if self.row_visible(id, T::schema())? {
+                visible.push(id);
+            }
+        }
+        let rows = self.inner.select_ids(T::schema(), &visible)?;
+        self.settings
+            .record(T::schema(), |stats| stats.read(rows.len()));
+        for (id, row) in rows {
+            found.insert(id, self.cache_row::<T>(id, row)?.map(|tx| tx.state));
+        }
+        Ok(ids
+            .iter()
+            .map(|id| found[id].clone().map(Tx::new))
+            .collect())
+    }
+
+    pub fn increment<T: Object>(&self, id: ObjectId, column: &str, delta: i64) -> Result<i64> {
+        self.try_increment::<T>(id, column, delta).map_err(|e| {
+            self.with_context(e, || {
+                format!("increment::<{}>({})", T::schema().type_name, column)
+            })
+        })
+    }
+
+    fn try_increment<T: Object>(&self, id: ObjectId, column: &str, delta: i64) -> Result<i64> {
+        let schema = T::schema();
+        let Some(index) = schema.columns.iter().position(|c| c.name == column) else {
+            let message = format!("no column '{}' to increment", column);
+            return Err(Error::invalid_request(schema.type_name, message));
+        };
+        if schema.columns[index].data_type != DataType::Int64 {
+            let message = format!("column '{}' is not an integer", column);
+            return Err(Error::invalid_request(schema.type_name, message));
+        }
+        self.check_access(schema, Operation::Update)?;
+
+        let cached = self.objects.borrow().get(&(schema, id)).cloned();
+        if cached.is_none() && !self.row_visible(id, schema)? {
+            return Err(Self::not_found(id, schema));
+        }
+        if cached
+            .as_ref()
+            .is_some_and(|state| *state.state.borrow() == ObjectState::Removed)
+        {
+            return Err(Error::NotFound(Box::new(NotFoundError {
+                object_id: id,
+                type_name: schema.type_name,
+            })));
+        }
+
+        self.ensure_table::<T>()?;
+        self.writing();
+        let value = self.inner.increment(id, schema, column, delta)?;
+        self.settings.record(schema, |stats| stats.updates += 1);
+        if let Some(state) = cached {
+            let mut obj = state.obj.borrow_mut();
+            let obj = obj.as_mut_any().downcast_mut::<T>().expect("type mismatch");
+            let mut row = Object::to_row(obj)
+                .into_iter()
+                .map(Value::into_owned)
+                .collect::<Vec<_>>();
+            row[index] = Value::Int64(value);
+            *obj = T::try_from_row(row)?;
+            obj.set_id(id);
+        }
+        Ok(value)
+    }
+
+    // Merges a copy of the object from another replica into the stored one,
+    // see merge::merge_rows. Returns the field times of the merged object.
+    pub fn merge<T: Object>(
+        &self,
+        id: ObjectId,
+        our_times: &FieldTimes,
+        theirs: &T,
+        their_times: &FieldTimes,
+    ) -> Result<FieldTimes> {
+        self.try_merge(id, our_times, theirs, their_times)
+            .map_err(|e| self.with_context(e, || format!("merge::<{}>", T::schema().type_name)))
+    }
+
+    fn try_merge<T: Object>(
+        &self,
+        id: ObjectId,
+        our_times: &FieldTimes,
+        theirs: &T,
+        their_times: &FieldTimes,
+    ) -> Result<FieldTimes> {
+        self.check_access(T::schema(), Operation::Update)?;
+        let tx = self.try_get::<T>(id)?;
+        let (mut merged, times) = merge::merge(&*tx.borrow(), our_times, theirs, their_times)?;
+        merged.set_id(id);
+        *tx.borrow_mut() = merged;
+        Ok(times)
+    }
+
+    // Applies each op under its own savepoint, so one that fails leaves no
+    // trace and the rest still go through. Writes reach storage right away to
+    // surface constraint violations per op, and commit doesn't repeat them.
+    pub fn apply_batch<T: Object>(
+        &self,
+        ops: impl IntoIterator<Item = BatchOp<T>>,
+    ) -> Result<BatchResult> {
+        let mut results = Vec::new();
+        for (i, op) in ops.into_iter().enumerate() {
+            let result = self.apply_batch_op(op).map_err(|e| {
+                self.with_context(e, || {
+                    format!("apply_batch::<{}> op {}", T::schema().type_name, i)
+                })
+            });
+            match result {
+                Err(e) if !batch::is_item_error(&e) => return Err(e),
+                result => results.push(result),
+            }
+        }
+        Ok(BatchResult { results })
+    }
+
+    fn apply_batch_op<T: Object>(&self, op: BatchOp<T>) -> Result<ObjectId> {
+        self.in_savepoint(|| self.try_apply_batch_op(op))
+    }
+
+    fn try_apply_batch_op<T: Object>(&self, op: BatchOp<T>) -> Result<ObjectId> {
+        match op {
+            BatchOp::Create(obj) => Ok(self.try_create(obj)?.id()),
+            BatchOp::Update(id, mut obj) => {
+                self.check_access(T::schema(), Operation::Update)?;
+                let tx = self.try_get::<T>(id)?;
+                obj.validate().map_err(|e| Error::Validation(Box::new(e)))?;
+                obj.set_id(id);
+                let row = self.encode_row(T::schema(), obj.to_row())?;
+                self.writing();
+                self.inner.update_row(id, T::schema(), &row)?;
+                self.settings
+                    .record(T::schema(), |stats| stats.updates += 1);
+                *tx.borrow_mut() = obj;
+                // Already written, so commit mustn't write it again.
+                *tx.state.state.borrow_mut() = if tx.state.created {
+                    ObjectState::Created
+                } else {
+                    ObjectState::Clean
+                };
+                Ok(id)
+            }
+            BatchOp::Delete(id) => {
+                self.check_access(T::schema(), Operation::Delete)?;
+                let tx = self.try_get::<T>(id)?;
+                self.writing();
+                self.inner.delete_row(id, T::schema())?;
+                self.settings
+                    .record(T::schema(), |stats| stats.deletes += 1);
+                tx.delete();
+                let key = (T::schema(), id);
+                self.objects.borrow_mut().remove(&key);
+                self.evicted_created.borrow_mut().retain(|k| *k != key);
+                self.last_used.borrow_mut().remove(&key);
+                Ok(id)
+            }
+        }
+    }
+
+    pub fn reference_exists<T: Object>(&self, id: ObjectId) -> Result<bool> {
+        self.try_reference_exists::<T>(id).map_err(|e| {
+            self.with_context(e, || {
+                format!("reference_exists::<{}>", T::schema().type_name)
+            })
+        })
+    }
+
+    fn try_reference_exists<T: Object>(&self, id: ObjectId) -> Result<bool> {
+        let key = (T::schema(), id);
+        if let Some(state) = self.objects.borrow().get(&key) {
+            return Ok(*state.state.borrow() != ObjectState::Removed);
+        }
+        if self.references.borrow().contains(&key) {
+            return Ok(true);
+        }
+        self.ensure_table::<T>()?;
+        let exists = self.inner.has_row(id, T::schema())? && self.row_visible(id, T::schema())?;
+        if exists {
+            self.references.borrow_mut().insert(key);
+        }
+        Ok(exists)
+    }
+
+    // Fast but approximate, see StorageTransaction::estimate_rows. Changes
+    // made by this transaction may not be reflected.
+    pub fn estimate_count<T: Object>(&self) -> Result<u64> {
+        self.try_estimate_count::<T>().map_err(|e| {
+            self.with_context(e, || format!("estimate_count::<{}>", T::schema().type_name))
+        })
+    }
+
+    fn try_estimate_count<T: Object>(&self) -> Result<u64> {
+        self.ensure_table::<T>()?;
+        self.inner.estimate_rows(T::schema())
+    }
+
+    // Totals of a Money field, one per currency. Like queries, this reads what
+    // is stored, so changes not yet committed by this transaction don't count.
+    pub(crate) fn sum_money<T: Object>(
+        &self,
+        filters: &[Filter],
+        column: &str,
+    ) -> Result<Vec<Money>> {
+        self.try_sum_money::<T>(filters, column).map_err(|e| {
+            self.with_context(e, || {
+                format!("sum_money::<{}>({})", T::schema().type_name, column)
+            })
+        })
+    }
+
+    fn try_sum_money<T: Object>(&self, filters: &[Filter], column: &str) -> Result<Vec<Money>> {
+        let schema = T::schema();
+        let invalid = |message: String| Err(Error::invalid_request(schema.type_name, message));
+        let Some(def) = schema.column(column) else {
+            return invalid(format!("no column '{}'", column));
+        };
+        let currency_column = schema
+            .columns
+            .iter()
+            .position(|def| def.name == format!("{}_currency", column));
+        if def.data_type != DataType::Int64 || (def.currency.is_none() && currency_column.is_none())
+        {
+            return invalid(format!("column '{}' is not a Money field", column));
+        }
+
+        self.ensure_table::<T>()?;
+        let filters = self.with_row_filter(schema, filters);
+        let sums = self.inner.sum_column(
+            schema,
+            &filters,
+            column,
+            currency_column.map(|index| schema.columns[index].name),
+        )?;
+        self.settings.record(schema, |stats| stats.read(sums.len()));
+        sums.into_iter()
+            .map(|(currency, minor_units)| {
+                let currency = match (def.currency, currency_column) {
+                    (Some(currency), _) => currency,
+                    (None, Some(index)) => money::currency(
+                        &String::try_from_value(&currency)
+                            .map_err(|e| object::conversion_error(schema, index, e))?,
+                    ),
+                    (None, None) => unreachable!("checked above"),
+                };
+                Ok(Money::new(minor_units, currency))
+            })
+            .collect()
+    }
+
+    pub fn with_row<T: Object, R>(&self, id: ObjectId, f: impl FnOnce(Row<'_>) -> R) -> Result<R> {
+        self.try_with_row::<T, R>(id, f)
+            .map_err(|e| self.with_context(e, || format!("with_row::<{}>", T::schema().type_name)))
+    }
+
+    // Like with_row, but hands out a typed view whose string and byte fields can
+    // borrow from the row instead of allocating owned copies.
+    pub fn with_view<V: ObjectView, R>(
+        &self,
+        id: ObjectId,
+        f: impl FnOnce(V::View<'_>) -> R,
+    ) -> Result<R> {
+        let type_name = V::Object::schema().type_name;
+        self.try_with_row::<V::Object, Result<R>>(id, |row| V::try_from_row(row).map(f))
+            .and_then(|result| result)
+            .map_err(|e| self.with_context(e, || format!("with_view::<{}>", type_name)))
+    }
+
+    fn try_with_row<T: Object, R>(&self, id: ObjectId, f: impl FnOnce(Row<'_>) -> R) -> Result<R> {
+        self.ensure_table::<T>()?;
+        if let Some(state) = self.objects.borrow().get(&(T::schema(), id)) {
+            if *state.state.borrow() == ObjectState::Removed {
+                return Err(Error::NotFound(Box::new(NotFoundError {
+                    object_id: id,
+                    type_name: T::schema().type_name,
+                })));
+            }
+            return Ok(f(state.obj.borrow().to_row()));
+        }
+
+        if !self.row_visible(id, T::schema())? {
+            return Err(Self::not_found(id, T::schema()));
+        }
+        let mut f = Some(f);
+        let mut result = None;
+        self.settings.record(T::schema(), |stats| stats.read(1));
+        self.inner.with_row(id, T::schema(), &mut |row| {
+            let row = self.decode_row(id, T::schema(), row);
+            result = f.take().map(|f| row.map(f));
+        })?;
+        result.expect("row callback was not called")
+    }
+
+    pub fn evict<T: Object>(&self, id: ObjectId) -> bool {
+        let key = (T::schema(), id);
+        let mut objects = self.objects.borrow_mut();
+        match objects.get(&key) {
+            Some(state) if state.is_evictable() => {
+                self.remove_entry(&mut objects, &key);
+                true
             }
+            _ => false,
+        }
+    }
+
+    pub fn evict_clean(&self) -> usize {
+        let mut objects = self.objects.borrow_mut();
+        let keys = objects
+            .iter()
+            .filter(|(_, state)| state.is_evictable())
+            .map(|(key, _)| *key)
+            .collect::<Vec<_>>();
+        for key in &keys {
+            self.remove_entry(&mut objects, key);
+        }
+        keys.len()
+    }
+
+    fn remove_entry(&self, objects: &mut HashMap<ObjectKey, TxState>, key: &ObjectKey) {
+        if let Some(state) = objects.remove(key) {
+            self.last_used.borrow_mut().remove(key);
+            if state.created {
+                self.evicted_created.borrow_mut().push(*key);
+            }
+        }
+    }
+
+    fn touch(&self, schema: &'static Schema, id: ObjectId) {
+        let now = self.clock.get();
+        self.clock.set(now + 1);
+        self.last_used.borrow_mut().insert((schema, id), now);
+
+        let Some(limit) = self.settings.max_objects else {
+            return;
+        };
+        let mut objects = self.objects.borrow_mut();
+        if objects.len() <= limit {
+            return;
+        }
+        let last_used = self.last_used.borrow();
+        let mut candidates = objects
+            .iter()
+            .filter(|(_, state)| state.is_evictable())
+            .map(|(key, _)| (last_used.get(key).copied().unwrap_or_default(), *key))
+            .collect::<Vec<_>>();
+        drop(last_used);
+        candidates.sort_unstable_by_key(|(used, _)| *used);
+        let excess = objects.len() - limit;
+        for (_, key) in candidates.into_iter().take(excess) {
+            self.remove_entry(&mut objects, &key);
+        }
+    }
+
+    // Every object of the type at once; see scan for tables too big for that.
+    pub fn get_all<T: Object>(&self) -> Result<Vec<Tx<'_, T>>> {
+        self.try_fetch(&[], None, None)
+            .map_err(|e| self.with_context(e, || format!("get_all::<{}>", T::schema().type_name)))
+    }
+
+    pub fn select<T: Object>(&self) -> Select<'_, 'a, T> {
+        Select::new(self)
+    }
+
+    pub(crate) fn fetch<T: Object>(
+        &self,
+        filters: &[Filter],
+        hint: Option<&IndexHint>,
+        limit: Option<usize>,
+    ) -> Result<Vec<Tx<'_, T>>> {
+        self.try_fetch(filters, hint, limit)
+            .map_err(|e| self.with_context(e, || format!("query::<{}>", T::schema().type_name)))
+    }
+
+    fn try_fetch<T: Object>(
+        &self,
+        filters: &[Filter],
+        hint: Option<&IndexHint>,
+        limit: Option<usize>,
+    ) -> Result<Vec<Tx<'_, T>>> {
+        self.ensure_table::<T>()?;
+        let visible = self.with_row_filter(T::schema(), filters);
+        let rows = self.inner.select_rows(T::schema(), &visible, hint, limit)?;
+        self.settings
+            .record(T::schema(), |stats| stats.read(rows.len()));
+        self.settings
+            .log_query(|| QueryShape::new(T::schema(), filters));
+        rows.into_iter()
+            .filter_map(|(id, row)| self.cache_row(id, row).transpose())
+            .collect()
+    }
+
+    pub fn children<T: Tree>(&self, id: ObjectId) -> Result<Vec<Tx<'_, T>>> {
+        let filter = Filter {
+            column: T::PARENT_COLUMN,
+            op: Op::Eq,
+            value: Value::Int64(id.into_i64()),
+        };
+        self.try_fetch(&[filter], None, None)
+            .map_err(|e| self.with_context(e, || format!("children::<{}>", T::schema().type_name)))
+    }
+
+    // Nearest first: the parent, then the grandparent and so on.
+    pub fn ancestors<T: Tree>(&self, id: ObjectId) -> Result<Vec<Tx<'_, T>>> {
+        let step = Step::new("id", T::PARENT_COLUMN);
+        self.try_fetch_recursive(
+            &[id_filter(Op::Eq, id)],
+            step,
+            &[id_filter(Op::Ne, id)],
+            None,
+        )
+        .map_err(|e| self.with_context(e, || format!("ancestors::<{}>", T::schema().type_name)))
+    }
+
+    // Breadth first: children, then grandchildren and so on.
+    pub fn descendants<T: Tree>(&self, id: ObjectId) -> Result<Vec<Tx<'_, T>>> {
+        let step = Step::new(T::PARENT_COLUMN, "id");
+        self.try_fetch_recursive(
+            &[id_filter(Op::Eq, id)],
+            step,
+            &[id_filter(Op::Ne, id)],
+            None,
+        )
+        .map_err(|e| self.with_context(e, || format!("descendants::<{}>", T::schema().type_name)))
+    }
+
+    // The object with the greatest `order` value in each group of equal
+    // `group` values, the latest created one on ties.
+    pub fn latest_per<T: Object>(
+        &self,
+        group: &'static str,
+        order: &'static str,
+    ) -> Result<Vec<Tx<'_, T>>> {
+        let window = Window::row_number()
+            .partition_by(group)
+            .order_by_desc(order)
+            .order_by_desc("id")
+            .filter(Op::Eq, 1i64);
+        let rows = self
+            .try_fetch_window::<T>(&[], &window, None)
+            .map_err(|e| {
+                self.with_context(e, || format!("latest_per::<{}>", T::schema().type_name))
+            })?;
+        Ok(rows.into_iter().map(|(tx, _)| tx).collect())
+    }
+
+    // Objects written after `revision`, oldest change first, with the revision
+    // of their latest write. Sync clients pass the greatest revision they've
+    // seen; deletions aren't tracked.
+    pub fn changed_since<T: Object>(&self, revision: i64) -> Result<Vec<(Tx<'_, T>, i64)>> {
+        self.try_changed_since(revision).map_err(|e| {
+            self.with_context(e, || {
+                format!("changed_since::<{}>({})", T::schema().type_name, revision)
+            })
+        })
+    }
+
+    fn try_changed_since<T: Object>(&self, revision: i64) -> Result<Vec<(Tx<'_, T>, i64)>> {
+        if !T::schema().revisions {
+            return Err(Error::invalid_request(
+                T::schema().type_name,
+                "type doesn't keep revisions, see #[orm(revisions)]",
+            ));
+        }
+        self.ensure_table::<T>()?;
+        let filters = self.with_row_filter(T::schema(), &[]);
+        let rows = self.inner.select_changed(T::schema(), &filters, revision)?;
+        self.settings
+            .record(T::schema(), |stats| stats.read(rows.len()));
+        let mut result = Vec::with_capacity(rows.len());
+        for (id, row, revision) in rows {
+            if let Some(tx) = self.cache_row(id, row)? {
+                result.push((tx, revision));
+            }
+        }
+        Ok(result)
+    }
+
+    pub(crate) fn fetch_window<T: Object>(
+        &self,
+        filters: &[Filter],
+        window: &Window,
+        limit: Option<usize>,
+    ) -> Result<Vec<(Tx<'_, T>, Value<'static>)>> {
+        self.try_fetch_window(filters, window, limit).map_err(|e| {
+            self.with_context(e, || format!("window query::<{}>", T::schema().type_name))
+        })
+    }
+
+    fn try_fetch_window<T: Object>(
+        &self,
+        filters: &[Filter],
+        window: &Window,
+        limit: Option<usize>,
+    ) -> Result<Vec<(Tx<'_, T>, Value<'static>)>> {
+        self.ensure_table::<T>()?;
+        let rows = self.inner.select_window(
+            T::schema(),
+            &self.with_row_filter(T::schema(), filters),
+            window,
+            limit,
+        )?;
+        self.settings
+            .record(T::schema(), |stats| stats.read(rows.len()));
+        let mut result = Vec::with_capacity(rows.len());
+        for (id, row, value) in rows {
+            if let Some(tx) = self.cache_row(id, row)? {
+                result.push((tx, value));
+            }
+        }
+        Ok(result)
+    }
+
+    pub(crate) fn fetch_recursive<T: Object>(
+        &self,
+        seed: &[Filter],
+        step: Step,
+        filters: &[Filter],
+        limit: Option<usize>,
+    ) -> Result<Vec<Tx<'_, T>>> {
+        self.try_fetch_recursive(seed, step, filters, limit)
+            .map_err(|e| {
+                self.with_context(e, || {
+                    format!("recursive query::<{}>", T::schema().type_name)
+                })
+            })
+    }
+
+    fn try_fetch_recursive<T: Object>(
+        &self,
+        seed: &[Filter],
+        step: Step,
+        filters: &[Filter],
+        limit: Option<usize>,
+    ) -> Result<Vec<Tx<'_, T>>> {
+        self.ensure_table::<T>()?;
+        let rows = self.inner.select_recursive(
+            T::schema(),
+            &self.with_row_filter(T::schema(), seed),
+            step,
+            &self.with_row_filter(T::schema(), filters),
+            limit,
+        )?;
+        self.settings
+            .record(T::schema(), |stats| stats.read(rows.len()));
+        rows.into_iter()
+            .filter_map(|(id, row)| self.cache_row(id, row).transpose())
+            .collect()
+    }
+
+    pub(crate) fn fetch_sample<T: Object>(
+        &self,
+        filters: &[Filter],
+        sample: Sample,
+        limit: Option<usize>,
+    ) -> Result<Vec<Tx<'_, T>>> {
+        self.try_fetch_sample(filters, sample, limit).map_err(|e| {
+            self.with_context(e, || format!("sample query::<{}>", T::schema().type_name))
+        })
+    }
+
+    fn try_fetch_sample<T: Object>(
+        &self,
+        filters: &[Filter],
+        sample: Sample,
+        limit: Option<usize>,
+    ) -> Result<Vec<Tx<'_, T>>> {
+        self.ensure_table::<T>()?;
+        let rows = self.inner.select_sample(
+            T::schema(),
+            &self.with_row_filter(T::schema(), filters),
+            sample,
+            limit,
+        )?;
+        self.settings
+            .record(T::schema(), |stats| stats.read(rows.len()));
+        self.settings
+            .log_query(|| QueryShape::new(T::schema(), filters));
+        rows.into_iter()
+            .filter_map(|(id, row)| self.cache_row(id, row).transpose())
+            .collect()
+    }
+
+    pub fn chunks<T: Object>(&self, size: usize) -> Chunks<'_, 'a, T> {
+        assert!(size != 0, "chunk size must be non-zero");
+        Chunks {
+            tx: self,
+            size,
+            filters: Vec::new(),
+            after: None,
+            evict: false,
+            previous: Vec::new(),
+            done: false,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn scan<T: Object>(&self) -> Scan<'_, 'a, T> {
+        self.scan_where(Vec::new(), None)
+    }
+
+    // Objects matching the filters, read a page at a time like scan, see
+    // Query::scan.
+    pub(crate) fn scan_where<T: Object>(
+        &self,
+        filters: Vec<Filter>,
+        limit: Option<usize>,
+    ) -> Scan<'_, 'a, T> {
+        let mut chunks = self.chunks(SCAN_BATCH_SIZE).evict_between(true);
+        chunks.filters = filters;
+        Scan {
+            chunks,
+            buffer: VecDeque::new(),
+            position: None,
+            remaining: limit,
+        }
+    }
+
+    // Each filter of the template takes its value from the parameters given
+    // per run. Runs share the same SQL, so its plan is reused from the
+    // statement cache rather than rebuilt.
+    pub fn prepare_query<T: Object>(
+        &self,
+        template: &[(&'static str, Op)],
+    ) -> Result<PreparedQuery<'_, 'a, T>> {
+        self.try_prepare_query(template).map_err(|e| {
+            self.with_context(e, || format!("prepare_query::<{}>", T::schema().type_name))
+        })
+    }
+
+    fn try_prepare_query<T: Object>(
+        &self,
+        template: &[(&'static str, Op)],
+    ) -> Result<PreparedQuery<'_, 'a, T>> {
+        let schema = T::schema();
+        for (column, _) in template {
+            if *column != "id" && schema.column(column).is_none() {
+                return Err(Error::invalid_request(
+                    schema.type_name,
+                    format!("no column '{}'", column),
+                ));
+            }
+        }
+        Ok(PreparedQuery {
+            tx: self,
+            template: template.to_vec(),
+            limit: None,
+            _marker: PhantomData,
+        })
+    }
+
+    fn cache_row<T: Object>(&self, id: ObjectId, row: Row<'static>) -> Result<Option<Tx<'_, T>>> {
+        let tx = match self.objects.borrow_mut().entry((T::schema(), id)) {
+            Entry::Vacant(place) => {
+                let row = self.decode_row(id, T::schema(), row)?;
+                let mut obj = T::try_from_row(row)?;
+                obj.set_id(id);
+                let state = self.track(id, obj, ObjectState::Clean);
+                let tx = Tx::new(state.clone());
+                place.insert(state);
+                tx
+            }
+            Entry::Occupied(e) => {
+                let rc = e.get().clone();
+                if rc.state.borrow().deref() == &ObjectState::Removed {
+                    return Ok(None);
+                }
+                Tx::new(rc)
+            }
+        };
+        self.touch(T::schema(), id);
+        Ok(Some(tx))
+    }
+
+    fn evict_unused<T: Object>(&self, ids: &[ObjectId]) {
+        for id in ids {
+            self.evict::<T>(*id);
         }
     }
 
     fn try_apply(&self) -> Result<()> {
+        self.apply_pending(None)
+    }
+
+    // Writes modified and removed objects, of one type if `only` is given.
+    fn apply_pending(&self, only: Option<&Schema>) -> Result<()> {
         for ((schema, id), obj) in self.objects.borrow().iter() {
+            if only.is_some_and(|only| only != *schema) {
+                continue;
+            }
             let state = obj.state.borrow();
             match state.deref() {
                 ObjectState::Modified => {
-                    self.inner
-                        .update_row(*id, schema, &obj.obj.borrow().to_row())?;
+                    let obj = obj.obj.borrow();
+                    obj.validate().map_err(|e| Error::Validation(Box::new(e)))?;
+                    let row = self.encode_row(schema, obj.to_row())?;
+                    self.writing();
+                    self.inner.update_row(*id, schema, &row)?;
+                    self.settings.record(schema, |stats| stats.updates += 1);
                 }
                 ObjectState::Removed => {
+                    self.writing();
                     self.inner.delete_row(*id, schema)?;
+                    self.settings.record(schema, |stats| stats.deletes += 1);
                 }
                 _ => {}
             }
@@ -104,19 +1247,408 @@ impl<'a> Transaction<'a> {
         Ok(())
     }
 
+    pub fn pending_changes(&self) -> Vec<ObjectChange> {
+        let change = |(schema, id): ObjectKey, state| ObjectChange {
+            type_name: schema.type_name,
+            table_name: schema.table_name,
+            id,
+            state,
+        };
+        let objects = self.objects.borrow();
+        let evicted_created = self.evicted_created.borrow();
+        let mut changes = evicted_created
+            .iter()
+            .filter(|key| !objects.contains_key(key))
+            .map(|key| change(*key, ObjectState::Created))
+            .collect::<Vec<_>>();
+        for (key, obj) in objects.iter() {
+            let created = obj.created || evicted_created.contains(key);
+            let state = match (created, *obj.state.borrow()) {
+                (_, ObjectState::Clean) | (true, ObjectState::Removed) => continue,
+                (true, _) => ObjectState::Created,
+                (false, state) => state,
+            };
+            changes.push(change(*key, state));
+        }
+        changes.sort_by_key(|change| (change.type_name, change.id));
+        changes
+    }
+
+    // Returns the rows deleted to make room, as changes for after_commit.
+    fn enforce_quotas(&self) -> Result<Vec<ObjectChange>> {
+        let mut deleted_rows = Vec::new();
+        if self.settings.quotas.is_empty() {
+            return Ok(deleted_rows);
+        }
+        let mut schemas = self
+            .evicted_created
+            .borrow()
+            .iter()
+            .map(|(schema, _)| *schema)
+            .collect::<Vec<_>>();
+        for ((schema, _), obj) in self.objects.borrow().iter() {
+            if obj.created || *obj.state.borrow() == ObjectState::Modified {
+                schemas.push(schema);
+            }
+        }
+        schemas.sort_unstable_by_key(|schema| schema.table_name);
+        schemas.dedup();
+
+        for schema in schemas {
+            let Some(quota) = self.settings.quotas.get(schema.table_name) else {
+                continue;
+            };
+            loop {
+                let usage = self.inner.table_usage(schema)?;
+                if !quota.is_exceeded(usage) {
+                    break;
+                }
+                let err = QuotaError {
+                    table_name: schema.table_name,
+                    quota: *quota,
+                    usage,
+                };
+                let action = match &self.settings.quota_policy {
+                    Some(policy) => policy(&err),
+                    None => QuotaAction::Reject,
+                };
+                let deleted = match action {
+                    QuotaAction::DeleteOldest(count) => self.delete_oldest(schema, count)?,
+                    QuotaAction::Reject => Vec::new(),
+                };
+                self.settings
+                    .record(schema, |stats| stats.deletes += deleted.len() as u64);
+                if deleted.is_empty() {
+                    return Err(Error::QuotaExceeded(Box::new(err)));
+                }
+                deleted_rows.extend(deleted.into_iter().map(|id| ObjectChange {
+                    type_name: schema.type_name,
+                    table_name: schema.table_name,
+                    id,
+                    state: ObjectState::Removed,
+                }));
+            }
+        }
+        Ok(deleted_rows)
+    }
+
+    // Deletes like the connection's own code would: rows hidden by the row
+    // filter are left alone, and so are objects this transaction holds, whose
+    // handles would otherwise outlive their rows.
+    fn delete_oldest(&self, schema: &'static Schema, count: usize) -> Result<Vec<ObjectId>> {
+        self.check_access(schema, Operation::Delete)?;
+        let held = self
+            .objects
+            .borrow()
+            .keys()
+            .chain(self.evicted_created.borrow().iter())
+            .filter(|(s, _)| *s == schema)
+            .map(|(_, id)| *id)
+            .collect::<HashSet<_>>();
+        let filters = self.with_row_filter(schema, &[]);
+        let rows = self
+            .inner
+            .select_rows(schema, &filters, None, Some(count + held.len()))?;
+        let ids = rows
+            .into_iter()
+            .map(|(id, _)| id)
+            .filter(|id| !held.contains(id))
+            .take(count)
+            .collect::<Vec<_>>();
+        self.writing();
+        for id in &ids {
+            self.inner.delete_row(*id, schema)?;
+        }
+        Ok(ids)
+    }
+
     pub fn commit(self) -> Result<()> {
-        self.try_apply()?;
-        self.inner.commit()
+        let changes = (!self.settings.after_commit.is_empty()).then(|| self.pending_changes());
+        let started = Instant::now();
+        let result = self.try_apply().and_then(|_| {
+            let deleted = self.enforce_quotas()?;
+            self.drop_temp_tables()?;
+            self.inner.commit()?;
+            Ok(deleted)
+        });
+        let deleted = result.map_err(|e| {
+            // Read before discard forgets the objects written.
+            let err = self.with_context(e, || "commit".to_string()).with_conflict(
+                || "commit".to_string(),
+                || {
+                    let mut tables = self
+                        .pending_changes()
+                        .into_iter()
+                        .map(|change| change.table_name)
+                        .collect::<Vec<_>>();
+                    tables.sort_unstable();
+                    tables.dedup();
+                    LockConflictInfo {
+                        tables,
+                        elapsed: started.elapsed(),
+                    }
+                },
+            );
+            self.discard();
+            err
+        })?;
+
+        if let Some(mut changes) = changes.filter(|_| !self.settings.after_commit.is_empty()) {
+            changes.extend(deleted);
+            let temp_tables = self.temp_tables.borrow();
+            changes.retain(|change| {
+                !temp_tables
+                    .iter()
+                    .any(|schema| schema.table_name == change.table_name)
+            });
+            for hook in &self.settings.after_commit {
+                hook(&changes);
+            }
+        }
+        Ok(())
     }
 
-    pub fn rollback(self) -> Result<()> {
+    fn discard(&self) {
         self.objects.borrow_mut().iter().for_each(|(_, obj)| {
             *obj.state
                 .try_borrow_mut()
-                .expect("cannot rollback with borrowed values") = ObjectState::Clean;
+                .expect("cannot rollback with borrowed values") = if obj.created {
+                ObjectState::Removed
+            } else {
+                ObjectState::Clean
+            };
         });
         self.objects.borrow_mut().clear();
-        self.inner.rollback()
+        self.evicted_created.borrow_mut().clear();
+        self.references.borrow_mut().clear();
+        self.last_used.borrow_mut().clear();
+    }
+
+    pub fn rollback(self) -> Result<()> {
+        self.discard();
+        self.inner
+            .rollback()
+            .map_err(|e| self.with_context(e, || "rollback".to_string()))
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub struct Chunks<'t, 'a, T> {
+    tx: &'t Transaction<'a>,
+    size: usize,
+    filters: Vec<Filter>,
+    after: Option<ObjectId>,
+    evict: bool,
+    previous: Vec<ObjectId>,
+    done: bool,
+    _marker: PhantomData<T>,
+}
+
+impl<'t, 'a, T: Object> Chunks<'t, 'a, T> {
+    pub fn evict_between(mut self, evict: bool) -> Self {
+        self.evict = evict;
+        self
+    }
+
+    fn next_chunk(&mut self) -> Result<Vec<Tx<'t, T>>> {
+        if self.after.is_none() {
+            self.tx.ensure_table::<T>()?;
+        }
+        if self.evict {
+            self.tx
+                .evict_unused::<T>(&std::mem::take(&mut self.previous));
+        }
+
+        let mut chunk = Vec::new();
+        while chunk.is_empty() && !self.done {
+            let rows = self
+                .tx
+                .select_page::<T>(self.after, self.size, &self.filters)?;
+            self.tx
+                .settings
+                .record(T::schema(), |stats| stats.read(rows.len()));
+            self.done = rows.len() < self.size;
+            self.after = rows.last().map(|(id, _)| *id).or(self.after);
+            for (id, row) in rows {
+                if let Some(obj) = self.tx.cache_row(id, row)? {
+                    chunk.push(obj);
+                }
+            }
+        }
+        if self.evict {
+            self.previous = chunk.iter().map(Tx::id).collect();
+        }
+        Ok(chunk)
+    }
+}
+
+impl<'t, 'a, T: Object> Iterator for Chunks<'t, 'a, T> {
+    type Item = Result<Vec<Tx<'t, T>>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            if self.evict {
+                self.tx
+                    .evict_unused::<T>(&std::mem::take(&mut self.previous));
+            }
+            return None;
+        }
+        match self.next_chunk() {
+            Ok(chunk) if chunk.is_empty() => None,
+            Ok(chunk) => Some(Ok(chunk)),
+            Err(e) => {
+                self.done = true;
+                let operation = || format!("chunks::<{}>", T::schema().type_name);
+                Some(Err(self.tx.with_context(e, operation)))
+            }
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+const SCAN_BATCH_SIZE: usize = 1000;
+
+pub struct Scan<'t, 'a, T> {
+    chunks: Chunks<'t, 'a, T>,
+    buffer: VecDeque<Tx<'t, T>>,
+    position: Option<ObjectId>,
+    remaining: Option<usize>,
+}
+
+impl<'t, 'a, T: Object> Scan<'t, 'a, T> {
+    pub fn batch_size(mut self, size: usize) -> Self {
+        assert!(size != 0, "batch size must be non-zero");
+        self.chunks.size = size;
+        self
+    }
+
+    pub fn resume(mut self, cursor: &ScanCursor) -> Result<Self> {
+        if cursor.table_name != T::schema().table_name {
+            return Err(Error::invalid_request(
+                T::schema().type_name,
+                format!(
+                    "cursor of table '{}' can't resume this scan",
+                    cursor.table_name
+                ),
+            ));
+        }
+        self.chunks.after = cursor.after;
+        self.position = cursor.after;
+        Ok(self)
+    }
+
+    // Points just past the last object returned by `next`, not the last one
+    // fetched, so nothing buffered but unprocessed is skipped on resume.
+    pub fn cursor(&self) -> ScanCursor {
+        ScanCursor {
+            table_name: T::schema().table_name.to_string(),
+            after: self.position,
+        }
+    }
+}
+
+impl<'t, 'a, T: Object> Iterator for Scan<'t, 'a, T> {
+    type Item = Result<Tx<'t, T>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == Some(0) {
+            return None;
+        }
+        if self.buffer.is_empty() {
+            match self.chunks.next()? {
+                Ok(chunk) => self.buffer.extend(chunk),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        let obj = self.buffer.pop_front()?;
+        self.position = Some(obj.id());
+        if let Some(remaining) = &mut self.remaining {
+            *remaining -= 1;
+        }
+        Some(Ok(obj))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScanCursor {
+    pub table_name: String,
+    pub after: Option<ObjectId>,
+}
+
+impl fmt::Display for ScanCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@", self.table_name)?;
+        match self.after {
+            Some(id) => write!(f, "{}", id),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseCursorError;
+
+impl fmt::Display for ParseCursorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid scan cursor")
+    }
+}
+
+impl std::error::Error for ParseCursorError {}
+
+impl FromStr for ScanCursor {
+    type Err = ParseCursorError;
+
+    fn from_str(token: &str) -> std::result::Result<Self, Self::Err> {
+        let (table_name, after) = token.rsplit_once('@').ok_or(ParseCursorError)?;
+        let after = match after {
+            "" => None,
+            id => Some(ObjectId::new(id.parse().map_err(|_| ParseCursorError)?)),
+        };
+        Ok(Self {
+            table_name: table_name.to_string(),
+            after,
+        })
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub struct PreparedQuery<'t, 'a, T> {
+    tx: &'t Transaction<'a>,
+    template: Vec<(&'static str, Op)>,
+    limit: Option<usize>,
+    _marker: PhantomData<T>,
+}
+
+impl<'t, 'a, T: Object> PreparedQuery<'t, 'a, T> {
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn fetch(&self, params: &[Value<'_>]) -> Result<Vec<Tx<'t, T>>> {
+        if params.len() != self.template.len() {
+            let message = format!(
+                "prepared query takes {} parameters, got {}",
+                self.template.len(),
+                params.len()
+            );
+            return Err(Error::invalid_request(T::schema().type_name, message));
+        }
+        let filters = self
+            .template
+            .iter()
+            .zip(params)
+            .map(|(&(column, op), value)| Filter {
+                column,
+                op,
+                value: value.clone().into_owned(),
+            })
+            .collect::<Vec<_>>();
+        self.tx.fetch(&filters, None, self.limit)
     }
 }
 
@@ -125,15 +1657,78 @@ impl<'a> Transaction<'a> {
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum ObjectState {
     Clean,
+    Created,
     Modified,
     Removed,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct ObjectChange {
+    pub type_name: &'static str,
+    pub table_name: &'static str,
+    pub id: ObjectId,
+    pub state: ObjectState,
+}
+
+pub(crate) fn load_any<'t, T: Object>(tx: &'t Transaction<'_>, id: ObjectId) -> Result<AnyTx<'t>> {
+    tx.get::<T>(id).map(Tx::into_any)
+}
+
+fn id_filter(op: Op, id: ObjectId) -> Filter {
+    Filter {
+        column: "id",
+        op,
+        value: Value::Int64(id.into_i64()),
+    }
+}
+
+type ObjectKey = (&'static Schema, ObjectId);
+type Observer = Rc<dyn Fn(&'static Schema, ObjectId, ObjectState)>;
+type Observers = RefCell<Vec<Observer>>;
+
 #[derive(Clone)]
 struct TxState {
     id: ObjectId,
+    schema: &'static Schema,
     obj: Rc<RefCell<dyn Store>>,
     state: Rc<RefCell<ObjectState>>,
+    created: bool,
+    access: Access,
+    observers: Rc<Observers>,
+}
+
+impl TxState {
+    // Tx methods can't report errors, so restricted writes panic there.
+    fn check_access(&self, operation: Operation) {
+        if self.created || self.access.allows(operation) {
+            return;
+        }
+        panic!(
+            "{}",
+            AccessDeniedError {
+                type_name: self.schema.type_name,
+                operation,
+                access: self.access,
+            }
+        )
+    }
+
+    fn transition(&self, state: ObjectState) {
+        let previous = std::mem::replace(&mut *self.state.borrow_mut(), state);
+        if previous != state {
+            let observers = self.observers.borrow().clone();
+            for observer in observers {
+                observer(self.schema, self.id, state);
+            }
+        }
+    }
+
+    fn is_evictable(&self) -> bool {
+        matches!(
+            *self.state.borrow(),
+            ObjectState::Clean | ObjectState::Created
+        ) && Rc::strong_count(&self.state) == 1
+    }
 }
 
 #[derive(Clone)]
@@ -142,14 +1737,7 @@ pub struct Tx<'a, T: ?Sized> {
     _marker: PhantomData<&'a T>,
 }
 
-impl<'a, T: Any> Tx<'a, T> {
-    fn new(state: TxState) -> Self {
-        Self {
-            state,
-            _marker: PhantomData,
-        }
-    }
-
+impl<T: ?Sized> Tx<'_, T> {
     pub fn id(&self) -> ObjectId {
         self.state.id
     }
@@ -157,6 +1745,15 @@ impl<'a, T: Any> Tx<'a, T> {
     pub fn state(&self) -> ObjectState {
         *self.state.state.borrow()
     }
+}
+
+impl<'a, T: Any> Tx<'a, T> {
+    fn new(state: TxState) -> Self {
+        Self {
+            state,
+            _marker: PhantomData,
+        }
+    }
 
     pub fn borrow(&self) -> Ref<'_, T> {
         if self.state() == ObjectState::Removed {
@@ -168,17 +1765,54 @@ impl<'a, T: Any> Tx<'a, T> {
     }
 
     pub fn borrow_mut(&self) -> RefMut<'_, T> {
-        *self.state.state.borrow_mut() = ObjectState::Modified;
+        self.state.check_access(Operation::Update);
+        self.state.transition(ObjectState::Modified);
         RefMut::map(self.state.obj.borrow_mut(), |x| {
             x.as_mut_any().downcast_mut::<T>().unwrap()
         })
     }
 
+    // An empty patch leaves the object clean.
+    pub fn apply<P: Patch<Object = T>>(&self, patch: P) {
+        if !patch.is_empty() {
+            patch.apply_to(&mut self.borrow_mut());
+        }
+    }
+
     pub fn delete(self) {
+        self.state.check_access(Operation::Delete);
         self.state
             .obj
             .try_borrow_mut()
             .expect("cannot delete a borrowed object");
-        *self.state.state.borrow_mut() = ObjectState::Removed;
+        self.state.transition(ObjectState::Removed);
+    }
+
+    pub fn into_any(self) -> AnyTx<'a> {
+        Tx {
+            state: self.state,
+            _marker: PhantomData,
+        }
+    }
+}
+
+// An object whose type is only known at runtime, such as a PolyRef target.
+pub type AnyTx<'a> = Tx<'a, dyn Any>;
+
+impl<'a> Tx<'a, dyn Any> {
+    pub fn schema(&self) -> &'static Schema {
+        self.state.schema
+    }
+
+    pub fn is<T: Object>(&self) -> bool {
+        self.state.schema == T::schema()
+    }
+
+    pub fn downcast<T: Object>(self) -> std::result::Result<Tx<'a, T>, Self> {
+        if self.is::<T>() {
+            Ok(Tx::new(self.state))
+        } else {
+            Err(self)
+        }
     }
 }
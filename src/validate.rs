@@ -0,0 +1,144 @@
+#![forbid(unsafe_code)]
+
+use std::fmt;
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ValidationFailure {
+    pub attr_name: &'static str,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ValidationError {
+    pub type_name: &'static str,
+    pub failures: Vec<ValidationFailure>,
+}
+
+impl ValidationError {
+    pub fn check(
+        type_name: &'static str,
+        failures: Vec<ValidationFailure>,
+    ) -> Result<(), ValidationError> {
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationError {
+                type_name,
+                failures,
+            })
+        }
+    }
+
+    pub fn attr_names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.failures.iter().map(|failure| failure.attr_name)
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "validation failed for {}: ", self.type_name)?;
+        let failures = self
+            .failures
+            .iter()
+            .map(|failure| format!("{} {}", failure.attr_name, failure.message))
+            .collect::<Vec<_>>();
+        write!(f, "{}", failures.join("; "))
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub trait Validated {
+    type Inner: ?Sized;
+
+    fn validated(&self) -> Option<&Self::Inner>;
+}
+
+macro_rules! impl_validated {
+    ($t:ty, $inner:ty) => {
+        impl Validated for $t {
+            type Inner = $inner;
+
+            fn validated(&self) -> Option<&Self::Inner> {
+                Some(self)
+            }
+        }
+    };
+}
+
+impl_validated!(String, str);
+impl_validated!(Vec<u8>, [u8]);
+impl_validated!(i64, i64);
+impl_validated!(f64, f64);
+impl_validated!(bool, bool);
+
+impl<T: Validated> Validated for Option<T> {
+    type Inner = T::Inner;
+
+    fn validated(&self) -> Option<&Self::Inner> {
+        self.as_ref().and_then(Validated::validated)
+    }
+}
+
+pub trait Length {
+    fn length(&self) -> usize;
+}
+
+impl Length for str {
+    fn length(&self) -> usize {
+        self.chars().count()
+    }
+}
+
+impl Length for [u8] {
+    fn length(&self) -> usize {
+        self.len()
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+const GOT: &str = ", got ";
+
+pub fn check_length<T>(value: &T, min: Option<usize>, max: Option<usize>) -> Result<(), String>
+where
+    T: Validated,
+    T::Inner: Length,
+{
+    let Some(len) = value.validated().map(Length::length) else {
+        return Ok(());
+    };
+    match (min, max) {
+        (Some(min), _) if len < min => {
+            Err(format!("length must be at least {}{}{}", min, GOT, len))
+        }
+        (_, Some(max)) if len > max => Err(format!("length must be at most {}{}{}", max, GOT, len)),
+        _ => Ok(()),
+    }
+}
+
+pub fn check_range<T>(value: &T, min: Option<T::Inner>, max: Option<T::Inner>) -> Result<(), String>
+where
+    T: Validated,
+    T::Inner: PartialOrd + fmt::Display + Sized,
+{
+    let Some(value) = value.validated() else {
+        return Ok(());
+    };
+    match (min, max) {
+        (Some(min), _) if *value < min => Err(format!("must be at least {}{}{}", min, GOT, value)),
+        (_, Some(max)) if *value > max => Err(format!("must be at most {}{}{}", max, GOT, value)),
+        _ => Ok(()),
+    }
+}
+
+pub fn redact_value(result: Result<(), String>) -> Result<(), String> {
+    result.map_err(|message| match message.rfind(GOT) {
+        Some(pos) => format!("{}{}<redacted>", &message[..pos], GOT),
+        None => message,
+    })
+}
@@ -0,0 +1,172 @@
+#![forbid(unsafe_code)]
+
+use std::backtrace::Backtrace;
+use std::collections::HashMap;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex, Weak,
+};
+use std::thread;
+use std::time::{Duration, Instant};
+
+////////////////////////////////////////////////////////////////////////////////
+
+// How long a transaction may stay open before it's reported, see
+// Connection::set_watchdog. Each limit is reported at most once per
+// transaction, from a background thread, while the transaction is still open.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Watchdog {
+    pub open: Option<Duration>,
+    // Counted from the first write, which takes the database's write lock and
+    // keeps other writers waiting until the transaction ends.
+    pub write_lock: Option<Duration>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overrun {
+    Open,
+    WriteLock,
+}
+
+#[derive(Debug)]
+pub struct LongTransaction {
+    pub overrun: Overrun,
+    pub elapsed: Duration,
+    // Where the transaction was started.
+    pub backtrace: Arc<Backtrace>,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+type Callback = Box<dyn Fn(&LongTransaction) + Send + Sync>;
+
+// Checks four times per limit, but no more than once a millisecond and at
+// least once a second.
+const MAX_TICK: Duration = Duration::from_secs(1);
+const MIN_TICK: Duration = Duration::from_millis(1);
+
+struct Entry {
+    started: Instant,
+    write_started: Option<Instant>,
+    backtrace: Arc<Backtrace>,
+    open_reported: bool,
+    write_reported: bool,
+}
+
+struct Shared {
+    limits: Watchdog,
+    callback: Callback,
+    entries: Mutex<HashMap<u64, Entry>>,
+    next_id: AtomicU64,
+}
+
+impl Shared {
+    fn check(&self, now: Instant) {
+        let mut reports = Vec::new();
+        for entry in self.entries.lock().unwrap().values_mut() {
+            let open = now - entry.started;
+            if !entry.open_reported && self.limits.open.is_some_and(|limit| open > limit) {
+                entry.open_reported = true;
+                reports.push(LongTransaction {
+                    overrun: Overrun::Open,
+                    elapsed: open,
+                    backtrace: Arc::clone(&entry.backtrace),
+                });
+            }
+            let Some(write_started) = entry.write_started else {
+                continue;
+            };
+            let locked = now - write_started;
+            if !entry.write_reported && self.limits.write_lock.is_some_and(|limit| locked > limit) {
+                entry.write_reported = true;
+                reports.push(LongTransaction {
+                    overrun: Overrun::WriteLock,
+                    elapsed: locked,
+                    backtrace: Arc::clone(&entry.backtrace),
+                });
+            }
+        }
+        // The callback may take its time; transactions starting or ending
+        // meanwhile shouldn't wait for it.
+        for report in &reports {
+            (self.callback)(report);
+        }
+    }
+}
+
+// The connection's side: owns the limits, and the thread checking them runs
+// until the monitor and every transaction it watches are gone.
+pub(crate) struct Monitor {
+    shared: Arc<Shared>,
+}
+
+impl Monitor {
+    pub fn new(limits: Watchdog, callback: Callback) -> Self {
+        let shared = Arc::new(Shared {
+            limits,
+            callback,
+            entries: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(0),
+        });
+        let tick = [limits.open, limits.write_lock]
+            .into_iter()
+            .flatten()
+            .map(|limit| limit / 4)
+            .min()
+            .unwrap_or(MAX_TICK)
+            .clamp(MIN_TICK, MAX_TICK);
+        let weak = Arc::downgrade(&shared);
+        thread::spawn(move || watch(weak, tick));
+        Self { shared }
+    }
+
+    // Capturing the backtrace is the expensive part of starting a watched
+    // transaction; it's forced, since the report is useless without it.
+    pub fn start(&self) -> Watch {
+        let id = self.shared.next_id.fetch_add(1, Ordering::Relaxed);
+        self.shared.entries.lock().unwrap().insert(
+            id,
+            Entry {
+                started: Instant::now(),
+                write_started: None,
+                backtrace: Arc::new(Backtrace::force_capture()),
+                open_reported: false,
+                write_reported: false,
+            },
+        );
+        Watch {
+            shared: Arc::clone(&self.shared),
+            id,
+        }
+    }
+}
+
+fn watch(shared: Weak<Shared>, tick: Duration) {
+    loop {
+        thread::sleep(tick);
+        let Some(shared) = shared.upgrade() else {
+            return;
+        };
+        shared.check(Instant::now());
+    }
+}
+
+// One watched transaction, unregistered when it ends.
+pub(crate) struct Watch {
+    shared: Arc<Shared>,
+    id: u64,
+}
+
+impl Watch {
+    pub fn writing(&self) {
+        if let Some(entry) = self.shared.entries.lock().unwrap().get_mut(&self.id) {
+            entry.write_started.get_or_insert_with(Instant::now);
+        }
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        self.shared.entries.lock().unwrap().remove(&self.id);
+    }
+}
@@ -0,0 +1,68 @@
+use orm::access::{Access, Operation};
+use orm::{Connection, Error, Object};
+
+mod common;
+
+#[derive(Object, Debug)]
+#[orm(expires(field = "expires_at"))]
+struct Session {
+    token: String,
+    expires_at: Option<i64>,
+}
+
+#[derive(Object, Debug)]
+struct Secret {
+    #[orm(encrypt)]
+    token: String,
+}
+
+struct Xor;
+
+impl orm::cipher::ColumnCipher for Xor {
+    fn encrypt(&self, plain: &[u8]) -> Vec<u8> {
+        plain.iter().map(|b| b ^ 3).collect()
+    }
+
+    fn decrypt(&self, cipher: &[u8]) -> Option<Vec<u8>> {
+        Some(cipher.iter().map(|b| b ^ 3).collect())
+    }
+}
+
+fn denied(err: &Error, expected: Operation) -> bool {
+    matches!(err, Error::AccessDenied(err) if err.operation == expected)
+}
+
+#[test]
+fn purges_respect_restricted_tables() {
+    let path = common::temp_db("access-purge");
+    let mut conn = Connection::open_sqlite_file(&path).unwrap();
+    let tx = conn.new_transaction().unwrap();
+    tx.create(Session {
+        token: "old".into(),
+        expires_at: Some(1),
+    })
+    .unwrap();
+    tx.commit().unwrap();
+
+    conn.restrict::<Session>(Access::AppendOnly);
+    let err = conn.purge_expired::<Session>().err().unwrap();
+    assert!(denied(&err, Operation::Delete), "{}", err);
+    assert_eq!(common::count(&path, "Session"), 1);
+}
+
+#[test]
+fn reencryption_respects_restricted_tables() {
+    let path = common::temp_db("access-reencrypt");
+    let mut conn = Connection::open_sqlite_file(&path).unwrap();
+    conn.set_cipher(Xor);
+    let tx = conn.new_transaction().unwrap();
+    tx.create(Secret {
+        token: "hunter2".into(),
+    })
+    .unwrap();
+    tx.commit().unwrap();
+
+    conn.restrict::<Secret>(Access::ReadOnly);
+    let err = conn.reencrypt::<Secret>().err().unwrap();
+    assert!(denied(&err, Operation::Update), "{}", err);
+}
@@ -0,0 +1,39 @@
+#![allow(dead_code)]
+
+use std::net::{SocketAddr, TcpListener};
+use std::path::PathBuf;
+use std::thread;
+
+use orm::remote::Server;
+
+////////////////////////////////////////////////////////////////////////////////
+
+// A fresh database file per test, so tests can run in parallel and inspect
+// the stored rows with a connection of their own.
+pub fn temp_db(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("orm-test-{}-{}.db", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+pub fn raw(path: &PathBuf) -> rusqlite::Connection {
+    rusqlite::Connection::open(path).unwrap()
+}
+
+pub fn count(path: &PathBuf, table: &str) -> i64 {
+    raw(path)
+        .query_row(&format!("SELECT COUNT(*) FROM \"{}\"", table), [], |row| {
+            row.get(0)
+        })
+        .unwrap()
+}
+
+// Serves `path` on a local port until the test process exits.
+pub fn serve(path: PathBuf, configure: impl FnOnce(&mut Server)) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut server = Server::new(path);
+    configure(&mut server);
+    thread::spawn(move || server.serve(listener).unwrap());
+    addr
+}
@@ -0,0 +1,57 @@
+use orm::query::{Op, Query};
+use orm::{Connection, Error, Object};
+
+mod common;
+
+#[derive(Object, Debug)]
+struct Secret {
+    name: String,
+    #[orm(encrypt)]
+    token: Option<String>,
+}
+
+struct Xor;
+
+impl orm::cipher::ColumnCipher for Xor {
+    fn encrypt(&self, plain: &[u8]) -> Vec<u8> {
+        plain.iter().map(|b| b ^ 3).collect()
+    }
+
+    fn decrypt(&self, cipher: &[u8]) -> Option<Vec<u8>> {
+        Some(cipher.iter().map(|b| b ^ 3).collect())
+    }
+}
+
+fn check(conn: &mut Connection) {
+    conn.set_cipher(Xor);
+    let tx = conn.new_transaction().unwrap();
+    tx.create(Secret {
+        name: "a".into(),
+        token: Some("hunter2".into()),
+    })
+    .unwrap();
+    tx.create(Secret {
+        name: "b".into(),
+        token: None,
+    })
+    .unwrap();
+
+    let by_token = Query::<Secret>::new().filter("token", Op::Eq, "hunter2".to_string());
+    let err = by_token.fetch(&tx).err().unwrap();
+    assert!(matches!(err.inner(), Error::InvalidRequest(_)), "{}", err);
+    let unset = Query::<Secret>::new().filter("token", Op::Eq, None::<String>);
+    assert_eq!(unset.fetch(&tx).unwrap().len(), 1);
+}
+
+#[test]
+fn filters_on_encrypted_columns_fail() {
+    check(&mut Connection::open_in_memory().unwrap());
+}
+
+#[test]
+fn filters_on_encrypted_columns_fail_remotely() {
+    let addr = common::serve(common::temp_db("encryption-remote"), |server| {
+        server.register::<Secret>();
+    });
+    check(&mut Connection::connect_remote(addr).unwrap());
+}
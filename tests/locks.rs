@@ -0,0 +1,28 @@
+use orm::{Connection, Error, Object};
+
+mod common;
+
+#[derive(Object, Debug)]
+struct Account {
+    n: i64,
+}
+
+#[test]
+fn lock_conflicts_carry_details_without_error_context() {
+    let path = common::temp_db("locks");
+    let mut a = Connection::open_sqlite_file(&path).unwrap();
+    let mut b = Connection::open_sqlite_file(&path).unwrap();
+    let tx = a.new_transaction().unwrap();
+    let id = tx.create(Account { n: 1 }).unwrap().id();
+    tx.commit().unwrap();
+
+    let holder = b.new_transaction().unwrap();
+    holder.increment::<Account>(id, "n", 1).unwrap();
+    let tx = a.new_transaction().unwrap();
+    tx.get::<Account>(id).unwrap().borrow_mut().n = 2;
+    let err = tx.commit().unwrap_err();
+    assert!(matches!(err.inner(), Error::LockConflict), "{}", err);
+    let info = err.lock_conflict().unwrap();
+    assert_eq!(info.tables, vec!["Account"]);
+    holder.rollback().unwrap();
+}
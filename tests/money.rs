@@ -0,0 +1,58 @@
+use orm::money::Money;
+use orm::{Connection, Error, Object};
+
+mod common;
+
+#[derive(Object, Debug)]
+#[orm(query)]
+struct Invoice {
+    name: String,
+    total: Money,
+}
+
+#[test]
+fn sums_of_unknown_or_plain_columns_fail() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    tx.create(Invoice {
+        name: "a".into(),
+        total: Money::new(5, "USD"),
+    })
+    .unwrap();
+    for column in ["totl", "name"] {
+        let err = Invoice::query().sum_money(&tx, column).err().unwrap();
+        assert!(matches!(err.inner(), Error::InvalidRequest(_)), "{}", err);
+    }
+}
+
+#[test]
+fn sums_report_malformed_currencies() {
+    let path = common::temp_db("money-currency");
+    let mut conn = Connection::open_sqlite_file(&path).unwrap();
+    let tx = conn.new_transaction().unwrap();
+    tx.create(Invoice {
+        name: "a".into(),
+        total: Money::new(5, "USD"),
+    })
+    .unwrap();
+    tx.commit().unwrap();
+    common::raw(&path)
+        .execute("UPDATE Invoice SET total_currency = X'FF'", [])
+        .unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let err = Invoice::query().sum_money(&tx, "total").err().unwrap();
+    assert!(matches!(err.inner(), Error::UnexpectedType(_)), "{}", err);
+}
+
+#[test]
+fn sums_of_limited_queries_fail() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let err = Invoice::query()
+        .limit(1)
+        .sum_money(&tx, "total")
+        .err()
+        .unwrap();
+    assert!(matches!(err, Error::InvalidRequest(_)), "{}", err);
+}
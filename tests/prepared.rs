@@ -0,0 +1,32 @@
+use orm::data::Value;
+use orm::query::Op;
+use orm::{Connection, Error, Object};
+
+#[derive(Object, Debug)]
+struct Account {
+    email: String,
+}
+
+#[test]
+fn prepared_queries_check_columns_and_parameters() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    tx.create(Account {
+        email: "a@example.com".into(),
+    })
+    .unwrap();
+
+    let err = tx
+        .prepare_query::<Account>(&[("mail", Op::Eq)])
+        .err()
+        .unwrap();
+    assert!(matches!(err.inner(), Error::InvalidRequest(_)), "{}", err);
+
+    let query = tx.prepare_query::<Account>(&[("email", Op::Eq)]).unwrap();
+    let err = query.fetch(&[]).err().unwrap();
+    assert!(matches!(err.inner(), Error::InvalidRequest(_)), "{}", err);
+    let found = query
+        .fetch(&[Value::String("a@example.com".into())])
+        .unwrap();
+    assert_eq!(found.len(), 1);
+}
@@ -0,0 +1,74 @@
+use std::sync::{Arc, Mutex};
+
+use orm::quota::{Quota, QuotaAction};
+use orm::{Connection, Error, Object, ObjectState};
+
+mod common;
+
+#[derive(Object, Debug)]
+struct Item {
+    n: i64,
+}
+
+fn stored(path: &std::path::PathBuf) -> Vec<i64> {
+    let conn = common::raw(path);
+    let mut stmt = conn.prepare("SELECT n FROM Item ORDER BY id").unwrap();
+    let rows = stmt.query_map([], |row| row.get(0)).unwrap();
+    rows.collect::<rusqlite::Result<_>>().unwrap()
+}
+
+#[test]
+fn exceeding_a_quota_rejects_the_commit() {
+    let path = common::temp_db("quota-reject");
+    let mut conn = Connection::open_sqlite_file(&path).unwrap();
+    let tx = conn.new_transaction().unwrap();
+    tx.create(Item { n: 0 }).unwrap();
+    tx.commit().unwrap();
+
+    conn.set_quota::<Item>(Some(Quota::rows(2)));
+    let tx = conn.new_transaction().unwrap();
+    for n in 1..3 {
+        tx.create(Item { n }).unwrap();
+    }
+    let err = tx.commit().unwrap_err();
+    assert!(
+        matches!(err.inner(), Error::QuotaExceeded(e) if e.usage.rows == 3),
+        "{}",
+        err
+    );
+    assert_eq!(stored(&path), vec![0]);
+}
+
+#[test]
+fn delete_oldest_spares_objects_held_by_the_transaction() {
+    let path = common::temp_db("quota-evict");
+    let mut conn = Connection::open_sqlite_file(&path).unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let first = tx.create(Item { n: 0 }).unwrap().id();
+    for n in 1..3 {
+        tx.create(Item { n }).unwrap();
+    }
+    tx.commit().unwrap();
+
+    let removed = Arc::new(Mutex::new(Vec::new()));
+    let seen = removed.clone();
+    conn.set_quota::<Item>(Some(Quota::rows(3)))
+        .on_quota_exceeded(|e| {
+            QuotaAction::DeleteOldest((e.usage.rows - e.quota.max_rows.unwrap()) as usize)
+        })
+        .after_commit(move |changes| {
+            let ids = changes
+                .iter()
+                .filter(|change| change.state == ObjectState::Removed)
+                .map(|change| change.id);
+            seen.lock().unwrap().extend(ids);
+        });
+
+    // The oldest row is loaded and modified, so the one after it goes.
+    let tx = conn.new_transaction().unwrap();
+    tx.get::<Item>(first).unwrap().borrow_mut().n = 10;
+    tx.create(Item { n: 3 }).unwrap();
+    tx.commit().unwrap();
+    assert_eq!(stored(&path), vec![10, 2, 3]);
+    assert_eq!(removed.lock().unwrap().len(), 1);
+}
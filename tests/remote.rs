@@ -0,0 +1,140 @@
+use orm::{Connection, Error, Object};
+
+mod common;
+
+#[derive(Object, Debug)]
+struct Note {
+    text: String,
+    n: i64,
+}
+
+#[derive(Object, Debug)]
+struct Attachment {
+    #[orm(external_blob)]
+    data: Vec<u8>,
+}
+
+#[derive(Object, Debug)]
+struct Unregistered {
+    x: i64,
+}
+
+#[test]
+fn objects_round_trip_to_the_server_database() {
+    let path = common::temp_db("remote");
+    let addr = common::serve(path.clone(), |server| {
+        server.register::<Note>();
+    });
+    let mut conn = Connection::connect_remote(addr).unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let id = tx
+        .create(Note {
+            text: "hi".into(),
+            n: 1,
+        })
+        .unwrap()
+        .id();
+    tx.commit().unwrap();
+
+    let (text, n): (String, i64) = common::raw(&path)
+        .query_row(
+            "SELECT text, n FROM Note WHERE id = ?",
+            [id.into_i64()],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .unwrap();
+    assert_eq!((text.as_str(), n), ("hi", 1));
+
+    let tx = conn.new_transaction().unwrap();
+    let note = tx.get::<Note>(id).unwrap();
+    assert_eq!(note.borrow().text, "hi");
+    note.borrow_mut().n = 5;
+    drop(note);
+    let missing = tx.get::<Note>(orm::ObjectId::new(99)).err().unwrap();
+    assert!(matches!(missing.inner(), Error::NotFound(_)));
+    tx.commit().unwrap();
+    let n: i64 = common::raw(&path)
+        .query_row("SELECT n FROM Note WHERE id = ?", [id.into_i64()], |row| {
+            row.get(0)
+        })
+        .unwrap();
+    assert_eq!(n, 5);
+}
+
+#[test]
+fn unregistered_schemas_and_ddl_are_refused() {
+    let path = common::temp_db("remote-refused");
+    let addr = common::serve(path.clone(), |server| {
+        server.register::<Note>();
+    });
+    let mut conn = Connection::connect_remote(addr).unwrap();
+    let tx = conn.new_transaction().unwrap();
+    assert!(tx.create(Unregistered { x: 1 }).is_err());
+    drop(tx);
+
+    let mut conn = Connection::connect_remote(addr).unwrap();
+    let tx = conn.new_transaction().unwrap();
+    assert!(tx.execute_ddl(&["CREATE TABLE t (x INTEGER)"]).is_err());
+    drop(tx);
+    let tables: i64 = common::raw(&path)
+        .query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE name = 't'",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(tables, 0);
+}
+
+#[test]
+fn ddl_runs_when_allowed() {
+    let path = common::temp_db("remote-ddl");
+    let addr = common::serve(path.clone(), |server| {
+        server.register::<Note>().allow_ddl(true);
+    });
+    let mut conn = Connection::connect_remote(addr).unwrap();
+    let tx = conn.new_transaction().unwrap();
+    tx.execute_ddl(&["CREATE TABLE t (x INTEGER)"]).unwrap();
+    tx.commit().unwrap();
+    assert_eq!(common::count(&path, "t"), 0);
+}
+
+#[test]
+fn filters_on_unknown_columns_fail() {
+    let path = common::temp_db("remote-typo");
+    let addr = common::serve(path, |server| {
+        server.register::<Note>();
+    });
+    let mut conn = Connection::connect_remote(addr).unwrap();
+    let tx = conn.new_transaction().unwrap();
+    tx.create(Note {
+        text: "a".into(),
+        n: 1,
+    })
+    .unwrap();
+    let query = orm::query::Query::<Note>::new().filter("txet", orm::query::Op::Eq, 1);
+    let err = query.fetch(&tx).err().unwrap();
+    assert!(matches!(err.inner(), Error::InvalidRequest(_)), "{}", err);
+}
+
+#[test]
+fn blob_purges_keep_blobs_of_registered_schemas() {
+    let path = common::temp_db("remote-blobs");
+    let addr = common::serve(path.clone(), |server| {
+        server.register::<Attachment>();
+    });
+    let mut conn = Connection::connect_remote(addr).unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let id = tx.create(Attachment { data: vec![1; 64] }).unwrap().id();
+    tx.create(Attachment { data: vec![2; 64] })
+        .unwrap()
+        .delete();
+    tx.commit().unwrap();
+
+    // The client names no schemas, but the server still knows Attachment
+    // references its blobs.
+    assert_eq!(conn.purge_unreferenced_blobs(&[]).unwrap(), 1);
+    assert_eq!(common::count(&path, "_orm_blobs"), 1);
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(tx.get::<Attachment>(id).unwrap().borrow().data, vec![1; 64]);
+}
@@ -0,0 +1,141 @@
+use orm::batch::BatchOp;
+use orm::{Connection, Error, Object};
+
+mod common;
+
+#[derive(Object, Debug)]
+#[orm(revisions)]
+struct Note {
+    text: String,
+    hits: i64,
+}
+
+fn revisions(path: &std::path::PathBuf) -> Vec<(i64, i64)> {
+    let conn = common::raw(path);
+    let mut stmt = conn
+        .prepare("SELECT id, _rev FROM Note ORDER BY id")
+        .unwrap();
+    let rows = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+        .unwrap();
+    rows.collect::<rusqlite::Result<_>>().unwrap()
+}
+
+#[test]
+fn every_write_takes_the_next_revision() {
+    let path = common::temp_db("revisions");
+    let mut conn = Connection::open_sqlite_file(&path).unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let a = tx
+        .create(Note {
+            text: "a".into(),
+            hits: 0,
+        })
+        .unwrap()
+        .id();
+    let b = tx
+        .create(Note {
+            text: "b".into(),
+            hits: 0,
+        })
+        .unwrap()
+        .id();
+    tx.commit().unwrap();
+    let (a, b) = (a.into_i64(), b.into_i64());
+    assert_eq!(revisions(&path), vec![(a, 1), (b, 2)]);
+
+    let tx = conn.new_transaction().unwrap();
+    tx.get::<Note>(orm::ObjectId::new(a))
+        .unwrap()
+        .borrow_mut()
+        .text = "a2".into();
+    tx.increment::<Note>(orm::ObjectId::new(b), "hits", 1)
+        .unwrap();
+    tx.commit().unwrap();
+    let mut stored = revisions(&path);
+    stored.sort_by_key(|(_, revision)| *revision);
+    assert_eq!(
+        stored
+            .iter()
+            .map(|(_, revision)| *revision)
+            .collect::<Vec<_>>(),
+        vec![3, 4]
+    );
+
+    let tx = conn.new_transaction().unwrap();
+    let changed = tx.changed_since::<Note>(3).unwrap();
+    assert_eq!(changed.len(), 1);
+    assert_eq!((changed[0].0.id().into_i64(), changed[0].1), stored[1]);
+    drop(changed);
+    tx.rollback().unwrap();
+}
+
+#[test]
+fn batch_ops_are_written_once() {
+    let path = common::temp_db("revisions-batch");
+    let mut conn = Connection::open_sqlite_file(&path).unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let a = tx
+        .create(Note {
+            text: "a".into(),
+            hits: 0,
+        })
+        .unwrap()
+        .id();
+    let b = tx
+        .create(Note {
+            text: "b".into(),
+            hits: 0,
+        })
+        .unwrap()
+        .id();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let result = tx
+        .apply_batch(vec![
+            BatchOp::Update(
+                a,
+                Note {
+                    text: "a2".into(),
+                    hits: 1,
+                },
+            ),
+            BatchOp::Delete(b),
+        ])
+        .unwrap();
+    assert!(result.results.iter().all(|result| result.is_ok()));
+    tx.commit().unwrap();
+
+    assert_eq!(revisions(&path), vec![(a.into_i64(), 3)]);
+    let stats = conn.stats_of::<Note>();
+    assert_eq!((stats.updates, stats.deletes), (1, 1));
+}
+
+#[derive(Object, Debug)]
+struct Plain {
+    text: String,
+}
+
+#[test]
+fn changed_since_reports_misuse_and_bad_revisions() {
+    let path = common::temp_db("revisions-errors");
+    let mut conn = Connection::open_sqlite_file(&path).unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let err = tx.changed_since::<Plain>(0).err().unwrap();
+    assert!(matches!(err.inner(), Error::InvalidRequest(_)), "{}", err);
+    tx.create(Note {
+        text: "a".into(),
+        hits: 0,
+    })
+    .unwrap();
+    tx.commit().unwrap();
+
+    common::raw(&path)
+        .execute("UPDATE Note SET _rev = 'x'", [])
+        .unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let err = tx.changed_since::<Note>(0).err().unwrap();
+    assert!(matches!(err.inner(), Error::UnexpectedType(_)), "{}", err);
+}
@@ -0,0 +1,44 @@
+use orm::{Connection, Error, Object, ScanCursor};
+
+#[derive(Object, Debug)]
+struct Reading {
+    n: i64,
+}
+
+#[test]
+fn scans_resume_only_from_their_own_cursors() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    for n in 0..5 {
+        tx.create(Reading { n }).unwrap();
+    }
+
+    let mut scan = tx.scan::<Reading>();
+    scan.next().unwrap().unwrap();
+    let cursor: ScanCursor = scan.cursor().to_string().parse().unwrap();
+    drop(scan);
+    let rest = tx.scan::<Reading>().resume(&cursor).unwrap();
+    assert_eq!(rest.count(), 4);
+
+    let foreign: ScanCursor = "Other@1".parse().unwrap();
+    let err = tx.scan::<Reading>().resume(&foreign).err().unwrap();
+    assert!(matches!(err.inner(), Error::InvalidRequest(_)), "{}", err);
+}
+
+#[test]
+fn scans_honour_limits_and_reject_other_queries() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    for n in 0..5 {
+        tx.create(Reading { n }).unwrap();
+    }
+
+    let limited = tx.select::<Reading>().limit(3).iter();
+    assert_eq!(limited.count(), 3);
+    let query = orm::query::Query::<Reading>::new().limit(2);
+    assert_eq!(query.scan(&tx).unwrap().count(), 2);
+
+    let sampled = orm::query::Query::<Reading>::new().sample(2);
+    let err = sampled.scan(&tx).err().unwrap();
+    assert!(matches!(err, Error::InvalidRequest(_)), "{}", err);
+}
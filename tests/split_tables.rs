@@ -0,0 +1,75 @@
+use orm::{Connection, Object};
+
+mod common;
+
+#[derive(Object, Debug)]
+#[orm(split(table = "user_profiles", fields(bio, avatar)))]
+struct User {
+    name: String,
+    bio: String,
+    avatar: Option<Vec<u8>>,
+}
+
+fn profile(path: &std::path::PathBuf, id: orm::ObjectId) -> Option<(String, Option<Vec<u8>>)> {
+    common::raw(path)
+        .query_row(
+            "SELECT bio, avatar FROM user_profiles WHERE id = ?",
+            [id.into_i64()],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .ok()
+}
+
+fn check(mut conn: Connection, path: &std::path::PathBuf) {
+    let tx = conn.new_transaction().unwrap();
+    let user = User {
+        name: "ann".into(),
+        bio: "long text".into(),
+        avatar: Some(vec![1, 2]),
+    };
+    let id = tx.create(user).unwrap().id();
+    tx.commit().unwrap();
+
+    let name: String = common::raw(path)
+        .query_row(
+            "SELECT name FROM User WHERE id = ?",
+            [id.into_i64()],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(name, "ann");
+    assert_eq!(
+        profile(path, id),
+        Some(("long text".into(), Some(vec![1, 2])))
+    );
+
+    let tx = conn.new_transaction().unwrap();
+    let user = tx.get::<User>(id).unwrap();
+    assert_eq!(user.borrow().bio, "long text");
+    user.borrow_mut().bio = "short".into();
+    user.borrow_mut().avatar = None;
+    drop(user);
+    tx.commit().unwrap();
+    assert_eq!(profile(path, id), Some(("short".into(), None)));
+
+    let tx = conn.new_transaction().unwrap();
+    tx.get::<User>(id).unwrap().delete();
+    tx.commit().unwrap();
+    assert_eq!(common::count(path, "User"), 0);
+    assert_eq!(common::count(path, "user_profiles"), 0);
+}
+
+#[test]
+fn split_columns_are_stored_in_their_own_table() {
+    let path = common::temp_db("split");
+    check(Connection::open_sqlite_file(&path).unwrap(), &path);
+}
+
+#[test]
+fn split_columns_over_remote() {
+    let path = common::temp_db("split-remote");
+    let addr = common::serve(path.clone(), |server| {
+        server.register::<User>();
+    });
+    check(Connection::connect_remote(addr).unwrap(), &path);
+}
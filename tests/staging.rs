@@ -0,0 +1,111 @@
+use orm::{Connection, Error, Object};
+
+mod common;
+
+#[derive(Object, Debug)]
+#[table_name("stock")]
+#[orm(revisions)]
+struct Stock {
+    #[orm(unique)]
+    sku: String,
+    qty: i64,
+}
+
+fn stored(path: &std::path::PathBuf) -> Vec<(String, i64, i64)> {
+    let conn = common::raw(path);
+    let mut stmt = conn
+        .prepare("SELECT sku, qty, _rev FROM stock ORDER BY sku")
+        .unwrap();
+    let rows = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+        .unwrap();
+    rows.collect::<rusqlite::Result<_>>().unwrap()
+}
+
+fn check(mut conn: Connection, path: &std::path::PathBuf) {
+    let tx = conn.new_transaction().unwrap();
+    tx.create(Stock {
+        sku: "a".into(),
+        qty: 1,
+    })
+    .unwrap();
+    tx.create(Stock {
+        sku: "c".into(),
+        qty: 9,
+    })
+    .unwrap();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    tx.create_temp_table::<Stock>().unwrap();
+    tx.create(Stock {
+        sku: "a".into(),
+        qty: 5,
+    })
+    .unwrap();
+    let b = tx
+        .create(Stock {
+            sku: "b".into(),
+            qty: 0,
+        })
+        .unwrap();
+    // Written to the TEMP table, then modified: merged once, not again on
+    // commit.
+    tx.get_all::<Stock>().unwrap();
+    b.borrow_mut().qty = 2;
+    drop(b);
+    tx.create(Stock {
+        sku: "x".into(),
+        qty: 0,
+    })
+    .unwrap()
+    .delete();
+    assert_eq!(tx.merge_from_staging::<Stock>(&["sku"]).unwrap(), 2);
+    tx.commit().unwrap();
+
+    let stored = stored(path);
+    let rows = stored
+        .iter()
+        .map(|(sku, qty, _)| (sku.as_str(), *qty))
+        .collect::<Vec<_>>();
+    assert_eq!(rows, vec![("a", 5), ("b", 2), ("c", 9)]);
+    // Merged rows share one revision, and nothing was written after them.
+    let merged = stored[0].2;
+    assert_eq!(stored[1].2, merged);
+    let last: i64 = common::raw(path)
+        .query_row(
+            "SELECT value FROM _orm_sequences WHERE name = 'stock._rev'",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(last, merged);
+}
+
+#[test]
+fn merge_upserts_staged_rows() {
+    let path = common::temp_db("staging");
+    check(Connection::open_sqlite_file(&path).unwrap(), &path);
+}
+
+#[test]
+fn merge_over_remote() {
+    let path = common::temp_db("staging-remote");
+    let addr = common::serve(path.clone(), |server| {
+        server.register::<Stock>();
+    });
+    check(Connection::connect_remote(addr).unwrap(), &path);
+}
+
+#[test]
+fn merge_rejects_misuse() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let result = tx.merge_from_staging::<Stock>(&["sku"]);
+    assert!(
+        matches!(result.map_err(|e| e.inner().to_string()), Err(e) if e.contains("no TEMP table"))
+    );
+    tx.create_temp_table::<Stock>().unwrap();
+    let result = tx.merge_from_staging::<Stock>(&["missing"]);
+    assert!(matches!(result, Err(ref e) if matches!(e.inner(), Error::InvalidRequest(_))));
+}
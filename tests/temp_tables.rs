@@ -0,0 +1,70 @@
+use orm::{Connection, Error, Object};
+
+mod common;
+
+#[derive(Object, Debug)]
+#[table_name("stock")]
+struct Stock {
+    sku: String,
+    qty: i64,
+}
+
+#[derive(Object, Debug)]
+#[orm(split(table = "split_notes", fields(note)))]
+struct Split {
+    note: String,
+}
+
+#[test]
+fn temp_tables_never_reach_the_stored_table() {
+    let path = common::temp_db("temp");
+    let mut conn = Connection::open_sqlite_file(&path).unwrap();
+    let tx = conn.new_transaction().unwrap();
+    tx.create(Stock {
+        sku: "kept".into(),
+        qty: 1,
+    })
+    .unwrap();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    tx.create_temp_table::<Stock>().unwrap();
+    assert!(tx.get_all::<Stock>().unwrap().is_empty());
+    tx.create(Stock {
+        sku: "staged".into(),
+        qty: 2,
+    })
+    .unwrap();
+    assert_eq!(tx.get_all::<Stock>().unwrap().len(), 1);
+    tx.commit().unwrap();
+
+    let sku: String = common::raw(&path)
+        .query_row("SELECT sku FROM stock", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(sku, "kept");
+    assert_eq!(common::count(&path, "stock"), 1);
+
+    // Dropped on commit, so the next transaction sees the stored rows again.
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(tx.get_all::<Stock>().unwrap().len(), 1);
+    tx.rollback().unwrap();
+}
+
+#[test]
+fn temp_tables_reject_misuse() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let invalid = |result: orm::Result<()>| matches!(result, Err(ref e) if matches!(e.inner(), Error::InvalidRequest(_)));
+    assert!(invalid(tx.create_temp_table::<Split>()));
+    tx.create(Stock {
+        sku: "a".into(),
+        qty: 1,
+    })
+    .unwrap();
+    assert!(invalid(tx.create_temp_table::<Stock>()));
+    tx.rollback().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    tx.create_temp_table::<Stock>().unwrap();
+    assert!(invalid(tx.create_temp_table::<Stock>()));
+}
@@ -0,0 +1,32 @@
+use orm::{Connection, Error, Object};
+
+#[derive(Object, Debug)]
+struct Doc {
+    title: String,
+    n: i64,
+}
+
+#[derive(Object)]
+#[orm(view_of = Doc)]
+struct Misnamed {
+    #[column_name("count")]
+    n: i64,
+}
+
+#[test]
+fn views_naming_missing_columns_fail() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    let tx = conn.new_transaction().unwrap();
+    let id = tx
+        .create(Doc {
+            title: "a".into(),
+            n: 1,
+        })
+        .unwrap()
+        .id();
+    let err = tx
+        .with_view::<Misnamed, _>(id, |view| view.n)
+        .err()
+        .unwrap();
+    assert!(matches!(err.inner(), Error::InvalidRequest(_)), "{}", err);
+}